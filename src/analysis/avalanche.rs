@@ -0,0 +1,521 @@
+use std::fmt;
+use std::io;
+use std::io::Write;
+
+use crate::recording::SpikeRecording;
+
+/// A run of consecutive active time bins bracketed by quiet ones
+#[derive(Debug, Copy, Clone)]
+pub struct Avalanche {
+    /// time in ms at the start of the first bin
+    pub start: u64,
+    /// number of bins
+    pub duration: u64,
+    /// total number of spikes
+    pub size: u64,
+    /// spikes in the first and second bins, the second is 0 for single bin avalanches
+    pub ancestors: u64,
+    pub descendants: u64,
+}
+
+/// Splits a stream of spike counts into avalanches. Bins with more than `threshold` spikes count
+/// as active, a threshold above 0 helps large networks where some neuron is almost always firing.
+#[derive(Debug, Clone)]
+pub struct AvalancheDetector {
+    bin: u64,
+    threshold: u64,
+    /// index and spike count of the bin being filled
+    current_bin: u64,
+    current_count: u64,
+    avalanche: Option<Avalanche>,
+}
+
+impl AvalancheDetector {
+    pub fn new(bin: u64, threshold: u64) -> Self {
+        AvalancheDetector {
+            bin: bin.max(1),
+            threshold,
+            current_bin: 0,
+            current_count: 0,
+            avalanche: None,
+        }
+    }
+
+    /// Adds the spikes of the step at `time` ms, times have to be increasing. Returns an
+    /// avalanche once a quiet bin ends it.
+    pub fn push(&mut self, time: u64, spikes: u64) -> Option<Avalanche> {
+        let bin = time / self.bin;
+        let mut finished = None;
+        if bin != self.current_bin {
+            finished = self.close_bin();
+            // any bins skipped over were quiet
+            if bin > self.current_bin + 1 {
+                finished = finished.or_else(|| self.avalanche.take());
+            }
+            self.current_bin = bin;
+        }
+        self.current_count += spikes;
+        finished
+    }
+
+    fn close_bin(&mut self) -> Option<Avalanche> {
+        let count = std::mem::replace(&mut self.current_count, 0);
+        if count <= self.threshold {
+            return self.avalanche.take();
+        }
+
+        match &mut self.avalanche {
+            Some(avalanche) => {
+                if avalanche.duration == 1 {
+                    avalanche.descendants = count;
+                }
+                avalanche.duration += 1;
+                avalanche.size += count;
+            }
+            None => {
+                self.avalanche = Some(Avalanche {
+                    start: self.current_bin * self.bin,
+                    duration: 1,
+                    size: count,
+                    ancestors: count,
+                    descendants: 0,
+                })
+            }
+        }
+        None
+    }
+}
+
+/// Avalanches in a recording, an avalanche still going at the end is left out since it's
+/// incomplete
+pub fn detect(recording: &SpikeRecording, bin: u64, threshold: u64) -> Vec<Avalanche> {
+    let mut counts = vec![0; recording.duration as usize];
+    for train in &recording.trains {
+        for &time in train {
+            counts[time as usize] += 1;
+        }
+    }
+
+    let mut detector = AvalancheDetector::new(bin, threshold);
+    counts
+        .into_iter()
+        .enumerate()
+        .filter_map(|(time, count)| detector.push(time as u64, count))
+        .collect()
+}
+
+/// The average time in ms between consecutive spikes from the whole population, the usual
+/// choice of bin width from Beggs and Plenz (2003)
+pub fn mean_inter_event_interval(recording: &SpikeRecording) -> f64 {
+    let spikes: usize = recording.trains.iter().map(|train| train.len()).sum();
+    if spikes == 0 {
+        recording.duration as f64
+    } else {
+        recording.duration as f64 / spikes as f64
+    }
+}
+
+/// Exponent of a discrete power law P(x) ~ x^-exponent fitted to the values of at least `xmin`
+#[derive(Debug, Copy, Clone)]
+pub struct PowerLawFit {
+    pub exponent: f64,
+    /// standard error of the exponent
+    pub error: f64,
+    pub xmin: u64,
+    /// number of values at least `xmin`
+    pub samples: usize,
+    /// Kolmogorov-Smirnov distance between the data and the fit
+    pub ks_distance: f64,
+}
+
+/// Maximum likelihood fit of a discrete power law as in Clauset, Shalizi and Newman (2009),
+/// `None` with fewer than 2 values or if they're all `xmin`
+pub fn fit_power_law(values: &[u64], xmin: u64) -> Option<PowerLawFit> {
+    let xmin = xmin.max(1);
+    let mut tail: Vec<u64> = values.iter().copied().filter(|x| *x >= xmin).collect();
+    if tail.len() < 2 || tail.iter().all(|x| *x == xmin) {
+        return None;
+    }
+    tail.sort_unstable();
+
+    let n = tail.len() as f64;
+    let log_sum: f64 = tail.iter().map(|x| (*x as f64).ln()).sum();
+    let log_likelihood =
+        |exponent: f64| -n * hurwitz_zeta(exponent, xmin).ln() - exponent * log_sum;
+    let exponent = maximize(log_likelihood, 1.0001, 6.0);
+
+    let normalization = hurwitz_zeta(exponent, xmin);
+    let mut ks_distance: f64 = 0.0;
+    let mut i = 0;
+    while i < tail.len() {
+        let x = tail[i];
+        while i < tail.len() && tail[i] == x {
+            i += 1;
+        }
+        let empirical = i as f64 / n;
+        let fitted = 1.0 - hurwitz_zeta(exponent, x + 1) / normalization;
+        ks_distance = ks_distance.max((empirical - fitted).abs());
+    }
+
+    Some(PowerLawFit {
+        exponent,
+        error: (exponent - 1.0) / n.sqrt(),
+        xmin,
+        samples: tail.len(),
+        ks_distance,
+    })
+}
+
+/// The sum of k^-s for k from `q` up, summing the first terms directly and the rest with the
+/// Euler-Maclaurin formula
+fn hurwitz_zeta(s: f64, q: u64) -> f64 {
+    const TERMS: u64 = 64;
+    let direct: f64 = (q..q + TERMS).map(|k| (k as f64).powf(-s)).sum();
+    let n = (q + TERMS) as f64;
+    direct + n.powf(1.0 - s) / (s - 1.0) + 0.5 * n.powf(-s) + s * n.powf(-s - 1.0) / 12.0
+}
+
+/// Golden section search for the maximum of a unimodal function
+fn maximize(f: impl Fn(f64) -> f64, mut low: f64, mut high: f64) -> f64 {
+    let ratio = (5f64.sqrt() - 1.0) / 2.0;
+    let mut a = high - ratio * (high - low);
+    let mut b = low + ratio * (high - low);
+    let (mut fa, mut fb) = (f(a), f(b));
+    while high - low > 1e-6 {
+        if fa < fb {
+            low = a;
+            a = b;
+            fa = fb;
+            b = low + ratio * (high - low);
+            fb = f(b);
+        } else {
+            high = b;
+            b = a;
+            fb = fa;
+            a = high - ratio * (high - low);
+            fa = f(a);
+        }
+    }
+    (low + high) / 2.0
+}
+
+/// Branching ratio from Beggs and Plenz (2003), the average ratio of spikes in the second bin of
+/// an avalanche to the first, 1 for a critical network
+pub fn branching_ratio(avalanches: &[Avalanche]) -> Option<f64> {
+    if avalanches.is_empty() {
+        return None;
+    }
+    let total: f64 = avalanches
+        .iter()
+        .map(|avalanche| avalanche.descendants as f64 / avalanche.ancestors as f64)
+        .sum();
+    Some(total / avalanches.len() as f64)
+}
+
+/// Exponent of the mean avalanche size growing with duration, <S>(T) ~ T^exponent, from a least
+/// squares fit in log-log space. At criticality it matches (duration exponent - 1) / (size
+/// exponent - 1).
+pub fn size_duration_exponent(avalanches: &[Avalanche]) -> Option<f64> {
+    let longest = avalanches.iter().map(|a| a.duration).max()? as usize;
+    let mut sizes = vec![(0.0, 0); longest + 1];
+    for avalanche in avalanches {
+        let (total, count) = &mut sizes[avalanche.duration as usize];
+        *total += avalanche.size as f64;
+        *count += 1;
+    }
+    let points: Vec<(f64, f64)> = sizes
+        .iter()
+        .enumerate()
+        .filter(|(_, (_, count))| *count > 0)
+        .map(|(duration, (total, count))| ((duration as f64).ln(), (total / *count as f64).ln()))
+        .collect();
+    if points.len() < 2 {
+        return None;
+    }
+
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|(x, _)| x).sum::<f64>() / n;
+    let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / n;
+    let covariance: f64 = points
+        .iter()
+        .map(|(x, y)| (x - mean_x) * (y - mean_y))
+        .sum();
+    let variance: f64 = points.iter().map(|(x, _)| (x - mean_x).powi(2)).sum();
+    Some(covariance / variance)
+}
+
+/// Summary of the avalanches seen so far
+#[derive(Debug, Clone)]
+pub struct AvalancheStats {
+    pub count: usize,
+    pub bin: u64,
+    pub size: Option<PowerLawFit>,
+    pub duration: Option<PowerLawFit>,
+    pub branching_ratio: Option<f64>,
+    pub size_duration_exponent: Option<f64>,
+}
+
+impl AvalancheStats {
+    pub fn compute(avalanches: &[Avalanche], bin: u64, xmin: u64) -> Self {
+        let sizes: Vec<u64> = avalanches.iter().map(|a| a.size).collect();
+        let durations: Vec<u64> = avalanches.iter().map(|a| a.duration).collect();
+        AvalancheStats {
+            count: avalanches.len(),
+            bin,
+            size: fit_power_law(&sizes, xmin),
+            duration: fit_power_law(&durations, xmin),
+            branching_ratio: branching_ratio(avalanches),
+            size_duration_exponent: size_duration_exponent(avalanches),
+        }
+    }
+}
+
+impl fmt::Display for AvalancheStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let fit = |fit: Option<PowerLawFit>| match fit {
+            Some(fit) => format!(
+                "{:.3} ± {:.3} (xmin {}, {} avalanches, KS {:.3})",
+                fit.exponent, fit.error, fit.xmin, fit.samples, fit.ks_distance
+            ),
+            None => "-".to_string(),
+        };
+        let optional = |value: Option<f64>| match value {
+            Some(v) => format!("{:.3}", v),
+            None => "-".to_string(),
+        };
+
+        writeln!(f, "{} avalanches with {}ms bins", self.count, self.bin)?;
+        writeln!(f, "size exponent: {}", fit(self.size))?;
+        writeln!(f, "duration exponent: {}", fit(self.duration))?;
+        writeln!(f, "branching ratio: {}", optional(self.branching_ratio))?;
+
+        let predicted = match (self.size, self.duration) {
+            (Some(size), Some(duration)) if size.exponent > 1.0 => {
+                Some((duration.exponent - 1.0) / (size.exponent - 1.0))
+            }
+            _ => None,
+        };
+        write!(
+            f,
+            "mean size ~ duration^{} (scaling relation predicts {})",
+            optional(self.size_duration_exponent),
+            optional(predicted)
+        )
+    }
+}
+
+/// Writes `start_ms,duration_bins,size,ancestors,descendants` for each avalanche
+pub fn write_csv<W: Write>(out: &mut W, avalanches: &[Avalanche]) -> io::Result<()> {
+    write_header(out)?;
+    for avalanche in avalanches {
+        write_row(out, avalanche)?;
+    }
+    Ok(())
+}
+
+pub fn write_header<W: Write>(out: &mut W) -> io::Result<()> {
+    writeln!(out, "start_ms,duration_bins,size,ancestors,descendants")
+}
+
+pub fn write_row<W: Write>(out: &mut W, avalanche: &Avalanche) -> io::Result<()> {
+    writeln!(
+        out,
+        "{},{},{},{},{}",
+        avalanche.start,
+        avalanche.duration,
+        avalanche.size,
+        avalanche.ancestors,
+        avalanche.descendants
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+
+    /// Exact draws from a discrete power law, inverting its survival function
+    /// P(X >= x) = zeta(exponent, x) / zeta(exponent, xmin) by bisection
+    fn power_law_sample(exponent: f64, xmin: u64, count: usize, seed: u64) -> Vec<u64> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let normalization = hurwitz_zeta(exponent, xmin);
+        let survival = |x: u64| hurwitz_zeta(exponent, x) / normalization;
+        (0..count)
+            .map(|_| {
+                let u = 1.0 - rng.gen::<f64>();
+                // the largest x with survival(x) >= u
+                let (mut low, mut high) = (xmin, xmin * 2);
+                while survival(high) >= u && high < 1 << 40 {
+                    low = high;
+                    high *= 2;
+                }
+                while high - low > 1 {
+                    let middle = low + (high - low) / 2;
+                    if survival(middle) >= u {
+                        low = middle;
+                    } else {
+                        high = middle;
+                    }
+                }
+                low
+            })
+            .collect()
+    }
+
+    #[test]
+    fn hurwitz_zeta_matches_riemann_zeta() {
+        // zeta(2) = pi^2 / 6 and the terms from 2 on leave out the 1
+        let zeta_2 = std::f64::consts::PI.powi(2) / 6.0;
+        assert!((hurwitz_zeta(2.0, 1) - zeta_2).abs() < 1e-9);
+        assert!((hurwitz_zeta(2.0, 2) - (zeta_2 - 1.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn fits_exponent_of_power_law_sample() {
+        let sample = power_law_sample(1.5, 1, 5000, 7);
+        let fit = fit_power_law(&sample, 1).unwrap();
+
+        assert_eq!(fit.samples, 5000);
+        assert!(
+            (fit.exponent - 1.5).abs() < 3.0 * fit.error,
+            "fitted {} ± {}",
+            fit.exponent,
+            fit.error
+        );
+        assert!(fit.ks_distance < 0.03, "KS distance {}", fit.ks_distance);
+    }
+
+    #[test]
+    fn fits_exponent_above_xmin() {
+        let sample = power_law_sample(2.5, 5, 5000, 11);
+        let fit = fit_power_law(&sample, 5).unwrap();
+
+        assert!(
+            (fit.exponent - 2.5).abs() < 3.0 * fit.error,
+            "fitted {} ± {}",
+            fit.exponent,
+            fit.error
+        );
+    }
+
+    #[test]
+    fn no_fit_without_spread() {
+        assert!(fit_power_law(&[3], 1).is_none());
+        assert!(fit_power_law(&[2, 2, 2], 2).is_none());
+    }
+
+    /// start, duration, size, ancestors and descendants of each avalanche found pushing a count
+    /// every ms
+    fn avalanches(counts: &[u64], bin: u64, threshold: u64) -> Vec<(u64, u64, u64, u64, u64)> {
+        let mut detector = AvalancheDetector::new(bin, threshold);
+        counts
+            .iter()
+            .enumerate()
+            .filter_map(|(time, &count)| detector.push(time as u64, count))
+            .map(summary)
+            .collect()
+    }
+
+    fn summary(a: Avalanche) -> (u64, u64, u64, u64, u64) {
+        (a.start, a.duration, a.size, a.ancestors, a.descendants)
+    }
+
+    fn avalanche(duration: u64, size: u64) -> Avalanche {
+        Avalanche {
+            start: 0,
+            duration,
+            size,
+            ancestors: 1,
+            descendants: 0,
+        }
+    }
+
+    #[test]
+    fn quiet_bins_split_avalanches() {
+        // the last quiet bin only ends the avalanche once the next one starts
+        let counts = [0, 2, 3, 0, 0, 1, 0, 4, 1, 2, 0, 0];
+        assert_eq!(
+            avalanches(&counts, 1, 0),
+            vec![(1, 2, 5, 2, 3), (5, 1, 1, 1, 0), (7, 3, 7, 4, 1)]
+        );
+        // a lone spike isn't enough to keep an avalanche going above a threshold of 1
+        assert_eq!(
+            avalanches(&counts, 1, 1),
+            vec![(1, 2, 5, 2, 3), (7, 1, 4, 4, 0), (9, 1, 2, 2, 0)]
+        );
+    }
+
+    #[test]
+    fn counts_are_summed_over_bins() {
+        // bins of 2ms hold 2, 3, 0, 1, 0 and 0 spikes
+        let counts = [0, 2, 3, 0, 0, 0, 0, 1, 0, 0, 0, 0];
+        assert_eq!(
+            avalanches(&counts, 2, 0),
+            vec![(0, 2, 5, 2, 3), (6, 1, 1, 1, 0)]
+        );
+        assert_eq!(avalanches(&counts, 10, 0), vec![]);
+    }
+
+    #[test]
+    fn skipped_steps_are_quiet() {
+        let mut detector = AvalancheDetector::new(1, 0);
+        assert!(detector.push(0, 2).is_none());
+        assert!(detector.push(1, 3).is_none());
+        assert_eq!(detector.push(10, 1).map(summary), Some((0, 2, 5, 2, 3)));
+        assert!(detector.push(11, 0).is_none());
+        assert_eq!(detector.push(20, 0).map(summary), Some((10, 1, 1, 1, 0)));
+    }
+
+    #[test]
+    fn unfinished_avalanches_are_left_out() {
+        let recording = SpikeRecording {
+            excitatory: 2,
+            inhibitory: 0,
+            duration: 10,
+            trains: vec![vec![1, 2, 8], vec![2, 9]],
+        };
+        let found: Vec<_> = detect(&recording, 1, 0).into_iter().map(summary).collect();
+        assert_eq!(found, vec![(1, 2, 3, 1, 2)]);
+        assert_eq!(mean_inter_event_interval(&recording), 2.0);
+    }
+
+    #[test]
+    fn branching_ratio_averages_over_avalanches() {
+        let avalanches = [
+            Avalanche {
+                ancestors: 2,
+                descendants: 3,
+                ..avalanche(2, 5)
+            },
+            Avalanche {
+                ancestors: 4,
+                descendants: 2,
+                ..avalanche(2, 6)
+            },
+        ];
+        assert_eq!(branching_ratio(&avalanches), Some(1.0));
+        assert_eq!(branching_ratio(&[]), None);
+    }
+
+    #[test]
+    fn size_grows_with_duration() {
+        // mean sizes of 1, 4, 16 and 64 for durations 1, 2, 4 and 8
+        let avalanches = [
+            avalanche(1, 1),
+            avalanche(2, 3),
+            avalanche(2, 5),
+            avalanche(4, 16),
+            avalanche(8, 64),
+        ];
+        let exponent = size_duration_exponent(&avalanches).unwrap();
+        assert!((exponent - 2.0).abs() < 1e-12, "exponent {}", exponent);
+
+        assert_eq!(
+            size_duration_exponent(&[avalanche(3, 5), avalanche(3, 7)]),
+            None
+        );
+        assert_eq!(size_duration_exponent(&[]), None);
+    }
+}
@@ -0,0 +1,329 @@
+use std::io;
+use std::io::Write;
+
+/// Counts in bins of `bin` ms covering `start` up to `start + bin * counts.len()` ms, relative to
+/// a reference spike or event
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    pub start: i64,
+    pub bin: u64,
+    pub counts: Vec<f64>,
+    /// number of reference spikes or events the counts were collected over
+    pub references: usize,
+}
+
+impl Histogram {
+    fn new(start: i64, end: i64, bin: u64) -> Self {
+        let bin = bin.max(1);
+        let bins = ((end - start) as u64).div_ceil(bin) as usize;
+        Histogram {
+            start,
+            bin,
+            counts: vec![0.0; bins],
+            references: 0,
+        }
+    }
+
+    fn add(&mut self, offset: i64) {
+        if offset < self.start {
+            return;
+        }
+        if let Some(count) = self
+            .counts
+            .get_mut(((offset - self.start) as u64 / self.bin) as usize)
+        {
+            *count += 1.0;
+        }
+    }
+
+    /// start of each bin in ms
+    pub fn offsets(&self) -> impl Iterator<Item = i64> + '_ {
+        (0..self.counts.len()).map(move |i| self.start + (i as u64 * self.bin) as i64)
+    }
+
+    /// counts turned into a rate in Hz per reference
+    pub fn rates(&self) -> Vec<f64> {
+        let scale = 1000.0 / (self.bin as f64 * self.references.max(1) as f64);
+        self.counts.iter().map(|count| count * scale).collect()
+    }
+
+    /// Writes `offset_ms,count,rate_hz` for each bin
+    pub fn write_csv<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "offset_ms,count,rate_hz")?;
+        for ((offset, count), rate) in self.offsets().zip(&self.counts).zip(self.rates()) {
+            writeln!(out, "{},{},{}", offset, count, rate)?;
+        }
+        Ok(())
+    }
+}
+
+/// Counts of `target` spikes at each lag from the `reference` spikes, up to `max_lag` ms either
+/// way in whole bins with one centred on zero lag. Only an odd number of whole ms can be centred
+/// so an even `bin` is widened by 1ms. Both trains have to be sorted.
+pub fn cross_correlogram(reference: &[u64], target: &[u64], max_lag: u64, bin: u64) -> Histogram {
+    correlogram(reference, target, max_lag, bin, false)
+}
+
+/// Cross-correlogram of a train with itself, leaving out each spike's zero lag with itself
+pub fn auto_correlogram(train: &[u64], max_lag: u64, bin: u64) -> Histogram {
+    correlogram(train, train, max_lag, bin, true)
+}
+
+fn correlogram(
+    reference: &[u64],
+    target: &[u64],
+    max_lag: u64,
+    bin: u64,
+    same_train: bool,
+) -> Histogram {
+    // centre a bin on zero lag and cover whole bins either side of it so the ends aren't
+    // partly empty, the zero lag bin covers lags from -half to half
+    let bin = correlogram_bin(bin);
+    let half = (bin / 2) as i64;
+    let side = (max_lag / bin * bin) as i64;
+    let mut histogram = Histogram::new(-side - half, side - half + bin as i64, bin);
+    let (min_lag, max_lag) = (-side - half, side - half + bin as i64 - 1);
+    histogram.references = reference.len();
+
+    let mut first = 0;
+    for (i, &time) in reference.iter().enumerate() {
+        let time = time as i64;
+        while first < target.len() && (target[first] as i64) < time + min_lag {
+            first += 1;
+        }
+        for (j, &other) in target.iter().enumerate().skip(first) {
+            let lag = other as i64 - time;
+            if lag > max_lag {
+                break;
+            }
+            if !(same_train && i == j) {
+                histogram.add(lag);
+            }
+        }
+    }
+    histogram
+}
+
+/// Bin width in ms actually used by the correlograms for a requested `bin`
+pub fn correlogram_bin(bin: u64) -> u64 {
+    if bin % 2 == 1 {
+        bin
+    } else {
+        bin + 1
+    }
+}
+
+/// Peri-stimulus time histogram of the pooled spikes of `trains` around each event, from
+/// `before` ms before to `after` ms after. Rates are per neuron.
+pub fn psth(trains: &[&[u64]], events: &[u64], before: u64, after: u64, bin: u64) -> Histogram {
+    let mut histogram = Histogram::new(-(before as i64), after as i64, bin);
+    histogram.references = events.len() * trains.len();
+
+    for &event in events {
+        let from = event.saturating_sub(before);
+        for train in trains {
+            let start = train.partition_point(|&t| t < from);
+            for &time in &train[start..] {
+                let offset = time as i64 - event as i64;
+                if offset >= after as i64 {
+                    break;
+                }
+                histogram.add(offset);
+            }
+        }
+    }
+    histogram
+}
+
+/// Average of a signal sampled every ms around each spike
+#[derive(Debug, Clone)]
+pub struct TriggeredAverage {
+    /// offset in ms of the first value from the spike
+    pub start: i64,
+    pub mean: Vec<f64>,
+    pub std: Vec<f64>,
+    /// spikes with the whole window inside the signal
+    pub spikes: usize,
+}
+
+impl TriggeredAverage {
+    /// Writes `offset_ms,mean,std` for each sample
+    pub fn write_csv<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "offset_ms,mean,std")?;
+        for (i, (mean, std)) in self.mean.iter().zip(&self.std).enumerate() {
+            writeln!(out, "{},{},{}", self.start + i as i64, mean, std)?;
+        }
+        Ok(())
+    }
+}
+
+/// Spike-triggered average of `signal` from `before` ms before each spike to `after` ms after,
+/// skipping spikes too close to either end of the signal
+pub fn spike_triggered_average(
+    train: &[u64],
+    signal: &[f64],
+    before: u64,
+    after: u64,
+) -> TriggeredAverage {
+    let length = (before + after + 1) as usize;
+    let mut sum = vec![0.0; length];
+    let mut sum_squares = vec![0.0; length];
+    let mut spikes = 0;
+
+    for &time in train {
+        if time < before || (time + after) as usize >= signal.len() {
+            continue;
+        }
+        let window = &signal[(time - before) as usize..=(time + after) as usize];
+        for ((sum, sum_squares), value) in sum.iter_mut().zip(&mut sum_squares).zip(window) {
+            *sum += value;
+            *sum_squares += value * value;
+        }
+        spikes += 1;
+    }
+
+    let n = spikes.max(1) as f64;
+    let mean: Vec<f64> = sum.iter().map(|sum| sum / n).collect();
+    let std = sum_squares
+        .iter()
+        .zip(&mean)
+        .map(|(sum_squares, mean)| (sum_squares / n - mean * mean).max(0.0).sqrt())
+        .collect();
+    TriggeredAverage {
+        start: -(before as i64),
+        mean,
+        std,
+        spikes,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// count in the bin holding `offset`
+    fn count_at(histogram: &Histogram, offset: i64) -> f64 {
+        let bin = (offset - histogram.start).div_euclid(histogram.bin as i64);
+        histogram.counts[bin as usize]
+    }
+
+    /// offset of the start of the bin with the most counts
+    fn peak(histogram: &Histogram) -> i64 {
+        let (offset, _) =
+            histogram
+                .offsets()
+                .zip(&histogram.counts)
+                .fold((0, 0.0), |best, (offset, &count)| {
+                    if count > best.1 {
+                        (offset, count)
+                    } else {
+                        best
+                    }
+                });
+        offset
+    }
+
+    #[test]
+    fn shifted_train_peaks_at_its_lag() {
+        let reference: Vec<u64> = (0..50).map(|i| 100 + i * 37).collect();
+        let shifted: Vec<u64> = reference.iter().map(|t| t + 5).collect();
+
+        let histogram = cross_correlogram(&reference, &shifted, 20, 1);
+        assert_eq!((histogram.start, histogram.counts.len()), (-20, 41));
+        assert_eq!(peak(&histogram), 5);
+        assert_eq!(count_at(&histogram, 5), 50.0);
+        assert_eq!(histogram.references, 50);
+
+        // the other way round it's at -5
+        let histogram = cross_correlogram(&shifted, &reference, 20, 1);
+        assert_eq!(peak(&histogram), -5);
+
+        // a 5ms bin centred on 5 covers lags 3 to 7
+        let histogram = cross_correlogram(&reference, &shifted, 20, 5);
+        assert_eq!((histogram.start, histogram.counts.len()), (-22, 9));
+        assert_eq!(peak(&histogram), 3);
+        assert_eq!(count_at(&histogram, 5), 50.0);
+    }
+
+    #[test]
+    fn zero_lag_bin_is_centred() {
+        let reference = [100, 200];
+        // lags of -1, 0 and 1 from the first reference spike and -2 and 2 from the second
+        let target = [99, 100, 101, 198, 202];
+        for bin in [2, 3] {
+            let histogram = cross_correlogram(&reference, &target, 10, bin);
+            assert_eq!(histogram.bin, 3);
+            assert_eq!((histogram.start, histogram.counts.len()), (-10, 7));
+            assert_eq!(count_at(&histogram, 0), 3.0);
+            assert_eq!(count_at(&histogram, -2), 1.0);
+            assert_eq!(count_at(&histogram, 2), 1.0);
+            // the bins either side mirror each other
+            let counts = &histogram.counts;
+            assert!(counts.iter().eq(counts.iter().rev()));
+        }
+    }
+
+    #[test]
+    fn lags_beyond_the_last_bin_are_dropped() {
+        let histogram = cross_correlogram(&[100], &[89, 90, 110, 111], 10, 1);
+        assert_eq!(histogram.counts.iter().sum::<f64>(), 2.0);
+        assert_eq!(histogram.counts[0], 1.0);
+        assert_eq!(histogram.counts[20], 1.0);
+    }
+
+    #[test]
+    fn auto_correlogram_leaves_out_each_spike_itself() {
+        let train: Vec<u64> = (0..20).map(|i| i * 10).collect();
+        let histogram = auto_correlogram(&train, 15, 1);
+        assert_eq!(count_at(&histogram, 0), 0.0);
+        assert_eq!(count_at(&histogram, 10), 19.0);
+        assert_eq!(count_at(&histogram, -10), 19.0);
+        assert_eq!(histogram.counts.iter().sum::<f64>(), 38.0);
+    }
+
+    #[test]
+    fn csv_rows_match_the_bins() {
+        let histogram = cross_correlogram(&[100], &[97, 100, 103], 6, 3);
+        let mut out = Vec::new();
+        histogram.write_csv(&mut out).unwrap();
+        let csv = String::from_utf8(out).unwrap();
+        assert_eq!(
+            csv,
+            "offset_ms,count,rate_hz\n\
+            -7,0,0\n\
+            -4,1,333.3333333333333\n\
+            -1,1,333.3333333333333\n\
+            2,1,333.3333333333333\n\
+            5,0,0\n"
+        );
+    }
+
+    #[test]
+    fn psth_counts_around_events() {
+        let first: Vec<u64> = vec![5, 115, 130, 215, 290];
+        let second: Vec<u64> = vec![95, 216, 400];
+        let trains: Vec<&[u64]> = vec![&first, &second];
+        let histogram = psth(&trains, &[100, 200], 10, 50, 10);
+
+        assert_eq!(histogram.start, -10);
+        assert_eq!(histogram.counts, vec![1.0, 0.0, 3.0, 0.0, 1.0, 0.0]);
+        assert_eq!(histogram.references, 4);
+        // 3 spikes in 10ms over 2 events and 2 neurons
+        assert_eq!(histogram.rates()[2], 75.0);
+    }
+
+    #[test]
+    fn spike_triggered_average_of_a_ramp() {
+        let signal: Vec<f64> = (0..100).map(|i| i as f64).collect();
+        // the first and last spikes don't have the whole window inside the signal
+        let average = spike_triggered_average(&[2, 50, 60, 98], &signal, 5, 3);
+
+        assert_eq!(average.spikes, 2);
+        assert_eq!(average.start, -5);
+        assert_eq!(average.mean.len(), 9);
+        for (i, (mean, std)) in average.mean.iter().zip(&average.std).enumerate() {
+            assert!((mean - (50.0 + i as f64)).abs() < 1e-9);
+            assert!((std - 5.0).abs() < 1e-9);
+        }
+    }
+}
@@ -0,0 +1,324 @@
+use std::fmt;
+use std::io;
+use std::io::Write;
+
+use rayon::prelude::*;
+
+use crate::recording::SpikeRecording;
+
+/// Victor-Purpura distance, the cheapest way to turn one train into the other where adding or
+/// removing a spike costs 1 and moving one costs `cost` per ms
+pub fn victor_purpura(a: &[u64], b: &[u64], cost: f64) -> f64 {
+    let mut previous: Vec<f64> = (0..=b.len()).map(|j| j as f64).collect();
+    let mut current = vec![0.0; b.len() + 1];
+    for i in 1..=a.len() {
+        current[0] = i as f64;
+        for j in 1..=b.len() {
+            let shift = cost * (a[i - 1] as f64 - b[j - 1] as f64).abs();
+            current[j] = (previous[j] + 1.0)
+                .min(current[j - 1] + 1.0)
+                .min(previous[j - 1] + shift);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[b.len()]
+}
+
+/// van Rossum distance with an exponential kernel of time constant `tau` ms, scaled so a single
+/// unmatched spike is 1
+pub fn van_rossum(a: &[u64], b: &[u64], tau: f64) -> f64 {
+    let overlap = |x: &[u64], y: &[u64]| -> f64 {
+        x.iter()
+            .map(|&s| {
+                y.iter()
+                    .map(|&t| (-(s as f64 - t as f64).abs() / tau).exp())
+                    .sum::<f64>()
+            })
+            .sum()
+    };
+    (overlap(a, a) + overlap(b, b) - 2.0 * overlap(a, b))
+        .max(0.0)
+        .sqrt()
+}
+
+/// A train with spikes added at the edges of the recording, so the interspike intervals cover
+/// all of it as in Kreuz et al. (2013)
+fn with_edges(train: &[u64], duration: u64) -> Vec<f64> {
+    let mut edged = Vec::with_capacity(train.len() + 2);
+    if train.first() != Some(&0) {
+        edged.push(0.0);
+    }
+    edged.extend(train.iter().map(|&t| t as f64));
+    if !matches!(train.last(), Some(&t) if t >= duration) {
+        edged.push(duration as f64);
+    }
+    edged
+}
+
+/// the spikes just before and after `t`, which has to lie between the edges
+fn surrounding(train: &[f64], t: f64) -> (f64, f64) {
+    let next = train.partition_point(|&s| s <= t).min(train.len() - 1);
+    (train[next - 1], train[next])
+}
+
+fn nearest(train: &[f64], t: f64) -> f64 {
+    let i = train.partition_point(|&s| s < t);
+    let after = train.get(i).map_or(f64::MAX, |s| s - t);
+    let before = if i > 0 { t - train[i - 1] } else { f64::MAX };
+    after.min(before)
+}
+
+/// Averages `profile` over the recording, evaluating it in the middle of each stretch between
+/// consecutive spikes of either train. That's exact for the ISI and SPIKE profiles which are
+/// constant and linear over those stretches.
+fn average_profile(a: &[f64], b: &[f64], profile: impl Fn(f64) -> f64) -> f64 {
+    let mut times: Vec<f64> = a.iter().chain(b).copied().collect();
+    times.sort_by(|x, y| x.total_cmp(y));
+    times.dedup();
+    let duration = times.last().unwrap() - times.first().unwrap();
+    if duration <= 0.0 {
+        return 0.0;
+    }
+    times
+        .windows(2)
+        .map(|pair| (pair[1] - pair[0]) * profile((pair[0] + pair[1]) / 2.0))
+        .sum::<f64>()
+        / duration
+}
+
+/// ISI-distance from Kreuz et al. (2007), the time averaged relative difference between the
+/// trains' current interspike intervals, 0 for identical rates and approaching 1 for very
+/// different ones
+pub fn isi_distance(a: &[u64], b: &[u64], duration: u64) -> f64 {
+    let a = with_edges(a, duration);
+    let b = with_edges(b, duration);
+    average_profile(&a, &b, |t| {
+        let (a_previous, a_next) = surrounding(&a, t);
+        let (b_previous, b_next) = surrounding(&b, t);
+        let a_isi = a_next - a_previous;
+        let b_isi = b_next - b_previous;
+        (a_isi - b_isi).abs() / a_isi.max(b_isi)
+    })
+}
+
+/// SPIKE-distance from Kreuz et al. (2013), the time averaged distance of each train's
+/// surrounding spikes from the other train's nearest spikes, weighted by the local interspike
+/// intervals. It's 0 for identical trains and is sensitive to spike timing rather than rates.
+pub fn spike_distance(a: &[u64], b: &[u64], duration: u64) -> f64 {
+    let a = with_edges(a, duration);
+    let b = with_edges(b, duration);
+
+    // the part of the profile from one train's spikes around t
+    let local = |own: &[f64], other: &[f64], t: f64| {
+        let (previous, next) = surrounding(own, t);
+        let isi = next - previous;
+        let distance =
+            (nearest(other, previous) * (next - t) + nearest(other, next) * (t - previous)) / isi;
+        (distance, isi)
+    };
+
+    average_profile(&a, &b, |t| {
+        let (a_distance, a_isi) = local(&a, &b, t);
+        let (b_distance, b_isi) = local(&b, &a, t);
+        let mean_isi = (a_isi + b_isi) / 2.0;
+        (a_distance * b_isi + b_distance * a_isi) / (2.0 * mean_isi * mean_isi)
+    })
+}
+
+/// Parameters of the distances that have them
+#[derive(Debug, Copy, Clone)]
+pub struct DistanceParams {
+    /// Victor-Purpura cost per ms of moving a spike
+    pub cost: f64,
+    /// van Rossum kernel time constant in ms
+    pub tau: f64,
+}
+
+impl Default for DistanceParams {
+    fn default() -> Self {
+        DistanceParams {
+            cost: 0.1,
+            tau: 10.0,
+        }
+    }
+}
+
+/// Every distance between two trains
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Distances {
+    pub victor_purpura: f64,
+    pub van_rossum: f64,
+    pub isi: f64,
+    pub spike: f64,
+}
+
+impl Distances {
+    pub fn between(a: &[u64], b: &[u64], duration: u64, params: &DistanceParams) -> Self {
+        Distances {
+            victor_purpura: victor_purpura(a, b, params.cost),
+            van_rossum: van_rossum(a, b, params.tau),
+            isi: isi_distance(a, b, duration),
+            spike: spike_distance(a, b, duration),
+        }
+    }
+}
+
+/// Distances between the trains of the same neuron in two recordings of the same network, and
+/// their averages over every neuron
+#[derive(Debug, Clone)]
+pub struct RasterDistances {
+    pub duration: u64,
+    pub neurons: Vec<Distances>,
+    pub mean: Distances,
+    /// index of the neuron with the largest SPIKE-distance
+    pub worst: Option<usize>,
+}
+
+impl RasterDistances {
+    /// Compares the first `duration` ms of both recordings, `None` if they have different numbers
+    /// of neurons
+    pub fn compare(
+        a: &SpikeRecording,
+        b: &SpikeRecording,
+        duration: u64,
+        params: &DistanceParams,
+    ) -> Option<Self> {
+        if a.len() != b.len() {
+            return None;
+        }
+        let clip = |train: &[u64]| -> Vec<u64> {
+            train.iter().copied().filter(|&t| t < duration).collect()
+        };
+        let neurons: Vec<Distances> = a
+            .trains
+            .par_iter()
+            .zip(&b.trains)
+            .map(|(a, b)| Distances::between(&clip(a), &clip(b), duration, params))
+            .collect();
+
+        let count = neurons.len().max(1) as f64;
+        let mean = Distances {
+            victor_purpura: neurons.iter().map(|d| d.victor_purpura).sum::<f64>() / count,
+            van_rossum: neurons.iter().map(|d| d.van_rossum).sum::<f64>() / count,
+            isi: neurons.iter().map(|d| d.isi).sum::<f64>() / count,
+            spike: neurons.iter().map(|d| d.spike).sum::<f64>() / count,
+        };
+        let worst = neurons
+            .iter()
+            .enumerate()
+            .max_by(|x, y| x.1.spike.total_cmp(&y.1.spike))
+            .map(|(i, _)| i);
+
+        Some(RasterDistances {
+            duration,
+            neurons,
+            mean,
+            worst,
+        })
+    }
+
+    /// Writes `neuron,victor_purpura,van_rossum,isi,spike` for each neuron
+    pub fn write_csv<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "neuron,victor_purpura,van_rossum,isi,spike")?;
+        for (i, d) in self.neurons.iter().enumerate() {
+            writeln!(
+                out,
+                "{},{},{},{},{}",
+                i, d.victor_purpura, d.van_rossum, d.isi, d.spike
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for RasterDistances {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "mean distances per neuron over {}ms:", self.duration)?;
+        writeln!(f, "  Victor-Purpura: {:.4}", self.mean.victor_purpura)?;
+        writeln!(f, "  van Rossum: {:.4}", self.mean.van_rossum)?;
+        writeln!(f, "  ISI-distance: {:.4}", self.mean.isi)?;
+        write!(f, "  SPIKE-distance: {:.4}", self.mean.spike)?;
+        if let Some(worst) = self.worst {
+            write!(
+                f,
+                "\nlargest SPIKE-distance {:.4} for neuron {}",
+                self.neurons[worst].spike, worst
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// spikes every `interval` ms from 0 up to `duration`
+    fn regular(interval: u64, duration: u64) -> Vec<u64> {
+        (0..duration).step_by(interval as usize).collect()
+    }
+
+    #[test]
+    fn identical_trains_are_0_apart() {
+        let train = [3, 17, 18, 40, 95];
+        let distances = Distances::between(&train, &train, 100, &DistanceParams::default());
+
+        assert_eq!(distances.victor_purpura, 0.0);
+        assert_eq!(distances.van_rossum, 0.0);
+        assert_eq!(distances.isi, 0.0);
+        assert_eq!(distances.spike, 0.0);
+    }
+
+    #[test]
+    fn extra_spike_costs_1() {
+        let a = [10, 50];
+        let b = [10, 50, 90];
+
+        assert_eq!(victor_purpura(&a, &b, 0.1), 1.0);
+        assert!((van_rossum(&a, &b, 10.0) - 1.0).abs() < 1e-9);
+        assert!((van_rossum(&b, &a, 10.0) - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn shifted_spike_costs_its_distance() {
+        let cost = 0.1;
+        for shift in [1, 5, 19] {
+            let distance = victor_purpura(&[100], &[100 + shift], cost);
+            assert!(
+                (distance - cost * shift as f64).abs() < 1e-9,
+                "shift {}",
+                shift
+            );
+        }
+        // past 2 it's cheaper to remove the spike and add it again
+        assert_eq!(victor_purpura(&[100], &[130], cost), 2.0);
+    }
+
+    #[test]
+    fn isi_distance_of_regular_trains() {
+        let duration = 1000;
+        for (a_interval, b_interval) in [(10, 20), (10, 40), (25, 20)] {
+            let distance = isi_distance(
+                &regular(a_interval, duration),
+                &regular(b_interval, duration),
+                duration,
+            );
+            // the intervals are the inverse rates
+            let (a_isi, b_isi) = (a_interval as f64, b_interval as f64);
+            let expected = (a_isi - b_isi).abs() / a_isi.max(b_isi);
+            assert!(
+                (distance - expected).abs() < 1e-9,
+                "{} against {}",
+                distance,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn edges_are_added_once() {
+        assert_eq!(with_edges(&[], 10), vec![0.0, 10.0]);
+        assert_eq!(with_edges(&[0, 4, 10], 10), vec![0.0, 4.0, 10.0]);
+        assert_eq!(with_edges(&[4], 10), vec![0.0, 4.0, 10.0]);
+    }
+}
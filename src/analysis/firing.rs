@@ -0,0 +1,501 @@
+use std::fmt;
+use std::io;
+use std::io::Write;
+use std::ops::Range;
+
+use rand::prelude::*;
+use rayon::prelude::*;
+
+use crate::recording::SpikeRecording;
+
+/// Bin sizes used for the statistics, all in ms
+#[derive(Debug, Copy, Clone)]
+pub struct FiringParams {
+    /// bin width for the spike counts used by the correlation and synchrony measures
+    pub bin: u64,
+    /// counting window for the Fano factor
+    pub fano_window: u64,
+    /// bin width of the ISI histogram
+    pub isi_bin: u64,
+    /// pairwise correlations are averaged over a random sample of at most this many pairs
+    pub max_pairs: usize,
+}
+
+impl Default for FiringParams {
+    fn default() -> Self {
+        FiringParams {
+            bin: 5,
+            fano_window: 100,
+            isi_bin: 5,
+            max_pairs: 10_000,
+        }
+    }
+}
+
+/// Counts of values falling in bins of equal width starting at 0
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    pub bin_width: u64,
+    pub counts: Vec<usize>,
+}
+
+impl Histogram {
+    pub fn new(values: impl Iterator<Item = u64>, bin_width: u64) -> Self {
+        let bin_width = bin_width.max(1);
+        let mut counts = Vec::new();
+        for value in values {
+            let bin = (value / bin_width) as usize;
+            if bin >= counts.len() {
+                counts.resize(bin + 1, 0);
+            }
+            counts[bin] += 1;
+        }
+        Histogram { bin_width, counts }
+    }
+
+    pub fn total(&self) -> usize {
+        self.counts.iter().sum()
+    }
+}
+
+/// Firing rate in Hz of a spike train over `duration` ms
+pub fn rate(train: &[u64], duration: u64) -> f64 {
+    if duration == 0 {
+        0.0
+    } else {
+        train.len() as f64 * 1000.0 / duration as f64
+    }
+}
+
+/// Intervals in ms between consecutive spikes
+pub fn isis(train: &[u64]) -> Vec<u64> {
+    train.windows(2).map(|pair| pair[1] - pair[0]).collect()
+}
+
+/// Coefficient of variation of the interspike intervals, 1 for a Poisson process and 0 for
+/// perfectly regular firing. Needs at least 3 spikes.
+pub fn cv(train: &[u64]) -> Option<f64> {
+    let intervals: Vec<f64> = isis(train).into_iter().map(|isi| isi as f64).collect();
+    if intervals.len() < 2 {
+        return None;
+    }
+    let (mean, variance) = mean_variance(&intervals);
+    if mean > 0.0 {
+        Some(variance.sqrt() / mean)
+    } else {
+        None
+    }
+}
+
+/// Spike counts in consecutive bins of `bin` ms, a partial last bin is dropped
+pub fn binned_counts(train: &[u64], duration: u64, bin: u64) -> Vec<f64> {
+    let bin = bin.max(1);
+    let mut counts = vec![0.0; (duration / bin) as usize];
+    for &time in train {
+        if let Some(count) = counts.get_mut((time / bin) as usize) {
+            *count += 1.0;
+        }
+    }
+    counts
+}
+
+/// Variance over mean of the spike counts in windows of `window` ms, 1 for a Poisson process
+pub fn fano_factor(train: &[u64], duration: u64, window: u64) -> Option<f64> {
+    let counts = binned_counts(train, duration, window);
+    if counts.len() < 2 {
+        return None;
+    }
+    let (mean, variance) = mean_variance(&counts);
+    if mean > 0.0 {
+        Some(variance / mean)
+    } else {
+        None
+    }
+}
+
+/// Pearson correlation coefficient of two binned spike trains, `None` if either is constant
+pub fn correlation(a: &[f64], b: &[f64]) -> Option<f64> {
+    let (mean_a, variance_a) = mean_variance(a);
+    let (mean_b, variance_b) = mean_variance(b);
+    if variance_a == 0.0 || variance_b == 0.0 {
+        return None;
+    }
+    let covariance = a
+        .iter()
+        .zip(b)
+        .map(|(a, b)| (a - mean_a) * (b - mean_b))
+        .sum::<f64>()
+        / a.len() as f64;
+    Some(covariance / (variance_a * variance_b).sqrt())
+}
+
+/// Average correlation coefficient over pairs of binned spike trains, taking a random sample of
+/// `max_pairs` pairs when there are more than that. Silent neurons are left out.
+pub fn mean_pairwise_correlation<R: Rng>(
+    binned: &[Vec<f64>],
+    max_pairs: usize,
+    rng: &mut R,
+) -> Option<f64> {
+    let active: Vec<&Vec<f64>> = binned
+        .iter()
+        .filter(|counts| counts.iter().any(|count| *count > 0.0))
+        .collect();
+    let n = active.len();
+    let pair_count = n * n.saturating_sub(1) / 2;
+    if pair_count == 0 {
+        return None;
+    }
+
+    let pairs: Vec<(usize, usize)> = if pair_count <= max_pairs {
+        (0..n)
+            .flat_map(|i| (i + 1..n).map(move |j| (i, j)))
+            .collect()
+    } else {
+        (0..max_pairs)
+            .map(|_| {
+                let i = rng.gen_range(0..n);
+                let j = (i + rng.gen_range(1..n)) % n;
+                (i, j)
+            })
+            .collect()
+    };
+
+    let correlations: Vec<f64> = pairs
+        .par_iter()
+        .filter_map(|&(i, j)| correlation(active[i], active[j]))
+        .collect();
+    if correlations.is_empty() {
+        None
+    } else {
+        Some(correlations.iter().sum::<f64>() / correlations.len() as f64)
+    }
+}
+
+/// The synchrony measure χ from Golomb (2007), the standard deviation of the population average
+/// over the mean standard deviation of the individual signals. It's 1 for fully synchronous
+/// activity and falls towards 0 as 1/sqrt(N) for asynchronous activity. Here the signals are the
+/// binned spike counts rather than voltages.
+pub fn golomb_chi(binned: &[Vec<f64>]) -> Option<f64> {
+    let bins = binned.first()?.len();
+    if bins < 2 {
+        return None;
+    }
+    let mut population = vec![0.0; bins];
+    for counts in binned {
+        for (total, count) in population.iter_mut().zip(counts) {
+            *total += count / binned.len() as f64;
+        }
+    }
+    let (_, population_variance) = mean_variance(&population);
+    let mean_variance = binned
+        .iter()
+        .map(|counts| mean_variance(counts).1)
+        .sum::<f64>()
+        / binned.len() as f64;
+    if mean_variance > 0.0 {
+        Some((population_variance / mean_variance).sqrt())
+    } else {
+        None
+    }
+}
+
+fn mean_variance(values: &[f64]) -> (f64, f64) {
+    if values.is_empty() {
+        return (0.0, 0.0);
+    }
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / values.len() as f64;
+    (mean, variance)
+}
+
+fn mean(values: impl Iterator<Item = f64>) -> Option<f64> {
+    let (sum, count) = values.fold((0.0, 0), |(sum, count), v| (sum + v, count + 1));
+    if count == 0 {
+        None
+    } else {
+        Some(sum / count as f64)
+    }
+}
+
+/// Summary statistics for a group of neurons
+#[derive(Debug, Clone)]
+pub struct PopulationStats {
+    pub neurons: usize,
+    /// mean firing rate in Hz
+    pub rate: f64,
+    /// mean CV of ISI over neurons with at least 3 spikes
+    pub cv: Option<f64>,
+    /// mean Fano factor over neurons that spiked
+    pub fano_factor: Option<f64>,
+    /// mean pairwise spike count correlation
+    pub correlation: Option<f64>,
+    /// Golomb χ synchrony
+    pub chi: Option<f64>,
+}
+
+/// Firing statistics of a recording for each neuron and each population
+#[derive(Debug, Clone)]
+pub struct FiringStats {
+    pub params: FiringParams,
+    pub duration: u64,
+    /// rate in Hz of each neuron
+    pub rates: Vec<f64>,
+    pub cvs: Vec<Option<f64>>,
+    pub fano_factors: Vec<Option<f64>>,
+    /// ISIs pooled over every neuron
+    pub isi_histogram: Histogram,
+    pub excitatory: PopulationStats,
+    pub inhibitory: PopulationStats,
+    pub all: PopulationStats,
+}
+
+impl FiringStats {
+    /// `rng` picks the pairs when the correlation is estimated from a sample
+    pub fn compute<R: Rng>(recording: &SpikeRecording, params: FiringParams, rng: &mut R) -> Self {
+        let duration = recording.duration;
+        let trains = &recording.trains;
+
+        let rates: Vec<f64> = trains.iter().map(|train| rate(train, duration)).collect();
+        let cvs: Vec<Option<f64>> = trains.par_iter().map(|train| cv(train)).collect();
+        let fano_factors: Vec<Option<f64>> = trains
+            .par_iter()
+            .map(|train| fano_factor(train, duration, params.fano_window))
+            .collect();
+        let binned: Vec<Vec<f64>> = trains
+            .par_iter()
+            .map(|train| binned_counts(train, duration, params.bin))
+            .collect();
+        let isi_histogram =
+            Histogram::new(trains.iter().flat_map(|train| isis(train)), params.isi_bin);
+
+        let mut population = |range: Range<usize>| PopulationStats {
+            neurons: range.len(),
+            rate: mean(rates[range.clone()].iter().copied()).unwrap_or(0.0),
+            cv: mean(cvs[range.clone()].iter().flatten().copied()),
+            fano_factor: mean(fano_factors[range.clone()].iter().flatten().copied()),
+            correlation: mean_pairwise_correlation(&binned[range.clone()], params.max_pairs, rng),
+            chi: golomb_chi(&binned[range]),
+        };
+        let excitatory = population(0..recording.excitatory);
+        let inhibitory = population(recording.excitatory..recording.len());
+        let all = population(0..recording.len());
+
+        FiringStats {
+            params,
+            duration,
+            rates,
+            cvs,
+            fano_factors,
+            isi_histogram,
+            excitatory,
+            inhibitory,
+            all,
+        }
+    }
+
+    /// Writes `neuron,rate_hz,cv,fano_factor` for each neuron, leaving out undefined values
+    pub fn write_neurons<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let optional = |value: Option<f64>| value.map(|v| v.to_string()).unwrap_or_default();
+        writeln!(out, "neuron,rate_hz,cv,fano_factor")?;
+        for (i, rate) in self.rates.iter().enumerate() {
+            writeln!(
+                out,
+                "{},{},{},{}",
+                i,
+                rate,
+                optional(self.cvs[i]),
+                optional(self.fano_factors[i])
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Writes `isi_ms,count` for each bin of the ISI histogram, labelled by the bin's start
+    pub fn write_isi_histogram<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "isi_ms,count")?;
+        for (bin, count) in self.isi_histogram.counts.iter().enumerate() {
+            writeln!(
+                out,
+                "{},{}",
+                bin as u64 * self.isi_histogram.bin_width,
+                count
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for FiringStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let optional = |value: Option<f64>| match value {
+            Some(v) => format!("{:.3}", v),
+            None => "-".to_string(),
+        };
+
+        writeln!(f, "duration: {}ms", self.duration)?;
+        writeln!(
+            f,
+            "{:<12}{:>8}{:>12}{:>8}{:>8}{:>14}{:>8}",
+            "population", "neurons", "rate (Hz)", "CV", "Fano", "correlation", "chi"
+        )?;
+        for (name, stats) in [
+            ("excitatory", &self.excitatory),
+            ("inhibitory", &self.inhibitory),
+            ("all", &self.all),
+        ] {
+            writeln!(
+                f,
+                "{:<12}{:>8}{:>12.3}{:>8}{:>8}{:>14}{:>8}",
+                name,
+                stats.neurons,
+                stats.rate,
+                optional(stats.cv),
+                optional(stats.fano_factor),
+                optional(stats.correlation),
+                optional(stats.chi)
+            )?;
+        }
+
+        let intervals = self.isi_histogram.total();
+        if intervals > 0 {
+            let (mode, _) = self
+                .isi_histogram
+                .counts
+                .iter()
+                .enumerate()
+                .max_by_key(|(_, count)| **count)
+                .unwrap();
+            let start = mode as u64 * self.isi_histogram.bin_width;
+            writeln!(
+                f,
+                "{} ISIs, most common {}-{}ms",
+                intervals,
+                start,
+                start + self.isi_histogram.bin_width
+            )?;
+        }
+        write!(
+            f,
+            "counts binned at {}ms for correlation and chi, {}ms windows for Fano factors",
+            self.params.bin, self.params.fano_window
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand_distr::Exp;
+
+    use super::*;
+
+    fn close(a: f64, b: f64, tolerance: f64) -> bool {
+        (a - b).abs() <= tolerance
+    }
+
+    /// spike times of a Poisson process at `rate` Hz over `duration` ms, at most one per ms
+    fn poisson<R: Rng>(rate: f64, duration: u64, rng: &mut R) -> Vec<u64> {
+        let intervals = Exp::new(rate / 1000.0).unwrap();
+        let mut train = Vec::new();
+        let mut time = 0.0;
+        loop {
+            time += rng.sample(intervals).max(1.0);
+            if time >= duration as f64 {
+                return train;
+            }
+            train.push(time as u64);
+        }
+    }
+
+    #[test]
+    fn regular_trains_have_no_variability() {
+        let train: Vec<u64> = (0..100).map(|i| 7 + i * 20).collect();
+        assert_eq!(cv(&train), Some(0.0));
+        // every 100ms window holds 5 spikes
+        assert_eq!(fano_factor(&train, 2000, 100), Some(0.0));
+        assert_eq!(rate(&train, 2000), 50.0);
+    }
+
+    #[test]
+    fn known_cv() {
+        // intervals of 10 and 30 have a mean of 20 and a standard deviation of 10
+        assert_eq!(cv(&[0, 10, 40, 50, 80]), Some(0.5));
+        assert_eq!(cv(&[0, 10]), None);
+        assert_eq!(cv(&[]), None);
+    }
+
+    #[test]
+    fn poisson_trains_vary_like_poisson() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let train = poisson(20.0, 200_000, &mut rng);
+        let cv = cv(&train).unwrap();
+        let fano = fano_factor(&train, 200_000, 100).unwrap();
+        assert!(close(cv, 1.0, 0.05), "CV {}", cv);
+        assert!(close(fano, 1.0, 0.1), "Fano factor {}", fano);
+    }
+
+    #[test]
+    fn known_correlations() {
+        let a = [0.0, 1.0, 2.0, 3.0];
+        assert!(close(correlation(&a, &a).unwrap(), 1.0, 1e-12));
+        assert!(close(
+            correlation(&a, &[3.0, 2.0, 1.0, 0.0]).unwrap(),
+            -1.0,
+            1e-12
+        ));
+        assert!(close(
+            correlation(&a, &[1.0, 0.0, 0.0, 1.0]).unwrap(),
+            0.0,
+            1e-12
+        ));
+        assert_eq!(correlation(&a, &[1.0; 4]), None);
+    }
+
+    #[test]
+    fn pairwise_correlation_sample() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let binned: Vec<Vec<f64>> = (0..30)
+            .map(|_| binned_counts(&poisson(20.0, 10_000, &mut rng), 10_000, 5))
+            .collect();
+        // every pair or a sample of them
+        let all = mean_pairwise_correlation(&binned, 10_000, &mut rng).unwrap();
+        let sampled = mean_pairwise_correlation(&binned, 100, &mut rng).unwrap();
+        assert!(close(all, 0.0, 0.01), "correlation {}", all);
+        assert!(close(sampled, 0.0, 0.02), "correlation {}", sampled);
+
+        // the same seed samples the same pairs
+        let seeded =
+            |seed| mean_pairwise_correlation(&binned, 50, &mut StdRng::seed_from_u64(seed));
+        assert_eq!(seeded(3), seeded(3));
+
+        let identical = vec![binned[0].clone(); 5];
+        assert_eq!(
+            mean_pairwise_correlation(&identical, 3, &mut rng),
+            Some(1.0)
+        );
+        let silent = vec![vec![0.0; 10]; 5];
+        assert_eq!(mean_pairwise_correlation(&silent, 10, &mut rng), None);
+    }
+
+    #[test]
+    fn identical_trains_are_synchronous() {
+        let mut rng = StdRng::seed_from_u64(4);
+        let counts = binned_counts(&poisson(20.0, 10_000, &mut rng), 10_000, 5);
+        let chi = golomb_chi(&vec![counts; 10]).unwrap();
+        assert!(close(chi, 1.0, 1e-12), "chi {}", chi);
+
+        // independent trains fall off as 1/sqrt(N)
+        let independent: Vec<Vec<f64>> = (0..100)
+            .map(|_| binned_counts(&poisson(20.0, 10_000, &mut rng), 10_000, 5))
+            .collect();
+        let chi = golomb_chi(&independent).unwrap();
+        assert!(close(chi, 0.1, 0.03), "chi {}", chi);
+    }
+
+    #[test]
+    fn histogram_bins() {
+        let histogram = Histogram::new(vec![0, 4, 5, 12].into_iter(), 5);
+        assert_eq!(histogram.counts, vec![2, 1, 1]);
+        assert_eq!(histogram.total(), 4);
+        assert_eq!(binned_counts(&[0, 4, 5, 12], 10, 5), vec![2.0, 1.0]);
+    }
+}
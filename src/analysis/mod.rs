@@ -0,0 +1,7 @@
+pub mod avalanche;
+pub mod correlogram;
+pub mod distance;
+pub mod firing;
+pub mod pattern;
+pub mod polychrony;
+pub mod rhythm;
@@ -0,0 +1,485 @@
+use std::fmt;
+use std::io;
+use std::io::Write;
+
+use rayon::prelude::*;
+
+use crate::izhikevich::Izhikevich;
+use crate::synapse::Synapses;
+
+/// Firing patterns from Izhikevich (2003) and (2004)
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum FiringClass {
+    RegularSpiking,
+    IntrinsicallyBursting,
+    Chattering,
+    FastSpiking,
+    LowThresholdSpiking,
+    /// a single spike or a few at the start of the stimulus and nothing after
+    PhasicSpiking,
+    /// a burst at the start of the stimulus and nothing after
+    PhasicBursting,
+    Silent,
+}
+
+impl FiringClass {
+    pub const ALL: [FiringClass; 8] = [
+        FiringClass::RegularSpiking,
+        FiringClass::IntrinsicallyBursting,
+        FiringClass::Chattering,
+        FiringClass::FastSpiking,
+        FiringClass::LowThresholdSpiking,
+        FiringClass::PhasicSpiking,
+        FiringClass::PhasicBursting,
+        FiringClass::Silent,
+    ];
+
+    /// classes the network's neurons are drawn around in Izhikevich (2003)
+    pub const CORTICAL: [FiringClass; 5] = [
+        FiringClass::RegularSpiking,
+        FiringClass::IntrinsicallyBursting,
+        FiringClass::Chattering,
+        FiringClass::FastSpiking,
+        FiringClass::LowThresholdSpiking,
+    ];
+
+    pub fn abbreviation(self) -> &'static str {
+        match self {
+            FiringClass::RegularSpiking => "RS",
+            FiringClass::IntrinsicallyBursting => "IB",
+            FiringClass::Chattering => "CH",
+            FiringClass::FastSpiking => "FS",
+            FiringClass::LowThresholdSpiking => "LTS",
+            FiringClass::PhasicSpiking => "PS",
+            FiringClass::PhasicBursting => "PB",
+            FiringClass::Silent => "-",
+        }
+    }
+
+    /// (a, b, c, d) of the cortical classes from Izhikevich (2003)
+    pub fn parameters(self) -> Option<(f32, f32, f32, f32)> {
+        match self {
+            FiringClass::RegularSpiking => Some((0.02, 0.2, -65.0, 8.0)),
+            FiringClass::IntrinsicallyBursting => Some((0.02, 0.2, -55.0, 4.0)),
+            FiringClass::Chattering => Some((0.02, 0.2, -50.0, 2.0)),
+            FiringClass::FastSpiking => Some((0.1, 0.2, -65.0, 2.0)),
+            FiringClass::LowThresholdSpiking => Some((0.02, 0.25, -65.0, 2.0)),
+            _ => None,
+        }
+    }
+
+    /// The cortical class whose parameters are closest to the neuron's, scaling each parameter
+    /// by how far it's spread in `randomized_neurons`
+    pub fn nearest(neuron: &Izhikevich) -> FiringClass {
+        let distance = |class: &FiringClass| {
+            let (a, b, c, d) = class.parameters().unwrap();
+            ((neuron.decay_rate - a) / 0.08).powi(2)
+                + ((neuron.sensitivity - b) / 0.05).powi(2)
+                + ((neuron.v_reset - c) / 15.0).powi(2)
+                + ((neuron.u_reset - d) / 6.0).powi(2)
+        };
+        *FiringClass::CORTICAL
+            .iter()
+            .min_by(|x, y| distance(x).total_cmp(&distance(y)))
+            .unwrap()
+    }
+}
+
+impl fmt::Display for FiringClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.abbreviation())
+    }
+}
+
+/// How spike trains are split into bursts and classified
+#[derive(Debug, Copy, Clone)]
+pub struct PatternParams {
+    /// intervals shorter than this fraction of the mean interval are within a burst
+    pub burst_ratio: f64,
+    /// trains firing faster than this in Hz once settled are fast spiking or low-threshold
+    pub fast_rate: f64,
+    /// trains whose `adaptation` is below this don't slow down, which tells fast spiking
+    /// neurons driven weakly apart from regular spiking ones
+    pub min_adaptation: f64,
+    /// trains that stop firing before this fraction of the stimulus are phasic
+    pub phasic_fraction: f64,
+}
+
+impl Default for PatternParams {
+    fn default() -> Self {
+        PatternParams {
+            burst_ratio: 0.3,
+            fast_rate: 40.0,
+            min_adaptation: 0.003,
+            phasic_fraction: 0.2,
+        }
+    }
+}
+
+/// Features of a spike train over a stimulus
+#[derive(Debug, Copy, Clone, Default)]
+pub struct PatternFeatures {
+    pub spikes: usize,
+    /// mean rate in Hz over the stimulus
+    pub rate: f64,
+    /// rate in Hz over the second half of the intervals
+    pub steady_rate: f64,
+    /// ms from the start of the stimulus to the first and last spikes
+    pub first_spike: Option<u64>,
+    pub last_spike: Option<u64>,
+    /// runs of at least 2 spikes with intervals below the burst threshold
+    pub bursts: usize,
+    /// fraction of spikes in bursts
+    pub burst_fraction: f64,
+    /// whether the train starts with a burst
+    pub initial_burst: bool,
+    /// mean of (ISI[k+1] - ISI[k]) / (ISI[k+1] + ISI[k]), positive when the train slows down
+    pub adaptation: f64,
+    /// first interval over the settled interval, well below 1 for trains that start fast
+    pub initial_ratio: f64,
+}
+
+impl PatternFeatures {
+    /// Features of a sorted train with times in ms from the start of a stimulus `window` ms long
+    pub fn compute(train: &[u64], window: u64, params: &PatternParams) -> Self {
+        let window = window.max(1);
+        let mut features = PatternFeatures {
+            spikes: train.len(),
+            rate: train.len() as f64 * 1000.0 / window as f64,
+            first_spike: train.first().copied(),
+            last_spike: train.last().copied(),
+            ..PatternFeatures::default()
+        };
+        let isis: Vec<f64> = train.windows(2).map(|w| (w[1] - w[0]) as f64).collect();
+        if isis.is_empty() {
+            return features;
+        }
+
+        let mean = isis.iter().sum::<f64>() / isis.len() as f64;
+        let threshold = params.burst_ratio * mean;
+        let mut in_burst = 0;
+        let mut run = 0;
+        for &isi in &isis {
+            if isi < threshold {
+                run += 1;
+            } else if run > 0 {
+                features.bursts += 1;
+                in_burst += run + 1;
+                run = 0;
+            }
+        }
+        if run > 0 {
+            features.bursts += 1;
+            in_burst += run + 1;
+        }
+        features.burst_fraction = in_burst as f64 / train.len() as f64;
+        features.initial_burst = isis[0] < threshold;
+
+        let settled = &isis[isis.len() / 2..];
+        let settled_mean = settled.iter().sum::<f64>() / settled.len() as f64;
+        features.steady_rate = 1000.0 / settled_mean;
+        features.initial_ratio = isis[0] / settled_mean;
+        if isis.len() > 1 {
+            features.adaptation = isis
+                .windows(2)
+                .map(|w| (w[1] - w[0]) / (w[1] + w[0]))
+                .sum::<f64>()
+                / (isis.len() - 1) as f64;
+        }
+        features
+    }
+
+    pub fn classify(&self, window: u64, params: &PatternParams) -> FiringClass {
+        let last = match self.last_spike {
+            Some(last) => last,
+            None => return FiringClass::Silent,
+        };
+        if (last as f64) < params.phasic_fraction * window as f64 {
+            return if self.initial_burst {
+                FiringClass::PhasicBursting
+            } else {
+                FiringClass::PhasicSpiking
+            };
+        }
+        // with fewer than 2 intervals there's no bursting, adaptation or settled rate to go on,
+        // a neuron only just driven past threshold fires sparsely like a regular spiking one
+        if self.spikes < 3 {
+            return FiringClass::RegularSpiking;
+        }
+        if self.bursts >= 2 && self.burst_fraction >= 0.5 {
+            return FiringClass::Chattering;
+        }
+        if self.initial_burst {
+            return FiringClass::IntrinsicallyBursting;
+        }
+        if self.steady_rate >= params.fast_rate || self.adaptation < params.min_adaptation {
+            return if self.initial_ratio < 0.5 {
+                FiringClass::LowThresholdSpiking
+            } else {
+                FiringClass::FastSpiking
+            };
+        }
+        FiringClass::RegularSpiking
+    }
+}
+
+/// The step current protocol used to probe a neuron's firing pattern in isolation
+#[derive(Debug, Copy, Clone)]
+pub struct ProbeParams {
+    /// ms without input before the step so the neuron settles at rest
+    pub settle: u64,
+    /// step length in ms
+    pub duration: u64,
+    pub current: f32,
+}
+
+impl Default for ProbeParams {
+    fn default() -> Self {
+        ProbeParams {
+            settle: 100,
+            duration: 1000,
+            current: 10.0,
+        }
+    }
+}
+
+/// Spike times in ms from the start of a step current into a copy of the neuron starting at rest,
+/// with no synaptic input
+pub fn probe(neuron: &Izhikevich, params: &ProbeParams) -> Vec<u64> {
+    let mut neuron = *neuron;
+    neuron.v = -65.0;
+    neuron.u = neuron.sensitivity * neuron.v;
+    let mut synapses = Synapses::default();
+
+    (0..params.settle + params.duration)
+        .filter(|&t| {
+            let current = if t < params.settle {
+                0.0
+            } else {
+                params.current
+            };
+            neuron.compute_step(current, &mut synapses) && t >= params.settle
+        })
+        .map(|t| t - params.settle)
+        .collect()
+}
+
+/// Spike times from a voltage trace sampled every ms, where it first reaches `threshold`
+pub fn spikes_from_trace(trace: &[f64], threshold: f64) -> Vec<u64> {
+    (0..trace.len())
+        .filter(|&t| trace[t] >= threshold && (t == 0 || trace[t - 1] < threshold))
+        .map(|t| t as u64)
+        .collect()
+}
+
+/// Classes and features of every neuron of a network
+#[derive(Debug, Clone)]
+pub struct Classification {
+    pub excitatory: usize,
+    pub classes: Vec<FiringClass>,
+    pub features: Vec<PatternFeatures>,
+    /// the class each neuron's parameters are closest to when it was probed
+    pub expected: Option<Vec<FiringClass>>,
+}
+
+impl Classification {
+    /// Classifies trains with times from the start of a stimulus `window` ms long
+    pub fn from_trains(
+        trains: &[Vec<u64>],
+        excitatory: usize,
+        window: u64,
+        params: &PatternParams,
+    ) -> Self {
+        let features: Vec<PatternFeatures> = trains
+            .par_iter()
+            .map(|train| PatternFeatures::compute(train, window, params))
+            .collect();
+        let classes = features
+            .iter()
+            .map(|features| features.classify(window, params))
+            .collect();
+        Classification {
+            excitatory,
+            classes,
+            features,
+            expected: None,
+        }
+    }
+
+    /// Probes every neuron with a step current and classifies its response
+    pub fn probe_neurons(
+        neurons: &[Izhikevich],
+        excitatory: usize,
+        probe_params: &ProbeParams,
+        params: &PatternParams,
+    ) -> Self {
+        let trains: Vec<Vec<u64>> = neurons
+            .par_iter()
+            .map(|neuron| probe(neuron, probe_params))
+            .collect();
+        Classification {
+            expected: Some(neurons.iter().map(FiringClass::nearest).collect()),
+            ..Classification::from_trains(&trains, excitatory, probe_params.duration, params)
+        }
+    }
+
+    /// Number of neurons of each class among `neurons`
+    pub fn counts(&self, neurons: std::ops::Range<usize>) -> Vec<(FiringClass, usize)> {
+        FiringClass::ALL
+            .iter()
+            .map(|&class| {
+                let count = self.classes[neurons.clone()]
+                    .iter()
+                    .filter(|c| **c == class)
+                    .count();
+                (class, count)
+            })
+            .filter(|(_, count)| *count > 0)
+            .collect()
+    }
+
+    /// Writes the class, expected class and features of each neuron, leaving the expected class
+    /// out for recordings and the latency out for silent neurons
+    pub fn write_csv<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(
+            out,
+            "neuron,class,expected,spikes,latency_ms,rate_hz,steady_rate_hz,bursts,burst_fraction,adaptation"
+        )?;
+        for (i, (class, features)) in self.classes.iter().zip(&self.features).enumerate() {
+            let expected = match &self.expected {
+                Some(expected) => expected[i].abbreviation(),
+                None => "",
+            };
+            writeln!(
+                out,
+                "{},{},{},{},{},{},{},{},{},{}",
+                i,
+                class,
+                expected,
+                features.spikes,
+                features
+                    .first_spike
+                    .map_or(String::new(), |latency| latency.to_string()),
+                features.rate,
+                features.steady_rate,
+                features.bursts,
+                features.burst_fraction,
+                features.adaptation
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Classification {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let total = self.classes.len();
+        let populations = [
+            ("excitatory", 0..self.excitatory.min(total)),
+            ("inhibitory", self.excitatory.min(total)..total),
+        ];
+        let mut lines = Vec::new();
+        for (name, neurons) in populations {
+            if neurons.is_empty() {
+                continue;
+            }
+            let size = neurons.len() as f64;
+            let counts: Vec<String> = self
+                .counts(neurons)
+                .iter()
+                .map(|(class, count)| {
+                    format!("{} {} ({:.0}%)", class, count, 100.0 * *count as f64 / size)
+                })
+                .collect();
+            lines.push(format!("{}: {}", name, counts.join(", ")));
+        }
+
+        if let Some(expected) = &self.expected {
+            lines.push("detected classes by nearest parameters:".to_string());
+            for class in FiringClass::CORTICAL {
+                let detected: Vec<FiringClass> = expected
+                    .iter()
+                    .zip(&self.classes)
+                    .filter(|(e, _)| **e == class)
+                    .map(|(_, detected)| *detected)
+                    .collect();
+                if detected.is_empty() {
+                    continue;
+                }
+                let matching = detected.iter().filter(|d| **d == class).count();
+                lines.push(format!(
+                    "  {}: {} neurons, {:.0}% detected as {}",
+                    class,
+                    detected.len(),
+                    100.0 * matching as f64 / detected.len() as f64,
+                    class
+                ));
+            }
+        }
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn neuron(class: FiringClass) -> Izhikevich {
+        let (a, b, c, d) = class.parameters().unwrap();
+        Izhikevich {
+            decay_rate: a,
+            sensitivity: b,
+            v_reset: c,
+            u_reset: d,
+            v: -65.0,
+            u: b * -65.0,
+        }
+    }
+
+    fn probed_class(class: FiringClass, current: f32) -> FiringClass {
+        let probe_params = ProbeParams {
+            current,
+            ..ProbeParams::default()
+        };
+        let params = PatternParams::default();
+        let train = probe(&neuron(class), &probe_params);
+        PatternFeatures::compute(&train, probe_params.duration, &params)
+            .classify(probe_params.duration, &params)
+    }
+
+    #[test]
+    fn cortical_classes_are_recognized() {
+        for class in FiringClass::CORTICAL {
+            assert_eq!(probed_class(class, ProbeParams::default().current), class);
+        }
+    }
+
+    #[test]
+    fn slow_fast_spiking_is_told_apart_by_adaptation() {
+        // too weakly driven to reach the fast rate, only the lack of adaptation gives it away
+        assert_eq!(
+            probed_class(FiringClass::FastSpiking, 5.0),
+            FiringClass::FastSpiking
+        );
+        assert_eq!(
+            probed_class(FiringClass::RegularSpiking, 5.0),
+            FiringClass::RegularSpiking
+        );
+    }
+
+    #[test]
+    fn short_trains() {
+        let params = PatternParams::default();
+        let class =
+            |train: &[u64]| PatternFeatures::compute(train, 1000, &params).classify(1000, &params);
+
+        assert_eq!(class(&[]), FiringClass::Silent);
+        // too early to be anything but phasic
+        assert_eq!(class(&[10]), FiringClass::PhasicSpiking);
+        assert_eq!(class(&[10, 14]), FiringClass::PhasicSpiking);
+        // sparse firing through the stimulus
+        assert_eq!(class(&[600]), FiringClass::RegularSpiking);
+        assert_eq!(class(&[300, 700]), FiringClass::RegularSpiking);
+        assert_eq!(class(&[500, 504]), FiringClass::RegularSpiking);
+    }
+}
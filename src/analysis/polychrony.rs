@@ -0,0 +1,512 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashSet};
+use std::io;
+use std::io::Write;
+
+use rand::prelude::*;
+use rand::seq::index;
+use rayon::prelude::*;
+
+use crate::network::Network;
+use crate::recording::SpikeRecording;
+
+/// How the cascades started by each anchor triplet are followed
+#[derive(Debug, Copy, Clone)]
+pub struct PolychronyParams {
+    /// only excitatory connections at least this strong carry a cascade
+    pub min_weight: f32,
+    /// spikes that have to arrive at a neuron within `jitter` ms to make it fire
+    pub threshold: usize,
+    pub jitter: u64,
+    /// cascades are followed for at most this many ms
+    pub max_span: u64,
+    /// anchor triplets tried for each neuron, picked at random when it has more than that
+    pub triplets_per_neuron: usize,
+    /// groups with fewer spikes than this, anchors included, are dropped
+    pub min_size: usize,
+}
+
+impl PolychronyParams {
+    /// Izhikevich (2006) counts synapses within 95% of the strongest as strong
+    pub fn for_network(network: &Network) -> Self {
+        let strongest = network
+            .connections
+            .slice(ndarray::s![.., ..network.excitatory])
+            .iter()
+            .copied()
+            .fold(0.0, f32::max);
+        PolychronyParams {
+            min_weight: 0.95 * strongest,
+            threshold: 2,
+            jitter: 1,
+            max_span: 100,
+            triplets_per_neuron: 20,
+            min_size: 5,
+        }
+    }
+}
+
+/// A spike in a polychronous group at a time relative to the first anchor
+#[derive(Debug, Clone, PartialEq)]
+pub struct GroupSpike {
+    pub neuron: usize,
+    pub time: u64,
+    /// 0 for the anchors, otherwise one more than the deepest spike that caused it
+    pub layer: usize,
+    /// indices of the spikes in the group whose arrivals made this neuron fire
+    pub causes: Vec<usize>,
+}
+
+/// A time locked pattern of spikes that follows from the anchors firing with the right timing,
+/// as in Izhikevich (2006)
+#[derive(Debug, Clone)]
+pub struct PolychronousGroup {
+    /// the neuron the anchors' spikes converge on
+    pub mother: usize,
+    /// spikes in time order starting with the anchors
+    pub spikes: Vec<GroupSpike>,
+}
+
+impl PolychronousGroup {
+    pub fn size(&self) -> usize {
+        self.spikes.len()
+    }
+
+    /// number of layers in the longest causal path
+    pub fn length(&self) -> usize {
+        self.spikes
+            .iter()
+            .map(|spike| spike.layer)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// time in ms from the first spike to the last
+    pub fn span(&self) -> u64 {
+        self.spikes
+            .iter()
+            .map(|spike| spike.time)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// Searches anchor triplets converging on every neuron for groups. Each neuron fires at most
+/// once per group and does so 1ms after the `threshold`th strong spike arrives. `rng` picks the
+/// triplets of neurons with more than `triplets_per_neuron` of them.
+pub fn find_groups<R: Rng>(
+    network: &Network,
+    params: &PolychronyParams,
+    rng: &mut R,
+) -> Vec<PolychronousGroup> {
+    let total = network.len();
+    // each neuron is searched in parallel with its own generator, seeded in order so the groups
+    // only depend on `rng`
+    let seeds: Vec<u64> = (0..total).map(|_| rng.gen()).collect();
+    let delay = |post: usize, pre: usize| match &network.delays {
+        Some(delays) => delays[[post, pre]] as u64,
+        None => 1,
+    };
+
+    // strong outgoing connections of each excitatory neuron as (post, delay)
+    let outgoing: Vec<Vec<(usize, u64)>> = (0..total)
+        .map(|pre| {
+            if pre >= network.excitatory {
+                return Vec::new();
+            }
+            (0..total)
+                .filter(|&post| {
+                    post != pre && network.connections[[post, pre]] >= params.min_weight
+                })
+                .map(|post| (post, delay(post, pre)))
+                .collect()
+        })
+        .collect();
+
+    let groups: Vec<PolychronousGroup> = (0..total)
+        .into_par_iter()
+        .flat_map_iter(|mother| {
+            let strong: Vec<usize> = (0..network.excitatory)
+                .filter(|&pre| {
+                    pre != mother && network.connections[[mother, pre]] >= params.min_weight
+                })
+                .collect();
+            let mut rng = StdRng::seed_from_u64(seeds[mother]);
+            triplets(strong.len(), params.triplets_per_neuron, &mut rng)
+                .into_iter()
+                .filter_map(|[a, b, c]| {
+                    let anchors = [strong[a], strong[b], strong[c]];
+                    // fire the anchors so their spikes all reach the mother together
+                    let latest = anchors.iter().map(|&pre| delay(mother, pre)).max().unwrap();
+                    let timed = anchors.map(|pre| (pre, latest - delay(mother, pre)));
+                    let spikes = cascade(&timed, &outgoing, params);
+                    if spikes.len() >= params.min_size {
+                        Some(PolychronousGroup { mother, spikes })
+                    } else {
+                        None
+                    }
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect();
+
+    // different anchors can set off the same pattern
+    let mut seen = HashSet::new();
+    groups
+        .into_iter()
+        .filter(|group| {
+            let mut key: Vec<(usize, u64)> = group
+                .spikes
+                .iter()
+                .map(|spike| (spike.neuron, spike.time))
+                .collect();
+            key.sort_unstable();
+            seen.insert(key)
+        })
+        .collect()
+}
+
+/// Up to `limit` distinct triplets of indices below `n`, every one of them if there are few enough
+fn triplets<R: Rng>(n: usize, limit: usize, rng: &mut R) -> Vec<[usize; 3]> {
+    if n < 3 {
+        return Vec::new();
+    }
+    let count = n * (n - 1) * (n - 2) / 6;
+    if count <= limit {
+        let mut all = Vec::with_capacity(count);
+        for a in 0..n {
+            for b in a + 1..n {
+                for c in b + 1..n {
+                    all.push([a, b, c]);
+                }
+            }
+        }
+        return all;
+    }
+
+    // kept in the order they're drawn, iterating over the set would depend on its hasher
+    let mut seen = HashSet::new();
+    let mut picked = Vec::with_capacity(limit);
+    while picked.len() < limit {
+        let mut triplet = index::sample(rng, n, 3).into_vec();
+        triplet.sort_unstable();
+        let triplet = [triplet[0], triplet[1], triplet[2]];
+        if seen.insert(triplet) {
+            picked.push(triplet);
+        }
+    }
+    picked
+}
+
+/// Follows the spikes set off by the anchors firing at the given times
+fn cascade(
+    anchors: &[(usize, u64)],
+    outgoing: &[Vec<(usize, u64)>],
+    params: &PolychronyParams,
+) -> Vec<GroupSpike> {
+    let first = anchors.iter().map(|(_, time)| *time).min().unwrap_or(0);
+    let mut spikes: Vec<GroupSpike> = anchors
+        .iter()
+        .map(|&(neuron, time)| GroupSpike {
+            neuron,
+            time: time - first,
+            layer: 0,
+            causes: Vec::new(),
+        })
+        .collect();
+    let mut fired: HashSet<usize> = anchors.iter().map(|(neuron, _)| *neuron).collect();
+    // recent arrivals at each neuron as (time, spike index)
+    let mut arrivals: Vec<Vec<(u64, usize)>> = vec![Vec::new(); outgoing.len()];
+    let mut queue = BinaryHeap::new();
+
+    let send = |queue: &mut BinaryHeap<_>, spikes: &[GroupSpike], index: usize| {
+        let spike = &spikes[index];
+        for &(post, delay) in &outgoing[spike.neuron] {
+            queue.push(Reverse((spike.time + delay, post, index)));
+        }
+    };
+    for index in 0..spikes.len() {
+        send(&mut queue, &spikes, index);
+    }
+
+    while let Some(Reverse((time, post, source))) = queue.pop() {
+        if time > params.max_span {
+            break;
+        }
+        if fired.contains(&post) {
+            continue;
+        }
+        let recent = &mut arrivals[post];
+        recent.retain(|(arrived, _)| arrived + params.jitter >= time);
+        recent.push((time, source));
+        if recent.len() < params.threshold {
+            continue;
+        }
+
+        let causes: Vec<usize> = recent.iter().map(|(_, index)| *index).collect();
+        let layer = causes.iter().map(|&i| spikes[i].layer).max().unwrap_or(0) + 1;
+        fired.insert(post);
+        spikes.push(GroupSpike {
+            neuron: post,
+            time: time + 1,
+            layer,
+            causes,
+        });
+        send(&mut queue, &spikes, spikes.len() - 1);
+    }
+
+    sort_by_time(spikes)
+}
+
+/// Puts the spikes in time order with their causes pointing at the new positions
+fn sort_by_time(spikes: Vec<GroupSpike>) -> Vec<GroupSpike> {
+    let mut order: Vec<usize> = (0..spikes.len()).collect();
+    order.sort_by_key(|&i| spikes[i].time);
+    let mut position = vec![0; spikes.len()];
+    for (new, &old) in order.iter().enumerate() {
+        position[old] = new;
+    }
+
+    order
+        .into_iter()
+        .map(|old| {
+            let spike = &spikes[old];
+            GroupSpike {
+                causes: spike.causes.iter().map(|&cause| position[cause]).collect(),
+                ..spike.clone()
+            }
+        })
+        .collect()
+}
+
+/// Times in ms when the group shows up in a recording, which is whenever a spike of its first
+/// anchor is followed by at least `fraction` of the group's spikes, each within `jitter` ms of
+/// its time in the group
+pub fn occurrences(
+    group: &PolychronousGroup,
+    recording: &SpikeRecording,
+    jitter: u64,
+    fraction: f64,
+) -> Vec<u64> {
+    let first = &group.spikes[0];
+    let needed = (fraction * group.size() as f64).ceil() as usize;
+    let trains = &recording.trains;
+    if group
+        .spikes
+        .iter()
+        .any(|spike| spike.neuron >= trains.len())
+    {
+        return Vec::new();
+    }
+
+    trains[first.neuron]
+        .iter()
+        .copied()
+        .filter(|&start| {
+            let matched = group
+                .spikes
+                .iter()
+                .filter(|spike| {
+                    let expected = start + spike.time;
+                    let train = &trains[spike.neuron];
+                    let from = train.partition_point(|&t| t + jitter < expected);
+                    train.get(from).is_some_and(|&t| t <= expected + jitter)
+                })
+                .count();
+            matched >= needed
+        })
+        .collect()
+}
+
+/// Writes `group,neuron,time_ms,layer` for every spike of every group
+pub fn write_csv<W: Write>(out: &mut W, groups: &[PolychronousGroup]) -> io::Result<()> {
+    writeln!(out, "group,neuron,time_ms,layer")?;
+    for (i, group) in groups.iter().enumerate() {
+        for spike in &group.spikes {
+            writeln!(out, "{},{},{},{}", i, spike.neuron, spike.time, spike.layer)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::Array2;
+
+    use super::*;
+
+    fn params() -> PolychronyParams {
+        PolychronyParams {
+            min_weight: 0.9,
+            threshold: 2,
+            jitter: 1,
+            max_span: 100,
+            triplets_per_neuron: 20,
+            min_size: 5,
+        }
+    }
+
+    /// Anchors 0, 1 and 2 converge on 3 with delays of 5, 3 and 1ms. 3 then meets 2 at 4, and 4
+    /// meets 3 at 5, so firing the anchors at 0, 2 and 4ms sets off 3 at 6ms, 4 at 9ms and 5 at
+    /// 11ms. The weak connection from 0 to 5 doesn't count.
+    fn network() -> Network {
+        let strong = [
+            (0, 3, 5),
+            (1, 3, 3),
+            (2, 3, 1),
+            (3, 4, 2),
+            (2, 4, 4),
+            (4, 5, 1),
+            (3, 5, 4),
+        ];
+        let mut network = Network::randomized(6, 0, &mut StdRng::seed_from_u64(0));
+        network.connections.fill(0.0);
+        let mut delays = Array2::ones((6, 6));
+        for (pre, post, delay) in strong {
+            network.connections[[post, pre]] = 1.0;
+            delays[[post, pre]] = delay;
+        }
+        network.connections[[5, 0]] = 0.5;
+        network.delays = Some(delays);
+        network
+    }
+
+    fn outgoing(network: &Network, params: &PolychronyParams) -> Vec<Vec<(usize, u64)>> {
+        let delays = network.delays.as_ref().unwrap();
+        (0..network.len())
+            .map(|pre| {
+                (0..network.len())
+                    .filter(|&post| network.connections[[post, pre]] >= params.min_weight)
+                    .map(|post| (post, delays[[post, pre]] as u64))
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn timing(spikes: &[GroupSpike]) -> Vec<(usize, u64, usize)> {
+        spikes
+            .iter()
+            .map(|spike| (spike.neuron, spike.time, spike.layer))
+            .collect()
+    }
+
+    #[test]
+    fn cascade_follows_the_delays() {
+        let params = params();
+        let outgoing = outgoing(&network(), &params);
+        let spikes = cascade(&[(0, 10), (1, 12), (2, 14)], &outgoing, &params);
+
+        assert_eq!(
+            timing(&spikes),
+            vec![
+                (0, 0, 0),
+                (1, 2, 0),
+                (2, 4, 0),
+                (3, 6, 1),
+                (4, 9, 2),
+                (5, 11, 3)
+            ]
+        );
+        // the first two spikes to arrive make a neuron fire
+        assert_eq!(spikes[3].causes, vec![0, 1]);
+        assert_eq!(spikes[4].causes, vec![2, 3]);
+        assert_eq!(spikes[5].causes, vec![3, 4]);
+    }
+
+    #[test]
+    fn cascade_needs_coincident_spikes() {
+        let params = params();
+        let outgoing = outgoing(&network(), &params);
+
+        // the anchors' spikes arrive 2ms apart, too far with 1ms of jitter
+        let spikes = cascade(&[(0, 0), (1, 4), (2, 8)], &outgoing, &params);
+        assert_eq!(spikes.len(), 3);
+
+        // 4 only ever gets two spikes
+        let strict = PolychronyParams {
+            threshold: 3,
+            ..params
+        };
+        let spikes = cascade(&[(0, 0), (1, 2), (2, 4)], &outgoing, &strict);
+        assert_eq!(timing(&spikes).last(), Some(&(3, 6, 1)));
+
+        // the spikes reaching 5 arrive after 10ms
+        let short = PolychronyParams {
+            max_span: 9,
+            ..params
+        };
+        let spikes = cascade(&[(0, 0), (1, 2), (2, 4)], &outgoing, &short);
+        assert_eq!(timing(&spikes).last(), Some(&(4, 9, 2)));
+    }
+
+    #[test]
+    fn finds_the_group() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let groups = find_groups(&network(), &params(), &mut rng);
+
+        assert_eq!(groups.len(), 1);
+        let group = &groups[0];
+        assert_eq!(group.mother, 3);
+        assert_eq!(
+            timing(&group.spikes),
+            vec![
+                (0, 0, 0),
+                (1, 2, 0),
+                (2, 4, 0),
+                (3, 6, 1),
+                (4, 9, 2),
+                (5, 11, 3)
+            ]
+        );
+        assert_eq!((group.size(), group.length(), group.span()), (6, 3, 11));
+
+        let larger = PolychronyParams {
+            min_size: 7,
+            ..params()
+        };
+        assert!(find_groups(&network(), &larger, &mut rng).is_empty());
+    }
+
+    #[test]
+    fn triplets_are_distinct_and_seeded() {
+        let mut rng = StdRng::seed_from_u64(2);
+        assert!(triplets(2, 10, &mut rng).is_empty());
+        assert_eq!(triplets(4, 10, &mut rng).len(), 4);
+
+        let picked = triplets(10, 50, &mut rng);
+        let distinct: HashSet<_> = picked.iter().collect();
+        assert_eq!(distinct.len(), 50);
+        assert!(picked.iter().all(|[a, b, c]| a < b && b < c && *c < 10));
+
+        let seeded = |seed| triplets(10, 50, &mut StdRng::seed_from_u64(seed));
+        assert_eq!(seeded(3), seeded(3));
+    }
+
+    #[test]
+    fn occurrences_within_jitter() {
+        let mut rng = StdRng::seed_from_u64(4);
+        let group = find_groups(&network(), &params(), &mut rng).remove(0);
+        let mut trains = vec![Vec::new(); 6];
+        // exactly at 100, two spikes 1ms off at 300 and only the anchors at 500
+        for (start, offsets) in [
+            (100, [0, 0, 0, 0, 0, 0]),
+            (300, [0, 0, 0, 1, -1, 0]),
+            (500, [0, 0, 0, 5, 5, 5]),
+        ] {
+            for (spike, offset) in group.spikes.iter().zip(offsets) {
+                let time = start + spike.time as i64 + offset;
+                trains[spike.neuron].push(time as u64);
+            }
+        }
+        let recording = SpikeRecording {
+            excitatory: 6,
+            inhibitory: 0,
+            duration: 1000,
+            trains,
+        };
+
+        assert_eq!(occurrences(&group, &recording, 1, 0.8), vec![100, 300]);
+        assert_eq!(occurrences(&group, &recording, 0, 0.8), vec![100]);
+        assert_eq!(occurrences(&group, &recording, 0, 0.5), vec![100, 300, 500]);
+    }
+}
@@ -0,0 +1,366 @@
+use std::f64::consts::PI;
+use std::io;
+use std::io::Write;
+
+use crate::recording::SpikeRecording;
+
+/// Every simulation step is 1ms
+pub const SAMPLE_RATE: f64 = 1000.0;
+
+/// A named range of frequencies in Hz, `low` inclusive and `high` exclusive
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Band {
+    pub name: &'static str,
+    pub low: f64,
+    pub high: f64,
+}
+
+/// The usual EEG bands, the 2003 paper reports alpha and gamma rhythms in the default network
+pub const BANDS: [Band; 5] = [
+    Band {
+        name: "delta",
+        low: 1.0,
+        high: 4.0,
+    },
+    Band {
+        name: "theta",
+        low: 4.0,
+        high: 8.0,
+    },
+    Band {
+        name: "alpha",
+        low: 8.0,
+        high: 13.0,
+    },
+    Band {
+        name: "beta",
+        low: 13.0,
+        high: 30.0,
+    },
+    Band {
+        name: "gamma",
+        low: 30.0,
+        high: 100.0,
+    },
+];
+
+/// Fraction of neurons spiking in each 1ms step, in Hz per neuron
+pub fn population_rate(recording: &SpikeRecording) -> Vec<f64> {
+    let mut rate = vec![0.0; recording.duration as usize];
+    let scale = SAMPLE_RATE / recording.len().max(1) as f64;
+    for train in &recording.trains {
+        for &time in train {
+            rate[time as usize] += scale;
+        }
+    }
+    rate
+}
+
+/// One-sided power spectral density, in signal units squared per Hz
+#[derive(Debug, Clone)]
+pub struct Spectrum {
+    pub frequencies: Vec<f64>,
+    pub power: Vec<f64>,
+}
+
+impl Spectrum {
+    /// Frequency with the most power at or above `min_frequency`
+    pub fn peak(&self, min_frequency: f64) -> Option<(f64, f64)> {
+        self.frequencies
+            .iter()
+            .copied()
+            .zip(self.power.iter().copied())
+            .filter(|(frequency, _)| *frequency >= min_frequency)
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+    }
+
+    /// Total power between the band's edges
+    pub fn band_power(&self, band: Band) -> f64 {
+        let resolution = self.resolution();
+        self.frequencies
+            .iter()
+            .zip(&self.power)
+            .filter(|(frequency, _)| **frequency >= band.low && **frequency < band.high)
+            .map(|(_, power)| power * resolution)
+            .sum()
+    }
+
+    /// Power in each of `BANDS`, strongest first, along with its share of the power in all of them
+    pub fn dominant_bands(&self) -> Vec<(Band, f64, f64)> {
+        let powers: Vec<(Band, f64)> = BANDS
+            .iter()
+            .map(|band| (*band, self.band_power(*band)))
+            .collect();
+        let total: f64 = powers.iter().map(|(_, power)| power).sum();
+        let mut bands: Vec<(Band, f64, f64)> = powers
+            .into_iter()
+            .map(|(band, power)| {
+                let relative = if total > 0.0 { power / total } else { 0.0 };
+                (band, power, relative)
+            })
+            .collect();
+        bands.sort_by(|a, b| b.1.total_cmp(&a.1));
+        bands
+    }
+
+    fn resolution(&self) -> f64 {
+        match self.frequencies.get(1) {
+            Some(frequency) => *frequency,
+            None => SAMPLE_RATE,
+        }
+    }
+
+    /// Writes `frequency_hz,power` for each frequency
+    pub fn write_csv<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "frequency_hz,power")?;
+        for (frequency, power) in self.frequencies.iter().zip(&self.power) {
+            writeln!(out, "{},{}", frequency, power)?;
+        }
+        Ok(())
+    }
+}
+
+/// Welch's method, averaging the periodograms of Hann windowed segments overlapping by half.
+/// The segment length is rounded down to a power of 2 and shortened to fit the signal, `None`
+/// if the signal has fewer than 8 samples.
+pub fn welch(signal: &[f64], segment: usize) -> Option<Spectrum> {
+    let segment = power_of_two_at_most(segment.min(signal.len()))?;
+    let step = (segment / 2).max(1);
+
+    let mut power = vec![0.0; segment / 2 + 1];
+    let mut segments = 0;
+    let mut start = 0;
+    while start + segment <= signal.len() {
+        for (total, p) in power
+            .iter_mut()
+            .zip(periodogram(&signal[start..start + segment]))
+        {
+            *total += p;
+        }
+        segments += 1;
+        start += step;
+    }
+    for p in &mut power {
+        *p /= segments as f64;
+    }
+
+    Some(Spectrum {
+        frequencies: frequencies(segment),
+        power,
+    })
+}
+
+/// Power spectra of consecutive windows of the signal
+#[derive(Debug, Clone)]
+pub struct Spectrogram {
+    /// time in ms at the middle of each window
+    pub times: Vec<f64>,
+    pub frequencies: Vec<f64>,
+    /// a spectrum for each time
+    pub power: Vec<Vec<f64>>,
+}
+
+impl Spectrogram {
+    /// Writes `time_ms,frequency_hz,power` rows, the long format most plotting tools take
+    pub fn write_csv<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "time_ms,frequency_hz,power")?;
+        for (time, power) in self.times.iter().zip(&self.power) {
+            for (frequency, p) in self.frequencies.iter().zip(power) {
+                writeln!(out, "{},{},{}", time, frequency, p)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Spectrogram with windows of `window` samples, rounded down to a power of 2, every `step`
+/// samples
+pub fn spectrogram(signal: &[f64], window: usize, step: usize) -> Option<Spectrogram> {
+    let window = power_of_two_at_most(window.min(signal.len()))?;
+    let step = step.max(1);
+
+    let mut times = Vec::new();
+    let mut power = Vec::new();
+    let mut start = 0;
+    while start + window <= signal.len() {
+        times.push((start + window / 2) as f64 * 1000.0 / SAMPLE_RATE);
+        power.push(periodogram(&signal[start..start + window]));
+        start += step;
+    }
+
+    Some(Spectrogram {
+        times,
+        frequencies: frequencies(window),
+        power,
+    })
+}
+
+fn power_of_two_at_most(n: usize) -> Option<usize> {
+    if n < 8 {
+        None
+    } else {
+        Some(1 << (usize::BITS - 1 - n.leading_zeros()))
+    }
+}
+
+fn frequencies(segment: usize) -> Vec<f64> {
+    (0..=segment / 2)
+        .map(|k| k as f64 * SAMPLE_RATE / segment as f64)
+        .collect()
+}
+
+/// One-sided periodogram of a segment with a power of 2 length, with the mean removed so the
+/// constant offset (like the resting potential) doesn't swamp the low frequencies
+fn periodogram(segment: &[f64]) -> Vec<f64> {
+    let n = segment.len();
+    let mean = segment.iter().sum::<f64>() / n as f64;
+    let window: Vec<f64> = (0..n)
+        .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f64 / n as f64).cos())
+        .collect();
+    let window_power: f64 = window.iter().map(|w| w * w).sum();
+
+    let mut re: Vec<f64> = segment
+        .iter()
+        .zip(&window)
+        .map(|(x, w)| (x - mean) * w)
+        .collect();
+    let mut im = vec![0.0; n];
+    fft(&mut re, &mut im);
+
+    (0..=n / 2)
+        .map(|k| {
+            let power = (re[k] * re[k] + im[k] * im[k]) / (SAMPLE_RATE * window_power);
+            // fold the negative frequencies in, except for DC and Nyquist which have none
+            if k == 0 || k == n / 2 {
+                power
+            } else {
+                2.0 * power
+            }
+        })
+        .collect()
+}
+
+/// In place iterative radix-2 FFT, the length has to be a power of 2
+fn fft(re: &mut [f64], im: &mut [f64]) {
+    let n = re.len();
+
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+
+    let mut length = 2;
+    while length <= n {
+        let angle = -2.0 * PI / length as f64;
+        for start in (0..n).step_by(length) {
+            for k in 0..length / 2 {
+                let (sin, cos) = (angle * k as f64).sin_cos();
+                let a = start + k;
+                let b = a + length / 2;
+                let t_re = re[b] * cos - im[b] * sin;
+                let t_im = re[b] * sin + im[b] * cos;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+        length <<= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sinusoid(amplitude: f64, frequency: f64, samples: usize) -> Vec<f64> {
+        (0..samples)
+            .map(|i| amplitude * (2.0 * PI * frequency * i as f64 / SAMPLE_RATE).sin())
+            .collect()
+    }
+
+    #[test]
+    fn fft_of_sinusoid_peaks_at_its_bin() {
+        let n = 64;
+        let bin = 5;
+        let mut re: Vec<f64> = (0..n)
+            .map(|i| (2.0 * PI * (bin * i) as f64 / n as f64).cos())
+            .collect();
+        let mut im = vec![0.0; n];
+        fft(&mut re, &mut im);
+
+        for k in 0..n {
+            let magnitude = re[k].hypot(im[k]);
+            if k == bin || k == n - bin {
+                assert!((magnitude - n as f64 / 2.0).abs() < 1e-9, "bin {}", k);
+            } else {
+                assert!(magnitude < 1e-9, "bin {} has {}", k, magnitude);
+            }
+        }
+    }
+
+    #[test]
+    fn welch_peaks_at_sinusoid_frequency() {
+        // a whole number of cycles in each 256 sample segment
+        let frequency = 10.0 * SAMPLE_RATE / 256.0;
+        let spectrum = welch(&sinusoid(1.0, frequency, 2048), 256).unwrap();
+
+        let (peak, _) = spectrum.peak(1.0).unwrap();
+        assert!((peak - frequency).abs() < 1e-9);
+    }
+
+    #[test]
+    fn periodogram_obeys_parseval() {
+        let signal: Vec<f64> = (0..128)
+            .map(|i| ((i * 37) % 11) as f64 - 0.3 * (i as f64 / 7.0).sin())
+            .collect();
+        let spectrum = welch(&signal, 128).unwrap();
+        let total: f64 = spectrum.power.iter().sum::<f64>() * spectrum.resolution();
+
+        // windowed variance, the same normalization as the periodogram
+        let n = signal.len();
+        let mean = signal.iter().sum::<f64>() / n as f64;
+        let window: Vec<f64> = (0..n)
+            .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f64 / n as f64).cos())
+            .collect();
+        let windowed: f64 = signal
+            .iter()
+            .zip(&window)
+            .map(|(x, w)| ((x - mean) * w).powi(2))
+            .sum();
+        let expected = windowed / window.iter().map(|w| w * w).sum::<f64>();
+
+        assert!((total - expected).abs() < 1e-9 * expected);
+    }
+
+    #[test]
+    fn welch_power_is_sinusoid_variance() {
+        let amplitude = 3.0;
+        let frequency = 8.0 * SAMPLE_RATE / 256.0;
+        let spectrum = welch(&sinusoid(amplitude, frequency, 1024), 256).unwrap();
+        let total: f64 = spectrum.power.iter().sum::<f64>() * spectrum.resolution();
+
+        assert!((total - amplitude * amplitude / 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn nan_power_doesnt_panic() {
+        let spectrum = Spectrum {
+            frequencies: vec![0.0, 10.0, 20.0, 30.0],
+            power: vec![1.0, f64::NAN, 3.0, 2.0],
+        };
+        // NaN sorts above every number
+        assert_eq!(spectrum.peak(15.0), Some((20.0, 3.0)));
+        assert!(spectrum.peak(0.0).unwrap().1.is_nan());
+        assert_eq!(spectrum.dominant_bands().len(), BANDS.len());
+    }
+}
@@ -0,0 +1,747 @@
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use rand::prelude::*;
+use structopt::StructOpt;
+
+use super::analysis::avalanche;
+use super::analysis::avalanche::AvalancheStats;
+use super::analysis::correlogram;
+use super::analysis::distance::{DistanceParams, RasterDistances};
+use super::analysis::firing::{FiringParams, FiringStats};
+use super::analysis::pattern;
+use super::analysis::pattern::{Classification, PatternParams, ProbeParams};
+use super::analysis::polychrony;
+use super::analysis::polychrony::PolychronyParams;
+use super::analysis::rhythm;
+use super::connectivity::formats;
+use super::izhikevich;
+use super::plot;
+use super::recording::{self, SpikeRecording};
+
+// Offline tools that work on files written by earlier runs instead of running a simulation,
+// a plain comment since structopt would make a doc comment the about text of --help
+#[derive(Debug, StructOpt, Clone)]
+pub enum Command {
+    /// compute firing statistics from a spike file written with --record-spikes
+    Analyze {
+        #[structopt(parse(from_os_str))]
+        spikes: PathBuf,
+
+        /// length of the recording in ms, defaults to just after the last spike
+        #[structopt(long = "duration")]
+        duration: Option<u64>,
+
+        /// bin width in ms of the spike counts used for correlation and synchrony
+        #[structopt(long = "bin", default_value = "5")]
+        bin: u64,
+
+        /// counting window in ms for Fano factors
+        #[structopt(long = "fano-window", default_value = "100")]
+        fano_window: u64,
+
+        /// bin width in ms of the ISI histogram
+        #[structopt(long = "isi-bin", default_value = "5")]
+        isi_bin: u64,
+
+        /// write the rate, CV and Fano factor of each neuron to this CSV file
+        #[structopt(long = "neurons-out", parse(from_os_str))]
+        neurons_out: Option<PathBuf>,
+
+        /// write the ISI histogram to this CSV file
+        #[structopt(long = "isi-out", parse(from_os_str))]
+        isi_out: Option<PathBuf>,
+    },
+
+    /// find rhythms in the population rate of a spike file from --record-spikes, or in a signal
+    /// like the LFP from --record-lfp
+    Spectrum {
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+
+        /// the file is a signal from --record-lfp or --record-voltage rather than spikes
+        #[structopt(long = "signal", alias = "lfp")]
+        signal: bool,
+
+        /// segment length in ms for Welch's method, rounded down to a power of 2
+        #[structopt(long = "segment", default_value = "1024")]
+        segment: usize,
+
+        /// window length in ms of the spectrogram, rounded down to a power of 2
+        #[structopt(long = "window", default_value = "512")]
+        window: usize,
+
+        /// time in ms between spectrogram windows
+        #[structopt(long = "window-step", default_value = "64")]
+        window_step: usize,
+
+        /// write the power spectral density to this CSV file
+        #[structopt(long = "psd-out", parse(from_os_str))]
+        psd_out: Option<PathBuf>,
+
+        /// write the spectrogram to this CSV file
+        #[structopt(long = "spectrogram-out", parse(from_os_str))]
+        spectrogram_out: Option<PathBuf>,
+    },
+
+    /// detect neuronal avalanches in a spike file from --record-spikes and fit power laws to
+    /// their sizes and durations
+    Avalanches {
+        #[structopt(parse(from_os_str))]
+        spikes: PathBuf,
+
+        /// time bin in ms, defaults to the mean time between spikes from the whole network
+        #[structopt(long = "bin")]
+        bin: Option<u64>,
+
+        /// bins need more than this many spikes to be part of an avalanche
+        #[structopt(long = "threshold", default_value = "0")]
+        threshold: u64,
+
+        /// smallest size and duration included in the power law fits
+        #[structopt(long = "xmin", default_value = "1")]
+        xmin: u64,
+
+        /// write every avalanche to this CSV file
+        #[structopt(long = "avalanches-out", parse(from_os_str))]
+        avalanches_out: Option<PathBuf>,
+    },
+
+    /// search a network saved with --save-connections for polychronous groups, time locked
+    /// cascades of spikes set off by triplets of anchor neurons
+    Polychrony {
+        #[structopt(parse(from_os_str))]
+        connections: PathBuf,
+
+        /// number of excitatory neurons in the network
+        #[structopt(long = "ne", default_value = "800")]
+        num_excitatory: usize,
+
+        /// number of inhibitory neurons in the network
+        #[structopt(long = "ni", default_value = "200")]
+        num_inhibitory: usize,
+
+        /// weight a connection needs to carry a cascade, defaults to 95% of the strongest
+        /// excitatory weight
+        #[structopt(long = "min-weight")]
+        min_weight: Option<f32>,
+
+        /// strong spikes that have to arrive within --jitter ms to make a neuron fire
+        #[structopt(long = "threshold", default_value = "2")]
+        threshold: usize,
+
+        #[structopt(long = "jitter", default_value = "1")]
+        jitter: u64,
+
+        /// longest time in ms a cascade is followed for
+        #[structopt(long = "max-span", default_value = "100")]
+        max_span: u64,
+
+        /// anchor triplets tried for each neuron
+        #[structopt(long = "triplets", default_value = "20")]
+        triplets: usize,
+
+        /// smallest number of spikes in a group, anchors included
+        #[structopt(long = "min-size", default_value = "5")]
+        min_size: usize,
+
+        /// count how often each group shows up in this spike file from --record-spikes
+        #[structopt(long = "spikes", parse(from_os_str))]
+        spikes: Option<PathBuf>,
+
+        /// fraction of a group's spikes that have to be in the recording for it to count
+        #[structopt(long = "match-fraction", default_value = "0.8")]
+        match_fraction: f64,
+
+        /// write the spikes of every group to this CSV file
+        #[structopt(long = "groups-out", parse(from_os_str))]
+        groups_out: Option<PathBuf>,
+
+        /// draw the largest groups to this .png or .svg file
+        #[structopt(long = "plot", parse(from_os_str))]
+        plot: Option<PathBuf>,
+    },
+
+    /// auto-correlogram of a neuron in a spike file from --record-spikes, or its
+    /// cross-correlogram with --target
+    Correlogram {
+        #[structopt(parse(from_os_str))]
+        spikes: PathBuf,
+
+        /// reference neuron
+        #[structopt(long = "neuron")]
+        neuron: usize,
+
+        /// neuron whose spikes are counted around the reference's
+        #[structopt(long = "target")]
+        target: Option<usize>,
+
+        /// longest lag in ms either way
+        #[structopt(long = "max-lag", default_value = "100")]
+        max_lag: u64,
+
+        /// bin width in ms, odd so a bin is centred on zero lag, even widths are widened by 1
+        #[structopt(long = "bin", default_value = "1")]
+        bin: u64,
+
+        /// write the correlogram to this CSV file
+        #[structopt(long = "out", parse(from_os_str))]
+        out: Option<PathBuf>,
+
+        /// draw the correlogram to this .png or .svg file
+        #[structopt(long = "plot", parse(from_os_str))]
+        plot: Option<PathBuf>,
+    },
+
+    /// spike-triggered average of a signal from --record-voltage or --record-lfp around a
+    /// neuron's spikes from --record-spikes, both recorded from the same run
+    Sta {
+        #[structopt(parse(from_os_str))]
+        spikes: PathBuf,
+
+        #[structopt(parse(from_os_str))]
+        signal: PathBuf,
+
+        /// neuron whose spikes trigger the average
+        #[structopt(long = "neuron")]
+        neuron: usize,
+
+        /// ms before each spike to average
+        #[structopt(long = "before", default_value = "50")]
+        before: u64,
+
+        /// ms after each spike to average
+        #[structopt(long = "after", default_value = "50")]
+        after: u64,
+
+        /// write the average to this CSV file
+        #[structopt(long = "out", parse(from_os_str))]
+        out: Option<PathBuf>,
+
+        /// draw the average to this .png or .svg file
+        #[structopt(long = "plot", parse(from_os_str))]
+        plot: Option<PathBuf>,
+    },
+
+    /// peri-stimulus time histogram of a spike file from --record-spikes around events
+    Psth {
+        #[structopt(parse(from_os_str))]
+        spikes: PathBuf,
+
+        /// event times in ms, like the --reward-at times
+        #[structopt(long = "events", use_delimiter = true)]
+        events: Vec<u64>,
+
+        /// read more event times in ms from the first column of this file
+        #[structopt(long = "events-file", parse(from_os_str))]
+        events_file: Option<PathBuf>,
+
+        /// neurons to pool, every neuron when left out
+        #[structopt(long = "neurons", use_delimiter = true)]
+        neurons: Vec<usize>,
+
+        /// ms before each event
+        #[structopt(long = "before", default_value = "100")]
+        before: u64,
+
+        /// ms after each event
+        #[structopt(long = "after", default_value = "500")]
+        after: u64,
+
+        /// bin width in ms
+        #[structopt(long = "bin", default_value = "10")]
+        bin: u64,
+
+        /// write the histogram to this CSV file
+        #[structopt(long = "out", parse(from_os_str))]
+        out: Option<PathBuf>,
+
+        /// draw the histogram to this .png or .svg file
+        #[structopt(long = "plot", parse(from_os_str))]
+        plot: Option<PathBuf>,
+    },
+
+    /// spike train distances between two spike files from --record-spikes of the same network,
+    /// like a run on the CPU and one on the GPU
+    Compare {
+        #[structopt(parse(from_os_str))]
+        a: PathBuf,
+
+        #[structopt(parse(from_os_str))]
+        b: PathBuf,
+
+        /// ms of both recordings to compare, defaults to the shorter of the two
+        #[structopt(long = "duration")]
+        duration: Option<u64>,
+
+        /// Victor-Purpura cost per ms of moving a spike
+        #[structopt(long = "cost", default_value = "0.1")]
+        cost: f64,
+
+        /// van Rossum kernel time constant in ms
+        #[structopt(long = "tau", default_value = "10")]
+        tau: f64,
+
+        /// write the distances of each neuron to this CSV file
+        #[structopt(long = "neurons-out", parse(from_os_str))]
+        neurons_out: Option<PathBuf>,
+
+        /// exit with an error when the mean SPIKE-distance is above this
+        #[structopt(long = "max-spike-distance")]
+        max_spike_distance: Option<f64>,
+    },
+
+    /// classify the firing pattern of each neuron as RS, IB, CH, FS, LTS, phasic or silent,
+    /// probing the neurons of a randomized network with a step current unless a recording is
+    /// given
+    Classify {
+        /// classify each neuron of a spike file from --record-spikes
+        #[structopt(long = "spikes", parse(from_os_str), conflicts_with = "trace")]
+        spikes: Option<PathBuf>,
+
+        /// classify a voltage trace from --record-voltage
+        #[structopt(long = "trace", parse(from_os_str))]
+        trace: Option<PathBuf>,
+
+        /// ms into the recording the stimulus starts, phasic patterns are only told apart from
+        /// there
+        #[structopt(long = "onset", default_value = "0")]
+        onset: u64,
+
+        /// number of excitatory neurons to probe
+        #[structopt(long = "ne", default_value = "800")]
+        num_excitatory: usize,
+
+        /// number of inhibitory neurons to probe
+        #[structopt(long = "ni", default_value = "200")]
+        num_inhibitory: usize,
+
+        /// step current injected when probing
+        #[structopt(long = "current", default_value = "10")]
+        current: f32,
+
+        /// length of the step in ms
+        #[structopt(long = "duration", default_value = "1000")]
+        duration: u64,
+
+        /// write the class and features of each neuron to this CSV file
+        #[structopt(long = "out", parse(from_os_str))]
+        out: Option<PathBuf>,
+    },
+}
+
+/// Runs the command, `rng` is seeded from --seed for the commands that sample at random
+pub fn run(command: Command, rng: &mut StdRng) {
+    match command {
+        Command::Analyze {
+            spikes,
+            duration,
+            bin,
+            fano_window,
+            isi_bin,
+            neurons_out,
+            isi_out,
+        } => {
+            let recording = SpikeRecording::load(&spikes, duration)
+                .unwrap_or_else(|err| panic!("error loading {}: {}", spikes.display(), err));
+            let params = FiringParams {
+                bin: bin.max(1),
+                fano_window: fano_window.max(1),
+                isi_bin: isi_bin.max(1),
+                ..FiringParams::default()
+            };
+            let stats = FiringStats::compute(&recording, params, rng);
+            println!("{}", stats);
+
+            if let Some(path) = neurons_out {
+                write_to(&path, |out| stats.write_neurons(out));
+            }
+            if let Some(path) = isi_out {
+                write_to(&path, |out| stats.write_isi_histogram(out));
+            }
+        }
+        Command::Spectrum {
+            file,
+            signal,
+            segment,
+            window,
+            window_step,
+            psd_out,
+            spectrogram_out,
+        } => {
+            let signal = if signal {
+                recording::load_signal(&file)
+            } else {
+                SpikeRecording::load(&file, None)
+                    .map(|recording| rhythm::population_rate(&recording))
+            }
+            .unwrap_or_else(|err| panic!("error loading {}: {}", file.display(), err));
+
+            let spectrum = rhythm::welch(&signal, segment)
+                .unwrap_or_else(|| panic!("{} is too short for a spectrum", file.display()));
+            if let Some((frequency, _)) = spectrum.peak(1.0) {
+                println!("peak frequency: {:.1}Hz", frequency);
+            }
+            println!("{:<8}{:>14}{:>10}", "band", "power", "share");
+            for (band, power, relative) in spectrum.dominant_bands() {
+                println!(
+                    "{:<8}{:>14.4}{:>9.1}%  ({}-{}Hz)",
+                    band.name,
+                    power,
+                    relative * 100.0,
+                    band.low,
+                    band.high
+                );
+            }
+
+            if let Some(path) = psd_out {
+                write_to(&path, |out| spectrum.write_csv(out));
+            }
+            if let Some(path) = spectrogram_out {
+                let spectrogram = rhythm::spectrogram(&signal, window, window_step)
+                    .unwrap_or_else(|| panic!("{} is too short for a spectrogram", file.display()));
+                write_to(&path, |out| spectrogram.write_csv(out));
+            }
+        }
+        Command::Avalanches {
+            spikes,
+            bin,
+            threshold,
+            xmin,
+            avalanches_out,
+        } => {
+            let recording = load_spikes(&spikes);
+            let bin = bin
+                .unwrap_or_else(|| avalanche::mean_inter_event_interval(&recording).round() as u64)
+                .max(1);
+            let avalanches = avalanche::detect(&recording, bin, threshold);
+            println!("{}", AvalancheStats::compute(&avalanches, bin, xmin));
+
+            if let Some(path) = avalanches_out {
+                write_to(&path, |out| avalanche::write_csv(out, &avalanches));
+            }
+        }
+        Command::Polychrony {
+            connections,
+            num_excitatory,
+            num_inhibitory,
+            min_weight,
+            threshold,
+            jitter,
+            max_span,
+            triplets,
+            min_size,
+            spikes,
+            match_fraction,
+            groups_out,
+            plot,
+        } => {
+            let network = formats::load_network(&connections, num_excitatory, num_inhibitory, rng)
+                .unwrap_or_else(|err| panic!("error loading {}: {}", connections.display(), err));
+            let defaults = PolychronyParams::for_network(&network);
+            let params = PolychronyParams {
+                min_weight: min_weight.unwrap_or(defaults.min_weight),
+                threshold: threshold.max(1),
+                jitter,
+                max_span,
+                triplets_per_neuron: triplets,
+                min_size,
+            };
+            log::info!("{:?}", params);
+
+            let mut groups = polychrony::find_groups(&network, &params, rng);
+            groups.sort_by_key(|group| std::cmp::Reverse(group.size()));
+            println!("{} polychronous groups", groups.len());
+            if groups.is_empty() {
+                return;
+            }
+
+            let mean = |value: &dyn Fn(&polychrony::PolychronousGroup) -> usize| {
+                groups.iter().map(value).sum::<usize>() as f64 / groups.len() as f64
+            };
+            println!(
+                "size: mean {:.1}, largest {}",
+                mean(&|group| group.size()),
+                groups[0].size()
+            );
+            println!(
+                "length: mean {:.1} layers, longest {}",
+                mean(&|group| group.length()),
+                groups.iter().map(|group| group.length()).max().unwrap_or(0)
+            );
+            println!(
+                "span: mean {:.1}ms, longest {}ms",
+                mean(&|group| group.span() as usize),
+                groups.iter().map(|group| group.span()).max().unwrap_or(0)
+            );
+
+            if let Some(path) = spikes {
+                let recording = load_spikes(&path);
+                let found: Vec<usize> = groups
+                    .iter()
+                    .map(|group| {
+                        polychrony::occurrences(group, &recording, jitter, match_fraction).len()
+                    })
+                    .collect();
+                println!(
+                    "{} of the groups show up in {}, {} times in total",
+                    found.iter().filter(|count| **count > 0).count(),
+                    path.display(),
+                    found.iter().sum::<usize>()
+                );
+                println!(
+                    "{:>8}{:>8}{:>8}{:>8}{:>10}{:>8}",
+                    "group", "mother", "size", "layers", "span (ms)", "seen"
+                );
+                for (i, (group, count)) in groups.iter().zip(&found).enumerate().take(10) {
+                    println!(
+                        "{:>8}{:>8}{:>8}{:>8}{:>10}{:>8}",
+                        i,
+                        group.mother,
+                        group.size(),
+                        group.length(),
+                        group.span(),
+                        count
+                    );
+                }
+            }
+
+            if let Some(path) = groups_out {
+                write_to(&path, |out| polychrony::write_csv(out, &groups));
+            }
+            if let Some(path) = plot {
+                plot::polychronous_groups(&path, &groups, network.excitatory);
+            }
+        }
+        Command::Correlogram {
+            spikes,
+            neuron,
+            target,
+            max_lag,
+            bin,
+            out,
+            plot,
+        } => {
+            let recording = load_spikes(&spikes);
+            if correlogram::correlogram_bin(bin) != bin {
+                log::warn!(
+                    "using {}ms bins, a {}ms bin can't be centred on zero lag",
+                    correlogram::correlogram_bin(bin),
+                    bin
+                );
+            }
+            let train = |neuron: usize| {
+                recording.trains.get(neuron).unwrap_or_else(|| {
+                    panic!("there's no neuron {} in {}", neuron, spikes.display())
+                })
+            };
+            let (histogram, caption) = match target {
+                Some(target) => (
+                    correlogram::cross_correlogram(train(neuron), train(target), max_lag, bin),
+                    format!("Cross-correlogram of neuron {} to {}", neuron, target),
+                ),
+                None => (
+                    correlogram::auto_correlogram(train(neuron), max_lag, bin),
+                    format!("Auto-correlogram of neuron {}", neuron),
+                ),
+            };
+            println!("{} reference spikes", histogram.references);
+
+            if let Some(path) = out {
+                write_to(&path, |out| histogram.write_csv(out));
+            }
+            if let Some(path) = plot {
+                plot::histogram(&path, &caption, &histogram);
+            }
+        }
+        Command::Sta {
+            spikes,
+            signal,
+            neuron,
+            before,
+            after,
+            out,
+            plot,
+        } => {
+            let recording = load_spikes(&spikes);
+            let values = recording::load_signal(&signal)
+                .unwrap_or_else(|err| panic!("error loading {}: {}", signal.display(), err));
+            let train = recording
+                .trains
+                .get(neuron)
+                .unwrap_or_else(|| panic!("there's no neuron {} in {}", neuron, spikes.display()));
+            let average = correlogram::spike_triggered_average(train, &values, before, after);
+            println!("averaged over {} spikes", average.spikes);
+
+            if let Some(path) = out {
+                write_to(&path, |out| average.write_csv(out));
+            }
+            if let Some(path) = plot {
+                let caption = format!("Average around the spikes of neuron {}", neuron);
+                plot::triggered_average(&path, &caption, &average);
+            }
+        }
+        Command::Psth {
+            spikes,
+            mut events,
+            events_file,
+            neurons,
+            before,
+            after,
+            bin,
+            out,
+            plot,
+        } => {
+            let recording = load_spikes(&spikes);
+            if let Some(path) = events_file {
+                events.extend(
+                    recording::load_events(&path)
+                        .unwrap_or_else(|err| panic!("error loading {}: {}", path.display(), err)),
+                );
+            }
+            if events.is_empty() {
+                panic!("no events given, use --events or --events-file");
+            }
+
+            let trains: Vec<&[u64]> = if neurons.is_empty() {
+                recording
+                    .trains
+                    .iter()
+                    .map(|train| train.as_slice())
+                    .collect()
+            } else {
+                neurons
+                    .iter()
+                    .map(|&neuron| {
+                        recording
+                            .trains
+                            .get(neuron)
+                            .map(|train| train.as_slice())
+                            .unwrap_or_else(|| {
+                                panic!("there's no neuron {} in {}", neuron, spikes.display())
+                            })
+                    })
+                    .collect()
+            };
+            let histogram = correlogram::psth(&trains, &events, before, after, bin);
+            println!("{} events over {} neurons", events.len(), trains.len());
+
+            if let Some(path) = out {
+                write_to(&path, |out| histogram.write_csv(out));
+            }
+            if let Some(path) = plot {
+                plot::histogram(&path, "Peri-stimulus time histogram", &histogram);
+            }
+        }
+        Command::Compare {
+            a,
+            b,
+            duration,
+            cost,
+            tau,
+            neurons_out,
+            max_spike_distance,
+        } => {
+            let first = load_spikes(&a);
+            let second = load_spikes(&b);
+            let duration = duration.unwrap_or_else(|| first.duration.min(second.duration));
+            let params = DistanceParams { cost, tau };
+            let distances = RasterDistances::compare(&first, &second, duration, &params)
+                .unwrap_or_else(|| {
+                    panic!(
+                        "{} has {} neurons but {} has {}",
+                        a.display(),
+                        first.len(),
+                        b.display(),
+                        second.len()
+                    )
+                });
+            println!("{}", distances);
+
+            if let Some(path) = neurons_out {
+                write_to(&path, |out| distances.write_csv(out));
+            }
+            if let Some(max) = max_spike_distance {
+                if distances.mean.spike > max {
+                    eprintln!(
+                        "mean SPIKE-distance {:.4} is above {}",
+                        distances.mean.spike, max
+                    );
+                    std::process::exit(1);
+                }
+            }
+        }
+        Command::Classify {
+            spikes,
+            trace,
+            onset,
+            num_excitatory,
+            num_inhibitory,
+            current,
+            duration,
+            out,
+        } => {
+            let params = PatternParams::default();
+            let from_onset = |train: &[u64]| -> Vec<u64> {
+                train
+                    .iter()
+                    .filter(|&&t| t >= onset)
+                    .map(|t| t - onset)
+                    .collect()
+            };
+
+            let classification = if let Some(path) = spikes {
+                let recording = load_spikes(&path);
+                let trains: Vec<Vec<u64>> = recording
+                    .trains
+                    .iter()
+                    .map(|train| from_onset(train))
+                    .collect();
+                let window = recording.duration.saturating_sub(onset);
+                Classification::from_trains(&trains, recording.excitatory, window, &params)
+            } else if let Some(path) = trace {
+                let trace = recording::load_signal(&path)
+                    .unwrap_or_else(|err| panic!("error loading {}: {}", path.display(), err));
+                let train = from_onset(&pattern::spikes_from_trace(&trace, 30.0));
+                let window = (trace.len() as u64).saturating_sub(onset);
+                Classification::from_trains(&[train], 1, window, &params)
+            } else {
+                let neurons = izhikevich::randomized_neurons(num_excitatory, num_inhibitory, rng);
+                let probe = ProbeParams {
+                    current,
+                    duration,
+                    ..ProbeParams::default()
+                };
+                Classification::probe_neurons(
+                    neurons.as_slice().unwrap(),
+                    num_excitatory,
+                    &probe,
+                    &params,
+                )
+            };
+            println!("{}", classification);
+
+            if let Some(path) = out {
+                write_to(&path, |out| classification.write_csv(out));
+            }
+        }
+    }
+}
+
+fn load_spikes(path: &Path) -> SpikeRecording {
+    SpikeRecording::load(path, None)
+        .unwrap_or_else(|err| panic!("error loading {}: {}", path.display(), err))
+}
+
+/// Creates the file and writes to it, panicking with the path on errors
+fn write_to(path: &Path, write: impl FnOnce(&mut BufWriter<File>) -> io::Result<()>) {
+    let mut out = BufWriter::new(
+        File::create(path)
+            .unwrap_or_else(|err| panic!("error creating {}: {}", path.display(), err)),
+    );
+    write(&mut out)
+        .and_then(|_| out.flush())
+        .unwrap_or_else(|err| panic!("error writing {}: {}", path.display(), err));
+}
@@ -0,0 +1,301 @@
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::network::Network;
+
+/// Graph formats the network structure can be exported to for other tools
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum GraphFormat {
+    /// GraphML, readable by Gephi, NetworkX and most graph libraries
+    GraphMl,
+    /// Graphviz DOT
+    Dot,
+}
+
+impl GraphFormat {
+    /// Picks the format from the file extension, `.graphml` or `.dot`/`.gv`
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("graphml") => Some(GraphFormat::GraphMl),
+            Some("dot") | Some("gv") => Some(GraphFormat::Dot),
+            _ => None,
+        }
+    }
+}
+
+/// Writes the neurons and every connection with a weight of at least `threshold` in magnitude,
+/// in the format picked by the file extension
+pub fn export_graph(path: &Path, network: &Network, threshold: f32) -> io::Result<()> {
+    let format = GraphFormat::from_path(path).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "can't tell the graph format of {}, expected a .graphml or .dot extension",
+                path.display()
+            ),
+        )
+    })?;
+    let mut out = BufWriter::new(File::create(path)?);
+
+    match format {
+        GraphFormat::GraphMl => write_graphml(&mut out, network, threshold)?,
+        GraphFormat::Dot => write_dot(&mut out, network, threshold)?,
+    }
+    out.flush()
+}
+
+fn neuron_type(network: &Network, i: usize) -> &'static str {
+    if i < network.excitatory {
+        "excitatory"
+    } else {
+        "inhibitory"
+    }
+}
+
+/// connections in the graph as (pre, post, weight), skipping those weaker than `threshold`
+fn edges(network: &Network, threshold: f32) -> impl Iterator<Item = (usize, usize, f32)> + '_ {
+    network
+        .connections
+        .indexed_iter()
+        .filter(move |(_, weight)| **weight != 0.0 && weight.abs() >= threshold)
+        .map(|((post, pre), weight)| (pre, post, *weight))
+}
+
+pub fn write_graphml<W: Write>(out: &mut W, network: &Network, threshold: f32) -> io::Result<()> {
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        out,
+        r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns" xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xsi:schemaLocation="http://graphml.graphdrawing.org/xmlns http://graphml.graphdrawing.org/xmlns/1.0/graphml.xsd">"#
+    )?;
+    writeln!(
+        out,
+        r#"  <key id="type" for="node" attr.name="type" attr.type="string"/>"#
+    )?;
+    for param in ["a", "b", "c", "d"] {
+        writeln!(
+            out,
+            r#"  <key id="{0}" for="node" attr.name="{0}" attr.type="float"/>"#,
+            param
+        )?;
+    }
+    if network.positions.is_some() {
+        for axis in ["x", "y", "z"] {
+            writeln!(
+                out,
+                r#"  <key id="{0}" for="node" attr.name="{0}" attr.type="float"/>"#,
+                axis
+            )?;
+        }
+    }
+    writeln!(
+        out,
+        r#"  <key id="weight" for="edge" attr.name="weight" attr.type="float"/>"#
+    )?;
+    if network.delays.is_some() {
+        writeln!(
+            out,
+            r#"  <key id="delay" for="edge" attr.name="delay" attr.type="int"/>"#
+        )?;
+    }
+    writeln!(out, r#"  <graph id="network" edgedefault="directed">"#)?;
+
+    for (i, neuron) in network.neurons.iter().enumerate() {
+        writeln!(out, r#"    <node id="n{}">"#, i)?;
+        writeln!(
+            out,
+            r#"      <data key="type">{}</data>"#,
+            neuron_type(network, i)
+        )?;
+        writeln!(out, r#"      <data key="a">{}</data>"#, neuron.decay_rate)?;
+        writeln!(out, r#"      <data key="b">{}</data>"#, neuron.sensitivity)?;
+        writeln!(out, r#"      <data key="c">{}</data>"#, neuron.v_reset)?;
+        writeln!(out, r#"      <data key="d">{}</data>"#, neuron.u_reset)?;
+        if let Some(positions) = &network.positions {
+            for (axis, value) in ["x", "y", "z"].iter().zip(positions.row(i)) {
+                writeln!(out, r#"      <data key="{}">{}</data>"#, axis, value)?;
+            }
+        }
+        writeln!(out, "    </node>")?;
+    }
+
+    for (pre, post, weight) in edges(network, threshold) {
+        writeln!(out, r#"    <edge source="n{}" target="n{}">"#, pre, post)?;
+        writeln!(out, r#"      <data key="weight">{}</data>"#, weight)?;
+        if let Some(delays) = &network.delays {
+            writeln!(
+                out,
+                r#"      <data key="delay">{}</data>"#,
+                delays[[post, pre]]
+            )?;
+        }
+        writeln!(out, "    </edge>")?;
+    }
+
+    writeln!(out, "  </graph>")?;
+    writeln!(out, "</graphml>")
+}
+
+pub fn write_dot<W: Write>(out: &mut W, network: &Network, threshold: f32) -> io::Result<()> {
+    writeln!(out, "digraph network {{")?;
+
+    for (i, neuron) in network.neurons.iter().enumerate() {
+        let color = if i < network.excitatory {
+            "red"
+        } else {
+            "blue"
+        };
+        write!(
+            out,
+            "  n{} [type={}, a={}, b={}, c={}, d={}, color={}",
+            i,
+            neuron_type(network, i),
+            neuron.decay_rate,
+            neuron.sensitivity,
+            neuron.v_reset,
+            neuron.u_reset,
+            color
+        )?;
+        if let Some(positions) = &network.positions {
+            // neato and fdp use pos, the ! keeps them from moving the neuron
+            let position = positions.row(i);
+            write!(
+                out,
+                ", pos=\"{},{},{}!\"",
+                position[0], position[1], position[2]
+            )?;
+        }
+        writeln!(out, "];")?;
+    }
+
+    for (pre, post, weight) in edges(network, threshold) {
+        // graphviz uses `weight` for layout and rejects negative values
+        write!(out, "  n{} -> n{} [synaptic_weight={}", pre, post, weight)?;
+        if let Some(delays) = &network.delays {
+            write!(out, ", delay={}", delays[[post, pre]])?;
+        }
+        writeln!(out, "];")?;
+    }
+
+    writeln!(out, "}}")
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::prelude::*;
+
+    use super::*;
+    use crate::izhikevich::Izhikevich;
+
+    /// two excitatory neurons and an inhibitory one, the 0.125 weight from 1 to 0 is below the
+    /// 0.2 threshold used in the tests
+    fn network() -> Network {
+        let neuron = |decay_rate, u_reset| Izhikevich {
+            decay_rate,
+            sensitivity: 0.25,
+            v_reset: -65.0,
+            u_reset,
+            v: -65.0,
+            u: -16.25,
+        };
+        Network {
+            excitatory: 2,
+            inhibitory: 1,
+            neurons: array![neuron(0.02, 8.0), neuron(0.02, 8.0), neuron(0.1, 2.0)],
+            connections: array![[0.0, 0.125, -1.0], [0.5, 0.0, 0.0], [0.25, 0.0, 0.0]],
+            delays: Some(array![[1, 4, 1], [3, 1, 1], [2, 1, 1]]),
+            positions: None,
+        }
+    }
+
+    fn export(
+        write: fn(&mut Vec<u8>, &Network, f32) -> io::Result<()>,
+        network: &Network,
+    ) -> String {
+        let mut out = Vec::new();
+        write(&mut out, network, 0.2).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn dot() {
+        let expected = "\
+digraph network {
+  n0 [type=excitatory, a=0.02, b=0.25, c=-65, d=8, color=red];
+  n1 [type=excitatory, a=0.02, b=0.25, c=-65, d=8, color=red];
+  n2 [type=inhibitory, a=0.1, b=0.25, c=-65, d=2, color=blue];
+  n2 -> n0 [synaptic_weight=-1, delay=1];
+  n0 -> n1 [synaptic_weight=0.5, delay=3];
+  n0 -> n2 [synaptic_weight=0.25, delay=2];
+}
+";
+        assert_eq!(export(write_dot, &network()), expected);
+    }
+
+    #[test]
+    fn dot_positions_without_delays() {
+        let mut network = network();
+        network.delays = None;
+        network.positions = Some(array![[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.5, 1.5, 0.0]]);
+        let dot = export(write_dot, &network);
+
+        assert!(dot.contains(
+            "  n2 [type=inhibitory, a=0.1, b=0.25, c=-65, d=2, color=blue, pos=\"0.5,1.5,0!\"];\n"
+        ));
+        assert!(dot.contains("  n2 -> n0 [synaptic_weight=-1];\n"));
+        assert!(!dot.contains("delay"));
+    }
+
+    #[test]
+    fn graphml() {
+        let graphml = export(write_graphml, &network());
+
+        assert!(graphml.starts_with("<?xml"));
+        assert!(graphml.ends_with("  </graph>\n</graphml>\n"));
+        assert!(
+            graphml.contains(r#"  <key id="delay" for="edge" attr.name="delay" attr.type="int"/>"#)
+        );
+        assert!(!graphml.contains(r#"<key id="x""#));
+        assert!(graphml.contains(
+            r#"    <node id="n2">
+      <data key="type">inhibitory</data>
+      <data key="a">0.1</data>
+      <data key="b">0.25</data>
+      <data key="c">-65</data>
+      <data key="d">2</data>
+    </node>
+"#
+        ));
+
+        let edges = &graphml[graphml.find("    <edge").unwrap()..];
+        let expected = r#"    <edge source="n2" target="n0">
+      <data key="weight">-1</data>
+      <data key="delay">1</data>
+    </edge>
+    <edge source="n0" target="n1">
+      <data key="weight">0.5</data>
+      <data key="delay">3</data>
+    </edge>
+    <edge source="n0" target="n2">
+      <data key="weight">0.25</data>
+      <data key="delay">2</data>
+    </edge>
+  </graph>
+"#;
+        assert!(edges.starts_with(expected), "{}", edges);
+    }
+
+    #[test]
+    fn format_from_extension() {
+        assert_eq!(
+            GraphFormat::from_path(Path::new("net.graphml")),
+            Some(GraphFormat::GraphMl)
+        );
+        assert_eq!(
+            GraphFormat::from_path(Path::new("net.gv")),
+            Some(GraphFormat::Dot)
+        );
+        assert_eq!(GraphFormat::from_path(Path::new("net.csv")), None);
+    }
+}
@@ -0,0 +1,662 @@
+use std::convert::TryInto;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use ndarray::prelude::*;
+use rand::Rng;
+
+use super::weights_from_adjacency;
+use crate::izhikevich;
+use crate::network::Network;
+
+/// File formats connectivity can be read from and written to
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Format {
+    /// `pre,post,weight` rows with an optional fourth `delay` column in ms, neurons are
+    /// numbered from 0
+    EdgeList,
+    /// Matrix Market sparse or dense matrix with a row for each postsynaptic neuron and a column
+    /// for each presynaptic neuron, like the connection matrix
+    MatrixMarket,
+    /// NumPy `.npy` array with the same layout as the connection matrix
+    Npy,
+}
+
+impl Format {
+    /// Picks the format from the file extension, `.csv`, `.mtx` or `.npy`
+    pub fn from_path(path: &Path) -> Result<Self, FormatError> {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("csv") => Ok(Format::EdgeList),
+            Some("mtx") => Ok(Format::MatrixMarket),
+            Some("npy") => Ok(Format::Npy),
+            _ => Err(FormatError::UnknownFormat(path.display().to_string())),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum FormatError {
+    Io(io::Error),
+    UnknownFormat(String),
+    /// malformed contents, with the line it's on for text formats
+    Parse {
+        line: Option<usize>,
+        message: String,
+    },
+    /// the connectivity doesn't match the number of neurons in the network
+    Dimensions {
+        expected: usize,
+        found: String,
+    },
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FormatError::Io(err) => write!(f, "{}", err),
+            FormatError::UnknownFormat(path) => write!(
+                f,
+                "can't tell the format of {}, expected a .csv, .mtx or .npy extension",
+                path
+            ),
+            FormatError::Parse {
+                line: Some(line),
+                message,
+            } => write!(f, "line {}: {}", line, message),
+            FormatError::Parse {
+                line: None,
+                message,
+            } => write!(f, "{}", message),
+            FormatError::Dimensions { expected, found } => write!(
+                f,
+                "connectivity is for {} but the network has {} neurons",
+                found, expected
+            ),
+        }
+    }
+}
+
+impl From<io::Error> for FormatError {
+    fn from(err: io::Error) -> Self {
+        FormatError::Io(err)
+    }
+}
+
+fn parse_error(line: Option<usize>, message: impl Into<String>) -> FormatError {
+    FormatError::Parse {
+        line,
+        message: message.into(),
+    }
+}
+
+/// Creates a network with the neurons from Izhikevich (2003) and the connectivity in the file,
+/// which has to be for exactly `excitatory + inhibitory` neurons
+pub fn load_network<R: Rng>(
+    path: &Path,
+    excitatory: usize,
+    inhibitory: usize,
+    rng: &mut R,
+) -> Result<Network, FormatError> {
+    let total = excitatory + inhibitory;
+    let reader = BufReader::new(File::open(path)?);
+
+    let format = Format::from_path(path)?;
+    let (connections, delays) = match format {
+        Format::EdgeList => read_edge_list(reader, total)?,
+        Format::MatrixMarket => (read_matrix_market(reader, total, excitatory, rng)?, None),
+        Format::Npy => (read_npy(reader, total)?, None),
+    };
+    if format != Format::EdgeList {
+        log::warn!(
+            "{} has no delays, every connection takes a single step",
+            path.display()
+        );
+    }
+    log::warn!(
+        "only the connectivity is loaded from {}, the neurons' parameters are randomized afresh",
+        path.display()
+    );
+
+    Ok(Network {
+        excitatory,
+        inhibitory,
+        neurons: izhikevich::randomized_neurons(excitatory, inhibitory, rng),
+        connections,
+        delays,
+        positions: None,
+    })
+}
+
+/// Writes the network's connectivity in the format picked by the file extension, only edge lists
+/// keep the delays
+pub fn save_network(path: &Path, network: &Network) -> Result<(), FormatError> {
+    let format = Format::from_path(path)?;
+    if network.delays.is_some() && format != Format::EdgeList {
+        log::warn!(
+            "{} can't hold delays, save to a .csv edge list to keep them",
+            path.display()
+        );
+    }
+    let mut out = BufWriter::new(File::create(path)?);
+
+    match format {
+        Format::EdgeList => {
+            write_edge_list(&mut out, &network.connections, network.delays.as_ref())?
+        }
+        Format::MatrixMarket => write_matrix_market(&mut out, &network.connections)?,
+        Format::Npy => write_npy(&mut out, &network.connections)?,
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn check_index(index: usize, total: usize, line: usize) -> Result<usize, FormatError> {
+    if index < total {
+        Ok(index)
+    } else {
+        Err(FormatError::Dimensions {
+            expected: total,
+            found: format!("neuron {} on line {}", index, line),
+        })
+    }
+}
+
+pub fn read_edge_list<R: BufRead>(
+    reader: R,
+    total: usize,
+) -> Result<(Array2<f32>, Option<Array2<u32>>), FormatError> {
+    let mut connections = Array2::<f32>::zeros((total, total));
+    let mut delays: Option<Array2<u32>> = None;
+
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line_number = i + 1;
+        let fields: Vec<&str> = line.split(',').map(|f| f.trim()).collect();
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        // optional header
+        if i == 0 && fields[0].parse::<usize>().is_err() {
+            continue;
+        }
+
+        let index = |field: &str| {
+            field
+                .parse::<usize>()
+                .map_err(|_| parse_error(Some(line_number), format!("invalid neuron {}", field)))
+        };
+        let (pre, post, weight) = match fields.as_slice() {
+            [pre, post, weight] | [pre, post, weight, _] => (
+                check_index(index(pre)?, total, line_number)?,
+                check_index(index(post)?, total, line_number)?,
+                weight.parse::<f32>().map_err(|_| {
+                    parse_error(Some(line_number), format!("invalid weight {}", weight))
+                })?,
+            ),
+            _ => {
+                return Err(parse_error(
+                    Some(line_number),
+                    "expected pre,post,weight or pre,post,weight,delay",
+                ))
+            }
+        };
+        connections[[post, pre]] = weight;
+
+        if let Some(delay) = fields.get(3) {
+            let delay = delay
+                .parse::<u32>()
+                .ok()
+                .filter(|d| *d > 0)
+                .ok_or_else(|| {
+                    parse_error(Some(line_number), format!("invalid delay {}", delay))
+                })?;
+            delays.get_or_insert_with(|| Array2::<u32>::ones((total, total)))[[post, pre]] = delay;
+        }
+    }
+
+    Ok((connections, delays))
+}
+
+pub fn write_edge_list<W: Write>(
+    out: &mut W,
+    connections: &Array2<f32>,
+    delays: Option<&Array2<u32>>,
+) -> io::Result<()> {
+    match delays {
+        Some(_) => writeln!(out, "pre,post,weight,delay")?,
+        None => writeln!(out, "pre,post,weight")?,
+    }
+    for ((post, pre), weight) in connections.indexed_iter() {
+        if *weight == 0.0 {
+            continue;
+        }
+        match delays {
+            Some(delays) => writeln!(out, "{},{},{},{}", pre, post, weight, delays[[post, pre]])?,
+            None => writeln!(out, "{},{},{}", pre, post, weight)?,
+        }
+    }
+    Ok(())
+}
+
+/// Reads `coordinate` and `array` matrices with `real`, `integer` or `pattern` values and
+/// `general` or `symmetric` structure. Pattern matrices only say which connections exist so
+/// their weights are randomized like the generated networks.
+pub fn read_matrix_market<B: BufRead, R: Rng>(
+    reader: B,
+    total: usize,
+    excitatory: usize,
+    rng: &mut R,
+) -> Result<Array2<f32>, FormatError> {
+    let mut lines = reader.lines().enumerate();
+
+    let banner = match lines.next() {
+        Some((_, line)) => line?.to_lowercase(),
+        None => return Err(parse_error(Some(1), "empty file")),
+    };
+    let banner: Vec<&str> = banner.split_whitespace().collect();
+    let (coordinate, field, symmetric) = match banner.as_slice() {
+        ["%%matrixmarket", "matrix", format, field, symmetry] => (
+            match *format {
+                "coordinate" => true,
+                "array" => false,
+                _ => {
+                    return Err(parse_error(
+                        Some(1),
+                        format!("unsupported format {}", format),
+                    ))
+                }
+            },
+            match *field {
+                "real" | "integer" | "pattern" => *field,
+                _ => return Err(parse_error(Some(1), format!("unsupported field {}", field))),
+            },
+            match *symmetry {
+                "general" => false,
+                "symmetric" => true,
+                _ => {
+                    return Err(parse_error(
+                        Some(1),
+                        format!("unsupported symmetry {}", symmetry),
+                    ))
+                }
+            },
+        ),
+        _ => return Err(parse_error(Some(1), "missing %%MatrixMarket matrix banner")),
+    };
+    if field == "pattern" && !coordinate {
+        return Err(parse_error(
+            Some(1),
+            "pattern matrices have to be coordinate",
+        ));
+    }
+
+    // skip comments, the first line left is the size
+    let mut data = lines.filter_map(|(i, line)| match line {
+        Ok(line) if line.starts_with('%') || line.trim().is_empty() => None,
+        Ok(line) => Some(Ok((i + 1, line))),
+        Err(err) => Some(Err(err)),
+    });
+
+    let (size_line, size) = data
+        .next()
+        .ok_or_else(|| parse_error(None, "missing matrix size"))??;
+    let size: Vec<usize> = size
+        .split_whitespace()
+        .map(|v| v.parse::<usize>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| parse_error(Some(size_line), "invalid matrix size"))?;
+    let (rows, columns) = match size.as_slice() {
+        [rows, columns, _entries] if coordinate => (*rows, *columns),
+        [rows, columns] if !coordinate => (*rows, *columns),
+        _ => return Err(parse_error(Some(size_line), "invalid matrix size")),
+    };
+    if rows != total || columns != total {
+        return Err(FormatError::Dimensions {
+            expected: total,
+            found: format!("a {}x{} matrix", rows, columns),
+        });
+    }
+
+    let mut connections = Array2::<f32>::zeros((total, total));
+    let mut adjacency = Array2::<bool>::default((total, total));
+
+    if coordinate {
+        for entry in data {
+            let (line_number, line) = entry?;
+            let values: Vec<&str> = line.split_whitespace().collect();
+            let invalid = || parse_error(Some(line_number), "invalid entry");
+            let index = |v: &str| {
+                v.parse::<usize>()
+                    .ok()
+                    .filter(|i| *i >= 1 && *i <= total)
+                    .map(|i| i - 1)
+                    .ok_or_else(invalid)
+            };
+            let (row, column, value) = match (field, values.as_slice()) {
+                ("pattern", [row, column]) => (index(row)?, index(column)?, 1.0),
+                (_, [row, column, value]) => (
+                    index(row)?,
+                    index(column)?,
+                    value.parse::<f32>().map_err(|_| invalid())?,
+                ),
+                _ => return Err(invalid()),
+            };
+            connections[[row, column]] = value;
+            adjacency[[row, column]] = true;
+            if symmetric {
+                connections[[column, row]] = value;
+                adjacency[[column, row]] = true;
+            }
+        }
+    } else {
+        // dense arrays are stored column by column, symmetric ones only the lower triangle
+        let mut positions = (0..columns)
+            .flat_map(|column| (0..rows).map(move |row| (row, column)))
+            .filter(|(row, column)| !symmetric || row >= column);
+        for entry in data {
+            let (line_number, line) = entry?;
+            let (row, column) = positions
+                .next()
+                .ok_or_else(|| parse_error(Some(line_number), "too many entries"))?;
+            let value = line
+                .trim()
+                .parse::<f32>()
+                .map_err(|_| parse_error(Some(line_number), "invalid entry"))?;
+            connections[[row, column]] = value;
+            if symmetric {
+                connections[[column, row]] = value;
+            }
+        }
+        if positions.next().is_some() {
+            return Err(parse_error(None, "too few entries"));
+        }
+    }
+
+    if field == "pattern" {
+        connections = weights_from_adjacency(&adjacency, excitatory, rng);
+    }
+
+    Ok(connections)
+}
+
+pub fn write_matrix_market<W: Write>(out: &mut W, connections: &Array2<f32>) -> io::Result<()> {
+    let entries = connections.iter().filter(|w| **w != 0.0).count();
+
+    writeln!(out, "%%MatrixMarket matrix coordinate real general")?;
+    writeln!(
+        out,
+        "% rows are postsynaptic neurons and columns presynaptic neurons"
+    )?;
+    writeln!(
+        out,
+        "{} {} {}",
+        connections.nrows(),
+        connections.ncols(),
+        entries
+    )?;
+    for ((row, column), weight) in connections.indexed_iter() {
+        if *weight != 0.0 {
+            writeln!(out, "{} {} {}", row + 1, column + 1, weight)?;
+        }
+    }
+    Ok(())
+}
+
+const NPY_MAGIC: &[u8] = b"\x93NUMPY";
+
+/// Reads 2D little endian `f4` or `f8` arrays in either C or Fortran order
+pub fn read_npy<R: Read>(mut reader: R, total: usize) -> Result<Array2<f32>, FormatError> {
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic[..6] != NPY_MAGIC {
+        return Err(parse_error(None, "not a .npy file"));
+    }
+
+    let header_len = match magic[6] {
+        1 => {
+            let mut len = [0u8; 2];
+            reader.read_exact(&mut len)?;
+            u16::from_le_bytes(len) as usize
+        }
+        2 | 3 => {
+            let mut len = [0u8; 4];
+            reader.read_exact(&mut len)?;
+            u32::from_le_bytes(len) as usize
+        }
+        version => {
+            return Err(parse_error(
+                None,
+                format!("unsupported .npy version {}", version),
+            ))
+        }
+    };
+    let mut header = vec![0u8; header_len];
+    reader.read_exact(&mut header)?;
+    let header = String::from_utf8_lossy(&header);
+
+    let descr = npy_header_value(&header, "descr")
+        .ok_or_else(|| parse_error(None, "missing descr in .npy header"))?;
+    let fortran_order = npy_header_value(&header, "fortran_order")
+        .ok_or_else(|| parse_error(None, "missing fortran_order in .npy header"))?
+        .starts_with("True");
+    let shape: Vec<usize> = npy_header_value(&header, "shape")
+        .ok_or_else(|| parse_error(None, "missing shape in .npy header"))?
+        .trim_matches(|c| c == '(' || c == ')')
+        .split(',')
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+        .map(|v| v.parse::<usize>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| parse_error(None, "invalid shape in .npy header"))?;
+
+    let (rows, columns) = match shape.as_slice() {
+        [rows, columns] => (*rows, *columns),
+        _ => {
+            return Err(FormatError::Dimensions {
+                expected: total,
+                found: format!("an array of shape {:?}", shape),
+            })
+        }
+    };
+    if rows != total || columns != total {
+        return Err(FormatError::Dimensions {
+            expected: total,
+            found: format!("a {}x{} array", rows, columns),
+        });
+    }
+
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    let values: Vec<f32> = match descr.trim_matches('\'') {
+        "<f4" => data
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+            .collect(),
+        "<f8" => data
+            .chunks_exact(8)
+            .map(|b| f64::from_le_bytes(b.try_into().unwrap()) as f32)
+            .collect(),
+        other => {
+            return Err(parse_error(
+                None,
+                format!("unsupported dtype {}, expected <f4 or <f8", other),
+            ))
+        }
+    };
+    if values.len() != rows * columns {
+        return Err(parse_error(None, "array data is shorter than its shape"));
+    }
+
+    let connections = if fortran_order {
+        Array2::from_shape_vec((columns, rows), values).map(|a| a.reversed_axes().to_owned())
+    } else {
+        Array2::from_shape_vec((rows, columns), values)
+    };
+    connections.map_err(|err| parse_error(None, err.to_string()))
+}
+
+/// Value of `key` in the python dict literal of a .npy header
+fn npy_header_value<'a>(header: &'a str, key: &str) -> Option<&'a str> {
+    let start = header.find(&format!("'{}'", key))? + key.len() + 2;
+    let rest = header[start..].trim_start().strip_prefix(':')?.trim_start();
+    let end = if rest.starts_with('(') {
+        rest.find(')')? + 1
+    } else {
+        rest.find(',')?
+    };
+    Some(&rest[..end])
+}
+
+/// Writes a version 1.0 `.npy` file of little endian `f4`
+pub fn write_npy<W: Write>(out: &mut W, connections: &Array2<f32>) -> io::Result<()> {
+    let mut header = format!(
+        "{{'descr': '<f4', 'fortran_order': False, 'shape': ({}, {}), }}",
+        connections.nrows(),
+        connections.ncols()
+    );
+    // the magic, version and length take 10 bytes and the data has to start on a multiple of 64
+    let padding = 64 - (10 + header.len() + 1) % 64;
+    header.push_str(&" ".repeat(padding % 64));
+    header.push('\n');
+
+    out.write_all(NPY_MAGIC)?;
+    out.write_all(&[1, 0])?;
+    out.write_all(&(header.len() as u16).to_le_bytes())?;
+    out.write_all(header.as_bytes())?;
+    for weight in connections.iter() {
+        out.write_all(&weight.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn connections() -> Array2<f32> {
+        array![
+            [0.0, 0.5, 0.0, -1.25],
+            [0.75, 0.0, 0.0, 0.0],
+            [0.0, 0.125, 0.0, -0.5],
+            [0.25, 0.0, 1.5, 0.0],
+        ]
+    }
+
+    /// .npy bytes with the given header dict and data
+    fn npy(header: &str, data: &[u8]) -> Vec<u8> {
+        let mut bytes = NPY_MAGIC.to_vec();
+        bytes.extend_from_slice(&[1, 0]);
+        bytes.extend_from_slice(&(header.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(header.as_bytes());
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    #[test]
+    fn edge_list_round_trip() {
+        let delays = connections().mapv(|w| if w < 0.0 { 1 } else { 3 });
+        let mut out = Vec::new();
+        write_edge_list(&mut out, &connections(), Some(&delays)).unwrap();
+
+        let (read, read_delays) = read_edge_list(out.as_slice(), 4).unwrap();
+        assert_eq!(read, connections());
+        let read_delays = read_delays.unwrap();
+        for ((index, weight), delay) in connections().indexed_iter().zip(read_delays.iter()) {
+            if *weight != 0.0 {
+                assert_eq!(*delay, delays[index]);
+            }
+        }
+    }
+
+    #[test]
+    fn edge_list_without_delays() {
+        let mut out = Vec::new();
+        write_edge_list(&mut out, &connections(), None).unwrap();
+
+        let (read, delays) = read_edge_list(out.as_slice(), 4).unwrap();
+        assert_eq!(read, connections());
+        assert!(delays.is_none());
+    }
+
+    #[test]
+    fn matrix_market_round_trip() {
+        let mut out = Vec::new();
+        write_matrix_market(&mut out, &connections()).unwrap();
+
+        let read = read_matrix_market(out.as_slice(), 4, 3, &mut rand::thread_rng()).unwrap();
+        assert_eq!(read, connections());
+    }
+
+    #[test]
+    fn symmetric_matrix_market() {
+        let coordinate = "%%MatrixMarket matrix coordinate real symmetric\n\
+                          % lower triangle\n\
+                          3 3 2\n\
+                          2 1 0.5\n\
+                          3 2 -1\n";
+        let array = "%%MatrixMarket matrix array real symmetric\n\
+                     3 3\n0\n0.5\n0\n0\n-1\n0\n";
+        let expected = array![[0.0, 0.5, 0.0], [0.5, 0.0, -1.0], [0.0, -1.0, 0.0]];
+
+        for input in &[coordinate, array] {
+            let read = read_matrix_market(input.as_bytes(), 3, 2, &mut rand::thread_rng()).unwrap();
+            assert_eq!(read, expected);
+        }
+    }
+
+    #[test]
+    fn npy_round_trip() {
+        let mut out = Vec::new();
+        write_npy(&mut out, &connections()).unwrap();
+        // the data has to start on a multiple of 64 bytes
+        assert_eq!((out.len() - 16 * 4) % 64, 0);
+
+        let read = read_npy(out.as_slice(), 4).unwrap();
+        assert_eq!(read, connections());
+    }
+
+    #[test]
+    fn fortran_order_npy() {
+        let data: Vec<u8> = connections()
+            .t()
+            .iter()
+            .flat_map(|w| w.to_le_bytes().to_vec())
+            .collect();
+        let bytes = npy(
+            "{'descr': '<f4', 'fortran_order': True, 'shape': (4, 4), }\n",
+            &data,
+        );
+
+        let read = read_npy(bytes.as_slice(), 4).unwrap();
+        assert_eq!(read, connections());
+    }
+
+    #[test]
+    fn double_precision_npy() {
+        let data: Vec<u8> = connections()
+            .iter()
+            .flat_map(|w| (*w as f64).to_le_bytes().to_vec())
+            .collect();
+        let bytes = npy(
+            "{'descr': '<f8', 'fortran_order': False, 'shape': (4, 4), }\n",
+            &data,
+        );
+
+        let read = read_npy(bytes.as_slice(), 4).unwrap();
+        assert_eq!(read, connections());
+    }
+
+    #[test]
+    fn wrong_dimensions() {
+        let mut out = Vec::new();
+        write_npy(&mut out, &connections()).unwrap();
+
+        assert!(matches!(
+            read_npy(out.as_slice(), 5),
+            Err(FormatError::Dimensions { expected: 5, .. })
+        ));
+    }
+}
@@ -0,0 +1,368 @@
+use std::fmt;
+use std::str::FromStr;
+
+use ndarray::prelude::*;
+use rand::prelude::*;
+use rand::seq::index;
+
+use super::izhikevich;
+use super::network::Network;
+
+pub mod export;
+pub mod formats;
+
+/// Graph models for generating which neurons are connected
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Generator {
+    /// every connection exists independently with probability `p`
+    ErdosRenyi { p: f64 },
+    /// every neuron receives connections from exactly `k` others
+    FixedInDegree { k: usize },
+    /// every neuron connects to exactly `k` others
+    FixedOutDegree { k: usize },
+    /// ring lattice where every neuron is connected to its `k` nearest neighbors, with each
+    /// connection rewired to a random neuron with probability `beta`
+    WattsStrogatz { k: usize, beta: f64 },
+    /// preferential attachment where every new neuron connects to `m` existing ones
+    BarabasiAlbert { m: usize },
+    /// neurons split into `blocks` groups, connected with probability `p_in` within a group and
+    /// `p_out` between groups
+    StochasticBlock {
+        blocks: usize,
+        p_in: f64,
+        p_out: f64,
+    },
+}
+
+impl Generator {
+    /// Creates a network with the neurons from Izhikevich (2003) connected according to the
+    /// graph model, with weights following `izhikevich::randomized_connections`
+    pub fn build<R: Rng>(&self, excitatory: usize, inhibitory: usize, rng: &mut R) -> Network {
+        let total = excitatory + inhibitory;
+        let adjacency = self.adjacency(total, rng);
+
+        Network {
+            excitatory,
+            inhibitory,
+            neurons: izhikevich::randomized_neurons(excitatory, inhibitory, rng),
+            connections: weights_from_adjacency(&adjacency, excitatory, rng),
+            delays: None,
+            positions: None,
+        }
+    }
+
+    /// Which connections exist, with a row for each postsynaptic neuron and a column for each
+    /// presynaptic neuron like the connection matrix
+    pub fn adjacency<R: Rng>(&self, total: usize, rng: &mut R) -> Array2<bool> {
+        let mut adjacency = Array2::<bool>::default((total, total));
+
+        match *self {
+            Generator::ErdosRenyi { p } => {
+                for ((post, pre), connected) in adjacency.indexed_iter_mut() {
+                    *connected = post != pre && rng.gen_bool(p);
+                }
+            }
+            Generator::FixedInDegree { k } => {
+                for (post, mut row) in adjacency.axis_iter_mut(Axis(0)).enumerate() {
+                    for pre in sample_others(rng, total, post, k) {
+                        row[pre] = true;
+                    }
+                }
+            }
+            Generator::FixedOutDegree { k } => {
+                for (pre, mut column) in adjacency.axis_iter_mut(Axis(1)).enumerate() {
+                    for post in sample_others(rng, total, pre, k) {
+                        column[post] = true;
+                    }
+                }
+            }
+            Generator::WattsStrogatz { k, beta } => {
+                // neurons are shuffled around the ring so the inhibitory ones, which all come
+                // last, aren't clustered together
+                let ring = shuffled(rng, total);
+                for i in 0..total {
+                    for offset in 1..=(k / 2) {
+                        let mut j = (i + offset) % total;
+                        if j == i {
+                            // k is at least the number of neurons so the ring wrapped around
+                            continue;
+                        }
+                        if rng.gen_bool(beta) {
+                            let candidate = rng.gen_range(0..total);
+                            if candidate != i && !adjacency[[ring[i], ring[candidate]]] {
+                                j = candidate;
+                            }
+                        }
+                        adjacency[[ring[i], ring[j]]] = true;
+                        adjacency[[ring[j], ring[i]]] = true;
+                    }
+                }
+            }
+            Generator::BarabasiAlbert { m } => {
+                // shuffled for the same reason as the ring in Watts-Strogatz, otherwise the
+                // inhibitory neurons would always be the youngest with the lowest degrees
+                let order = shuffled(rng, total);
+                let seed = (m + 1).min(total);
+                // every neuron appears once per connection so sampling from this is
+                // proportional to degree
+                let mut endpoints: Vec<usize> = Vec::new();
+
+                for a in 0..seed {
+                    for b in (a + 1)..seed {
+                        adjacency[[order[a], order[b]]] = true;
+                        adjacency[[order[b], order[a]]] = true;
+                        endpoints.push(a);
+                        endpoints.push(b);
+                    }
+                }
+
+                for new in seed..total {
+                    let mut targets: Vec<usize> = Vec::with_capacity(m);
+                    while targets.len() < m.min(new) {
+                        let target = *endpoints.choose(rng).unwrap_or(&0);
+                        if !targets.contains(&target) {
+                            targets.push(target);
+                        }
+                    }
+                    for target in targets {
+                        adjacency[[order[new], order[target]]] = true;
+                        adjacency[[order[target], order[new]]] = true;
+                        endpoints.push(new);
+                        endpoints.push(target);
+                    }
+                }
+            }
+            Generator::StochasticBlock {
+                blocks,
+                p_in,
+                p_out,
+            } => {
+                // blocks are interleaved so each has its share of inhibitory neurons
+                for ((post, pre), connected) in adjacency.indexed_iter_mut() {
+                    let p = if post % blocks == pre % blocks {
+                        p_in
+                    } else {
+                        p_out
+                    };
+                    *connected = post != pre && rng.gen_bool(p);
+                }
+            }
+        }
+
+        adjacency
+    }
+}
+
+/// Random weights for the existing connections using the same distributions and sign convention
+/// as `izhikevich::randomized_connections`, connections from the first `excitatory` columns are
+/// positive and the rest negative
+pub fn weights_from_adjacency<R: Rng>(
+    adjacency: &Array2<bool>,
+    excitatory: usize,
+    rng: &mut R,
+) -> Array2<f32> {
+    let mut connections = Array2::<f32>::zeros(adjacency.raw_dim());
+    for (((_y, x), v), &connected) in connections.indexed_iter_mut().zip(adjacency) {
+        if !connected {
+            continue;
+        }
+        let noise: f32 = rng.gen();
+        *v = if x < excitatory { 0.5 * noise } else { -noise };
+    }
+
+    connections
+}
+
+/// `k` distinct neurons out of `total` excluding `exclude`
+fn sample_others<R: Rng>(rng: &mut R, total: usize, exclude: usize, k: usize) -> Vec<usize> {
+    // there's no one else to sample when the network is empty or just `exclude`
+    let others = total.saturating_sub(1);
+    index::sample(rng, others, k.min(others))
+        .into_iter()
+        .map(|i| if i >= exclude { i + 1 } else { i })
+        .collect()
+}
+
+fn shuffled<R: Rng>(rng: &mut R, total: usize) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..total).collect();
+    order.shuffle(rng);
+    order
+}
+
+impl FromStr for Generator {
+    type Err = ParseGeneratorError;
+
+    /// Parses a model name followed by its parameters separated by colons, i.e. `er:<p>`,
+    /// `in-degree:<k>`, `out-degree:<k>`, `ws:<k>:<beta>`, `ba:<m>` or
+    /// `sbm:<blocks>:<p in>:<p out>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseGeneratorError(s.to_string());
+        let lower = s.to_lowercase();
+        let mut parts = lower.split(':');
+        let name = parts.next().unwrap_or_default();
+        let params: Vec<&str> = parts.collect();
+
+        let probability = |s: &str| s.parse::<f64>().ok().filter(|p| (0.0..=1.0).contains(p));
+        let count = |s: &str| s.parse::<usize>().ok().filter(|n| *n > 0);
+
+        let parse = || {
+            Some(match (name, params.as_slice()) {
+                ("er" | "erdos-renyi", &[p]) => Generator::ErdosRenyi { p: probability(p)? },
+                ("in-degree", &[k]) => Generator::FixedInDegree { k: count(k)? },
+                ("out-degree", &[k]) => Generator::FixedOutDegree { k: count(k)? },
+                ("ws" | "watts-strogatz", &[k, beta]) => Generator::WattsStrogatz {
+                    k: count(k)?,
+                    beta: probability(beta)?,
+                },
+                ("ba" | "barabasi-albert", &[m]) => Generator::BarabasiAlbert { m: count(m)? },
+                ("sbm", &[blocks, p_in, p_out]) => Generator::StochasticBlock {
+                    blocks: count(blocks)?,
+                    p_in: probability(p_in)?,
+                    p_out: probability(p_out)?,
+                },
+                _ => return None,
+            })
+        };
+        parse().ok_or_else(err)
+    }
+}
+
+#[derive(Debug)]
+pub struct ParseGeneratorError(String);
+
+impl fmt::Display for ParseGeneratorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "invalid generator \"{}\", expected one of er:<p>, in-degree:<k>, out-degree:<k>, \
+            ws:<k>:<beta>, ba:<m> or sbm:<blocks>:<p in>:<p out>",
+            self.0
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOTAL: usize = 50;
+
+    fn generators() -> [Generator; 6] {
+        [
+            Generator::ErdosRenyi { p: 0.3 },
+            Generator::FixedInDegree { k: 7 },
+            Generator::FixedOutDegree { k: 7 },
+            Generator::WattsStrogatz { k: 6, beta: 0.3 },
+            Generator::BarabasiAlbert { m: 3 },
+            Generator::StochasticBlock {
+                blocks: 4,
+                p_in: 0.8,
+                p_out: 0.1,
+            },
+        ]
+    }
+
+    fn degrees(adjacency: &Array2<bool>, axis: Axis) -> Vec<usize> {
+        adjacency
+            .axis_iter(axis)
+            .map(|lane| lane.iter().filter(|&&connected| connected).count())
+            .collect()
+    }
+
+    #[test]
+    fn fixed_degrees_are_exact() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let adjacency = Generator::FixedInDegree { k: 7 }.adjacency(TOTAL, &mut rng);
+        assert!(degrees(&adjacency, Axis(0)).iter().all(|&k| k == 7));
+
+        let adjacency = Generator::FixedOutDegree { k: 7 }.adjacency(TOTAL, &mut rng);
+        assert!(degrees(&adjacency, Axis(1)).iter().all(|&k| k == 7));
+
+        // capped at everyone else
+        let adjacency = Generator::FixedInDegree { k: 10 }.adjacency(5, &mut rng);
+        assert!(degrees(&adjacency, Axis(0)).iter().all(|&k| k == 4));
+    }
+
+    #[test]
+    fn no_self_connections() {
+        let mut rng = StdRng::seed_from_u64(2);
+        for generator in generators() {
+            let adjacency = generator.adjacency(TOTAL, &mut rng);
+            assert!(
+                adjacency.diag().iter().all(|&connected| !connected),
+                "{:?}",
+                generator
+            );
+        }
+    }
+
+    #[test]
+    fn small_world_is_undirected() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let adjacency = Generator::WattsStrogatz { k: 6, beta: 0.3 }.adjacency(TOTAL, &mut rng);
+        assert_eq!(adjacency, adjacency.t());
+
+        // without rewiring it's the ring lattice
+        let lattice = Generator::WattsStrogatz { k: 6, beta: 0.0 }.adjacency(TOTAL, &mut rng);
+        assert!(degrees(&lattice, Axis(0)).iter().all(|&k| k == 6));
+    }
+
+    #[test]
+    fn preferential_attachment_adds_m_edges_per_neuron() {
+        let mut rng = StdRng::seed_from_u64(4);
+        for m in 1..5 {
+            let adjacency = Generator::BarabasiAlbert { m }.adjacency(TOTAL, &mut rng);
+            assert_eq!(adjacency, adjacency.t());
+            // the fully connected seed of m + 1 neurons then m edges for each later one
+            let edges = m * (m + 1) / 2 + (TOTAL - m - 1) * m;
+            assert_eq!(
+                degrees(&adjacency, Axis(0)).iter().sum::<usize>(),
+                2 * edges
+            );
+            assert!(degrees(&adjacency, Axis(0)).iter().all(|&k| k >= m));
+        }
+    }
+
+    #[test]
+    fn empty_and_single_networks() {
+        let mut rng = StdRng::seed_from_u64(5);
+        for generator in generators() {
+            assert_eq!(generator.adjacency(0, &mut rng).len(), 0);
+            assert_eq!(generator.adjacency(1, &mut rng), array![[false]]);
+        }
+        assert!(sample_others(&mut rng, 0, 0, 3).is_empty());
+    }
+
+    #[test]
+    fn weights_follow_the_sign_convention() {
+        let mut rng = StdRng::seed_from_u64(6);
+        let adjacency = Generator::ErdosRenyi { p: 0.5 }.adjacency(TOTAL, &mut rng);
+        let excitatory = 40;
+        let connections = weights_from_adjacency(&adjacency, excitatory, &mut rng);
+
+        for ((post, pre), &weight) in connections.indexed_iter() {
+            if !adjacency[[post, pre]] {
+                assert_eq!(weight, 0.0);
+            } else if pre < excitatory {
+                assert!((0.0..=0.5).contains(&weight));
+            } else {
+                assert!((-1.0..=0.0).contains(&weight));
+            }
+        }
+    }
+
+    #[test]
+    fn parses_generators() {
+        assert_eq!(
+            "er:0.1".parse::<Generator>().unwrap(),
+            Generator::ErdosRenyi { p: 0.1 }
+        );
+        assert_eq!(
+            "WS:4:0.5".parse::<Generator>().unwrap(),
+            Generator::WattsStrogatz { k: 4, beta: 0.5 }
+        );
+        assert!("er:1.5".parse::<Generator>().is_err());
+        assert!("ba:0".parse::<Generator>().is_err());
+        assert!("sbm:2:0.5".parse::<Generator>().is_err());
+    }
+}
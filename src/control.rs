@@ -0,0 +1,323 @@
+use std::fmt;
+
+use ndarray::prelude::*;
+use tokio::sync::mpsc;
+use tokio::time::{self, Duration, Interval, MissedTickBehavior};
+
+use super::analysis::pattern::FiringClass;
+use super::izhikevich::Izhikevich;
+use super::network::{Network, Population};
+
+/// How fast the backends step through simulated time
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Pace {
+    /// 1ms of simulated time per ms
+    RealTime,
+    /// 10ms of simulated time per ms
+    Fast,
+    /// as fast as the backend can go
+    Max,
+}
+
+impl Pace {
+    fn interval(self) -> Option<Duration> {
+        match self {
+            Pace::RealTime => Some(Duration::from_millis(1)),
+            Pace::Fast => Some(Duration::from_micros(100)),
+            Pace::Max => None,
+        }
+    }
+}
+
+impl fmt::Display for Pace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Pace::RealTime => "1x",
+            Pace::Fast => "10x",
+            Pace::Max => "max",
+        })
+    }
+}
+
+/// Messages from the UI to the running backend, applied between steps
+#[derive(Debug, Clone, PartialEq)]
+pub enum Control {
+    Pause,
+    Resume,
+    /// run a single step while paused
+    Step,
+    Pace(Pace),
+    /// start again from the network the run started with
+    Reset,
+    /// start again from a newly generated network
+    Reseed,
+    Tweak(Tweak),
+    /// report the v and u of these neurons every step
+    Probe(Vec<usize>),
+}
+
+/// A change to the parameters of the running network
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Tweak {
+    /// multiplies the thalamic noise amplitudes from the paper, 5 for excitatory and 2 for
+    /// inhibitory neurons
+    Noise(f32),
+    /// multiplies every weight out of a population
+    ScaleWeights(Population, f32),
+    /// sets a, b, c and d of every neuron in a population to a cortical class's, keeping their
+    /// v and u
+    Preset(Population, FiringClass),
+}
+
+impl Tweak {
+    /// Applies the tweak to a backend's noise scale, neurons and connections
+    pub fn apply(
+        self,
+        excitatory: usize,
+        noise: &mut f32,
+        neurons: &mut Array1<Izhikevich>,
+        connections: &mut Array2<f32>,
+    ) {
+        let total = neurons.len();
+        match self {
+            Tweak::Noise(scale) => *noise = scale,
+            Tweak::ScaleWeights(population, factor) => {
+                // columns are the presynaptic neurons
+                connections
+                    .slice_mut(s![.., population.range(excitatory, total)])
+                    .mapv_inplace(|w| w * factor);
+            }
+            Tweak::Preset(population, class) => {
+                if let Some((a, b, c, d)) = class.parameters() {
+                    for neuron in neurons.slice_mut(s![population.range(excitatory, total)]) {
+                        neuron.decay_rate = a;
+                        neuron.sensitivity = b;
+                        neuron.v_reset = c;
+                        neuron.u_reset = d;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for Tweak {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Tweak::Noise(scale) => write!(f, "thalamic noise scaled to {:.2}x", scale),
+            Tweak::ScaleWeights(population, factor) => {
+                write!(f, "{} weights scaled by {:.2}", population, factor)
+            }
+            Tweak::Preset(population, class) => {
+                write!(f, "{} neurons set to {}", population, class)
+            }
+        }
+    }
+}
+
+/// What a backend does next
+pub enum Next {
+    Step,
+    /// throw away the simulation state and carry on with this network, which has the same
+    /// number of neurons
+    Restart(Box<Network>),
+    /// change the network's parameters before carrying on
+    Tweak(Tweak),
+}
+
+/// Applies the controls sent to a backend and paces its steps
+pub struct Controller {
+    controls: mpsc::UnboundedReceiver<Control>,
+    initial: Network,
+    reseed: Box<dyn FnMut() -> Network + Send>,
+    paused: bool,
+    single_steps: usize,
+    pace: Pace,
+    /// timer for the pace, made on the first step after it's set since that has to happen
+    /// inside the runtime
+    interval: Option<Interval>,
+    probed: Vec<usize>,
+    /// ms between snapshots of the weights for the UI, or none if it doesn't show them
+    weights_interval: Option<u64>,
+    /// time of the next regular snapshot of the weights
+    next_weights: u64,
+    /// whether the weights changed since the last snapshot
+    weights_changed: bool,
+}
+
+impl Controller {
+    /// `reseed` generates a new network like the initial one for `Control::Reseed`, and
+    /// `weights_interval` is how often in ms the weights go in a frame if at all
+    pub fn new(
+        controls: mpsc::UnboundedReceiver<Control>,
+        initial: Network,
+        weights_interval: Option<u64>,
+        reseed: impl FnMut() -> Network + Send + 'static,
+    ) -> Self {
+        Controller {
+            controls,
+            initial,
+            reseed: Box::new(reseed),
+            paused: false,
+            single_steps: 0,
+            pace: Pace::RealTime,
+            interval: None,
+            probed: vec![0],
+            weights_interval: weights_interval.map(|interval| interval.max(1)),
+            next_weights: 0,
+            weights_changed: true,
+        }
+    }
+
+    /// Neurons whose state goes in each step's frame
+    pub fn probed(&self) -> &[usize] {
+        &self.probed
+    }
+
+    /// Whether the step at `time` should send a snapshot of the weights, which it does every
+    /// interval and as soon as they've been reset or scaled
+    pub fn weights_due(&mut self, time: u64) -> bool {
+        match self.weights_interval {
+            Some(interval) => {
+                let due = self.weights_changed || time >= self.next_weights;
+                if time >= self.next_weights {
+                    self.next_weights = (time / interval + 1) * interval;
+                }
+                self.weights_changed = false;
+                due
+            }
+            None => false,
+        }
+    }
+
+    /// Waits until the next step is due, or returns straight away when the network has to be
+    /// restarted or tweaked
+    pub async fn next(&mut self) -> Next {
+        loop {
+            while let Ok(control) = self.controls.try_recv() {
+                if let Some(next) = self.apply(control) {
+                    return next;
+                }
+            }
+            if !self.paused {
+                break;
+            }
+            if self.single_steps > 0 {
+                self.single_steps -= 1;
+                return Next::Step;
+            }
+            match self.controls.recv().await {
+                Some(control) => {
+                    if let Some(next) = self.apply(control) {
+                        return next;
+                    }
+                }
+                // nothing can resume a paused simulation once the UI is gone
+                None => self.paused = false,
+            }
+        }
+
+        match self.pace.interval() {
+            Some(period) => {
+                let interval = self.interval.get_or_insert_with(|| pace_interval(period));
+                interval.tick().await;
+            }
+            // still let the UI and recorders catch up
+            None => tokio::task::yield_now().await,
+        }
+        Next::Step
+    }
+
+    fn apply(&mut self, control: Control) -> Option<Next> {
+        match control {
+            Control::Pause => self.paused = true,
+            Control::Resume => {
+                self.paused = false;
+                self.single_steps = 0;
+            }
+            Control::Step => {
+                if self.paused {
+                    self.single_steps += 1;
+                }
+            }
+            Control::Pace(pace) => {
+                self.pace = pace;
+                self.interval = None;
+            }
+            Control::Reset => {
+                self.weights_changed = true;
+                return Some(Next::Restart(Box::new(self.initial.clone())));
+            }
+            Control::Reseed => {
+                self.weights_changed = true;
+                return Some(Next::Restart(Box::new((self.reseed)())));
+            }
+            Control::Tweak(tweak) => {
+                if let Tweak::ScaleWeights(..) = tweak {
+                    self.weights_changed = true;
+                }
+                return Some(Next::Tweak(tweak));
+            }
+            Control::Probe(neurons) => self.probed = neurons,
+        }
+        None
+    }
+}
+
+fn pace_interval(period: Duration) -> Interval {
+    let mut interval = time::interval(period);
+    // catch up on ticks shorter than the timer resolution rather than falling behind
+    interval.set_missed_tick_behavior(MissedTickBehavior::Burst);
+    interval
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn controller(weights_interval: Option<u64>) -> (mpsc::UnboundedSender<Control>, Controller) {
+        let (controls, receiver) = mpsc::unbounded_channel();
+        let network = Network::randomized(4, 1, &mut rand::thread_rng());
+        let reseed = network.clone();
+        let controller =
+            Controller::new(receiver, network, weights_interval, move || reseed.clone());
+        (controls, controller)
+    }
+
+    #[test]
+    fn made_outside_the_runtime() {
+        let (_controls, mut controller) = controller(None);
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        for _ in 0..3 {
+            assert!(matches!(runtime.block_on(controller.next()), Next::Step));
+        }
+    }
+
+    #[test]
+    fn weights_due_every_interval_and_after_changes() {
+        let (controls, mut controller) = controller(Some(10));
+        let due: Vec<u64> = (0..30).filter(|&t| controller.weights_due(t)).collect();
+        assert_eq!(due, vec![0, 10, 20]);
+
+        controls
+            .send(Control::Tweak(Tweak::ScaleWeights(
+                Population::Excitatory,
+                2.0,
+            )))
+            .unwrap();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        assert!(matches!(
+            runtime.block_on(controller.next()),
+            Next::Tweak(_)
+        ));
+        assert!(controller.weights_due(33));
+        assert!(!controller.weights_due(34));
+        assert!(controller.weights_due(40));
+    }
+}
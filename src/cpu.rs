@@ -2,115 +2,289 @@ use std::time;
 
 use ndarray::prelude::*;
 use ndarray::Zip;
+use rand::rngs::StdRng;
 use rayon::prelude::*;
 use tokio::sync::mpsc;
 
-use super::izhikevich;
-use super::izhikevich::{thalamic_input, Izhikevich};
+use super::control::{Controller, Next};
+use super::izhikevich::thalamic_input;
+use super::network::Network;
+use super::plasticity::{
+    DistalReward, Homeostasis, Plasticity, RewardSchedule, RewardStdp, RewardStdpParams,
+    DISTAL_REWARD_AMOUNT,
+};
+use super::recording::{Probe, StepFrame};
+use super::synapse::{Projections, Resources, Synapses};
 
 /// Currently this is meant to closely replicate the example Matlab code from the paper though
 /// written in a more object oriented style rather than array oriented to be closer to a
 /// theoretically more GPU-friendly style
 pub(crate) async fn main(
     time_buffer_size: usize,
-    excitatory: usize,
-    inhibitory: usize,
-    voltage_channel: mpsc::Sender<f32>,
-    spike_channel: mpsc::Sender<Vec<bool>>,
+    network: Network,
+    projections: Projections,
+    plasticity: Plasticity,
+    mut controller: Controller,
+    frame_channel: mpsc::Sender<StepFrame>,
+    mut rng: StdRng,
 ) {
-    let mut neurons = izhikevich::randomized_neurons(excitatory, inhibitory);
-    let connections = izhikevich::randomized_connections(excitatory, inhibitory);
+    let Network {
+        excitatory,
+        inhibitory,
+        mut neurons,
+        mut connections,
+        mut delays,
+        ..
+    } = network;
+    let mut synapses = Array1::<Synapses>::default(excitatory + inhibitory);
+    let mut resources = Array1::<Resources>::default(excitatory + inhibitory);
+    // fraction of each neuron's connection weights delivered by its spike at each step, delays
+    // are limited to the buffer size so this holds every spike still in transit
+    let mut release = Array2::<f32>::zeros((time_buffer_size, excitatory + inhibitory));
 
     let mut spikes = Array2::<bool>::default((excitatory + inhibitory, time_buffer_size));
     let mut voltages = Array1::<f32>::zeros(time_buffer_size);
 
+    let (mut rstdp, mut distal_reward, mut homeostasis) = start_plasticity(
+        &plasticity,
+        excitatory,
+        inhibitory,
+        &mut connections,
+        &mut rng,
+    );
+
+    // scale of the thalamic noise amplitudes
+    let mut noise: f32 = 1.0;
+
     let mut t: usize = 0;
-    let mut interval = tokio::time::interval(tokio::time::Duration::from_millis(1));
+    // total time simulated in ms, unlike `t` this doesn't wrap around
+    let mut time: u64 = 0;
     loop {
-        interval.tick().await;
+        match controller.next().await {
+            Next::Step => {}
+            Next::Restart(network) => {
+                log::info!("{}ms: restarting the network", time);
+                neurons = network.neurons;
+                connections = network.connections;
+                delays = network.delays;
+                synapses.fill(Synapses::default());
+                resources.fill(Resources::default());
+                release.fill(0.0);
+                spikes.fill(false);
+                noise = 1.0;
+                (rstdp, distal_reward, homeostasis) = start_plasticity(
+                    &plasticity,
+                    excitatory,
+                    inhibitory,
+                    &mut connections,
+                    &mut rng,
+                );
+                continue;
+            }
+            Next::Tweak(tweak) => {
+                log::info!("{}ms: {}", time, tweak);
+                tweak.apply(excitatory, &mut noise, &mut neurons, &mut connections);
+                continue;
+            }
+        }
 
         let timer = time::Instant::now();
 
-        let ci = if t == 0 {
-            Array1::<f32>::zeros(excitatory + inhibitory)
-        } else {
-            let prev_column = wrapping_dec(t, time_buffer_size);
-            connection_input(&spikes.column(prev_column), &connections)
+        let (excitatory_input, inhibitory_input) = match &delays {
+            Some(delays) => delayed_connection_input(&release, t, &connections, delays, excitatory),
+            None => {
+                let prev_row = wrapping_sub(t, 1, time_buffer_size);
+                connection_input(&release.row(prev_row), &connections, excitatory)
+            }
         };
-        let input = thalamic_input(excitatory, inhibitory) + ci;
-
-        let mut new_neurons: Vec<Izhikevich> = Vec::with_capacity(neurons.len());
-        let mut current_spikes_buf: Vec<bool> = Vec::with_capacity(neurons.len());
-
-        (0..neurons.len())
-            .into_par_iter()
-            .zip_eq(0..input.len())
-            .map(|(n, i)| {
-                let mut neuron = neurons[n];
-                let input = input[i];
-                let s = neuron.compute_step(input);
-                (neuron, s)
-            })
-            .unzip_into_vecs(&mut new_neurons, &mut current_spikes_buf);
+        let input = thalamic_input(excitatory, inhibitory, &mut rng) * noise;
+
+        let direct_input = Zip::from(&mut synapses)
+            .and(&excitatory_input)
+            .and(&inhibitory_input)
+            .par_map_collect(|synapses, &e, &h| synapses.receive(&projections, e, h));
+        let current_spikes = Zip::from(&mut neurons)
+            .and(&mut synapses)
+            .and(&input)
+            .and(&direct_input)
+            .par_map_collect(|neuron, synapses, &i, &d| neuron.compute_step(i + d, synapses));
 
-        let current_spikes = Array::from(current_spikes_buf);
-        neurons.assign(&Array::from(new_neurons));
+        Zip::indexed(&mut resources)
+            .and(&current_spikes)
+            .and(release.row_mut(t))
+            .par_for_each(|j, resources, &spiked, release| {
+                let projection = if j < excitatory {
+                    projections.excitatory
+                } else {
+                    projections.inhibitory
+                };
+                *release = resources.release(projection.stp, spiked);
+            });
+
+        if let (Some(rstdp), Some(reward)) = (&mut rstdp, &plasticity.reward) {
+            if reward.times.contains(&time) {
+                rstdp.reward(reward.amount);
+            }
+            if let Some(experiment) = &mut distal_reward {
+                if experiment.step(time, &current_spikes.view(), &mut rng) {
+                    rstdp.reward(DISTAL_REWARD_AMOUNT);
+                    log::info!(
+                        "{}ms: reward delivered, dopamine {}, reinforced synapse weight {} with \
+                        eligibility {}",
+                        time,
+                        rstdp.dopamine(),
+                        connections[[experiment.post, experiment.pre]],
+                        rstdp.eligibility(experiment.pre, experiment.post)
+                    );
+                }
+            }
+            rstdp.step(&current_spikes.view(), &mut connections);
+        }
+
+        if let Some(homeostasis) = &mut homeostasis {
+            homeostasis.step(time, &current_spikes.view(), &mut neurons, &mut connections);
+        }
 
         let v = neurons[0].v;
         voltages[t] = v;
         spikes.column_mut(t).assign(&current_spikes);
 
-        let vc = voltage_channel.clone();
-        tokio::spawn(async move {
-            if let Err(_) = vc.send(v).await {
-                println!("sending voltage failed");
-            }
-        });
+        let lfp = neurons.iter().map(|neuron| neuron.v).sum::<f32>() / neurons.len() as f32;
+        let probes = controller
+            .probed()
+            .iter()
+            .map(|&neuron| Probe {
+                neuron,
+                state: neurons[neuron],
+                input: input[neuron]
+                    + direct_input[neuron]
+                    + synapses[neuron].current(neurons[neuron].v),
+            })
+            .collect();
+        let frame = StepFrame {
+            time,
+            voltage: v,
+            lfp,
+            spikes: current_spikes.to_vec(),
+            probes,
+            connections: controller.weights_due(time).then(|| connections.clone()),
+        };
+        if frame_channel.send(frame).await.is_err() {
+            println!("sending step failed");
+        }
 
-        let sc = spike_channel.clone();
-        tokio::spawn(async move {
-            if let Err(_) = sc.send(current_spikes.to_vec()).await {
-                println!("sending spikes failed");
-            }
-        });
+        log::trace!("{}ms: step took {:?}", time, timer.elapsed());
 
         t = wrapping_inc(t, time_buffer_size);
-        let elapsed = timer.elapsed();
-        tokio::spawn(async move {
-            println!("{:?}", elapsed);
-        });
+        time += 1;
     }
 }
 
-fn connection_input(prev_spikes: &ArrayView1<bool>, connections: &Array2<f32>) -> Array1<f32> {
-    // this isn't the ideal way of doing this but ndarray doesn't currently support boolean
-    // masking so this is the only way to get this to work.
+/// Fresh reward-modulated STDP, distal reward experiment and homeostasis state for a network,
+/// whichever of them are enabled
+fn start_plasticity(
+    plasticity: &Plasticity,
+    excitatory: usize,
+    inhibitory: usize,
+    connections: &mut Array2<f32>,
+    rng: &mut StdRng,
+) -> (
+    Option<RewardStdp>,
+    Option<DistalReward>,
+    Option<Homeostasis>,
+) {
+    let rstdp = plasticity
+        .reward
+        .as_ref()
+        .map(|_| RewardStdp::new(RewardStdpParams::default(), excitatory, connections));
+    let distal_reward = match plasticity.reward {
+        Some(RewardSchedule {
+            distal_reward: true,
+            ..
+        }) => {
+            let experiment = DistalReward::new(excitatory, connections, rng);
+            match &experiment {
+                Some(experiment) => log::info!(
+                    "reinforcing synapse from neuron {} to {}",
+                    experiment.pre,
+                    experiment.post
+                ),
+                None => log::warn!(
+                    "no connections between excitatory neurons to reinforce, skipping the \
+                    distal reward experiment"
+                ),
+            }
+            experiment
+        }
+        _ => None,
+    };
+    let homeostasis = plasticity
+        .homeostasis
+        .map(|params| Homeostasis::new(params, excitatory, inhibitory));
+    (rstdp, distal_reward, homeostasis)
+}
 
-    let mut out = Vec::with_capacity(prev_spikes.len());
+/// Sums the weights of last step's spikes for each neuron, scaled by how much of each spike is
+/// released, kept separate for the excitatory and inhibitory presynaptic populations since they
+/// can use different kinds of synapses
+fn connection_input(
+    prev_release: &ArrayView1<f32>,
+    connections: &Array2<f32>,
+    excitatory: usize,
+) -> (Array1<f32>, Array1<f32>) {
+    let mut excitatory_out = Vec::with_capacity(prev_release.len());
+    let mut inhibitory_out = Vec::with_capacity(prev_release.len());
+
+    let (excitatory_release, inhibitory_release) =
+        prev_release.view().split_at(Axis(0), excitatory);
 
     connections
         .axis_iter(Axis(0)) // iterate across rows
         .into_par_iter()
         .map(|row| {
-            // this method is a tiny bit faster than the below but nearly equal
-            Zip::from(row)
-                .and(prev_spikes)
-                .fold(0.0, |acc, w, s| match s {
-                    true => acc + w,
-                    false => acc,
-                })
-            /*
-            row.iter()
-                .zip(prev_spikes)
-                .fold(0.0, |acc, (w, s)| match s {
-                    true => acc + w,
-                    false => acc,
-                })
-                */
+            let (excitatory_row, inhibitory_row) = row.split_at(Axis(0), excitatory);
+            (
+                excitatory_row.dot(&excitatory_release),
+                inhibitory_row.dot(&inhibitory_release),
+            )
         })
-        .collect_into_vec(&mut out);
+        .unzip_into_vecs(&mut excitatory_out, &mut inhibitory_out);
 
-    Array1::from(out)
+    (Array1::from(excitatory_out), Array1::from(inhibitory_out))
+}
+
+/// Like `connection_input` but each spike arrives after its connection's delay instead of on the
+/// next step
+fn delayed_connection_input(
+    release: &Array2<f32>,
+    t: usize,
+    connections: &Array2<f32>,
+    delays: &Array2<u32>,
+    excitatory: usize,
+) -> (Array1<f32>, Array1<f32>) {
+    let mut excitatory_out = Vec::with_capacity(connections.nrows());
+    let mut inhibitory_out = Vec::with_capacity(connections.nrows());
+    let time_buffer_size = release.nrows();
+
+    connections
+        .axis_iter(Axis(0))
+        .into_par_iter()
+        .zip(delays.axis_iter(Axis(0)))
+        .map(|(row, delays)| {
+            let mut total = (0.0, 0.0);
+            for (j, (w, d)) in row.iter().zip(delays).enumerate() {
+                let weighted = w * release[[wrapping_sub(t, *d as usize, time_buffer_size), j]];
+                if j < excitatory {
+                    total.0 += weighted;
+                } else {
+                    total.1 += weighted;
+                }
+            }
+            total
+        })
+        .unzip_into_vecs(&mut excitatory_out, &mut inhibitory_out);
+
+    (Array1::from(excitatory_out), Array1::from(inhibitory_out))
 }
 
 fn wrapping_inc(t: usize, max: usize) -> usize {
@@ -121,10 +295,7 @@ fn wrapping_inc(t: usize, max: usize) -> usize {
     }
 }
 
-fn wrapping_dec(t: usize, max: usize) -> usize {
-    if t == 1 {
-        max - 1
-    } else {
-        t - 1
-    }
+/// `t - d` wrapped around a buffer of size `max`
+fn wrapping_sub(t: usize, d: usize, max: usize) -> usize {
+    (t + max - d) % max
 }
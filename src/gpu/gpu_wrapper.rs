@@ -78,6 +78,11 @@ impl GpuWrapper {
         }
     }
 
+    /// Replaces the contents of a buffer's storage with data of the same size
+    pub fn write_buffer<T: 'static + Copy + AsBytes>(&self, buffer: &BufferWrapper, data: &[T]) {
+        self.queue.write_buffer(&buffer.storage, 0, data.as_bytes());
+    }
+
     pub fn device(&self) -> &wgpu::Device {
         &self.device
     }
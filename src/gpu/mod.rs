@@ -2,11 +2,16 @@ use std::convert::TryInto;
 use std::time;
 
 use ndarray::prelude::*;
+use rand::rngs::StdRng;
 use tokio::sync::{mpsc, oneshot};
 use wgpu::util::DeviceExt;
 use zerocopy::AsBytes;
 
+use super::control::{Controller, Next, Tweak};
 use super::izhikevich;
+use super::network::Network;
+use super::recording::{Probe, StepFrame};
+use super::synapse::{Projections, Resources, Synapses};
 //use super::izhikevich::Izhikevich;
 
 mod gpu_wrapper;
@@ -19,17 +24,42 @@ struct Config {
     neurons: u32,
     total_time_steps: u32,
     time_step: u32,
+    excitatory: u32,
+    // see `SynapseKind::shader_code`
+    excitatory_synapse: u32,
+    inhibitory_synapse: u32,
+    // see `SynapseKind::shader_taus`
+    excitatory_tau_rise: f32,
+    excitatory_tau_decay: f32,
+    inhibitory_tau_rise: f32,
+    inhibitory_tau_decay: f32,
+    // see `Projection::shader_stp`
+    excitatory_utilization: f32,
+    excitatory_tau_rec: f32,
+    excitatory_tau_facil: f32,
+    inhibitory_utilization: f32,
+    inhibitory_tau_rec: f32,
+    inhibitory_tau_facil: f32,
 }
 
 pub(crate) async fn main(
     time_buffer_size: usize,
-    excitatory: usize,
-    inhibitory: usize,
-    voltage_channel: mpsc::Sender<f32>,
-    spike_channel: mpsc::Sender<Vec<bool>>,
+    network: Network,
+    projections: Projections,
+    mut controller: Controller,
+    frame_channel: mpsc::Sender<StepFrame>,
+    mut rng: StdRng,
 ) {
-    let neurons = izhikevich::randomized_neurons(excitatory, inhibitory);
-    let connections = izhikevich::randomized_connections(excitatory, inhibitory);
+    let Network {
+        excitatory,
+        inhibitory,
+        mut neurons,
+        mut connections,
+        delays,
+        ..
+    } = network;
+    // the shader always reads delays so networks without them get the implicit 1ms delay
+    let delays = delays.unwrap_or_else(|| Array2::<u32>::ones(connections.raw_dim()));
     let spikes = Array2::<u32>::zeros((time_buffer_size, neurons.len()));
 
     let mut gw: GpuWrapper = GpuWrapper::new().await;
@@ -38,6 +68,14 @@ pub(crate) async fn main(
     // this will be created with more permissions than it needs since it's readonly right now
     let connections_buffer = gw.create_buffer("connections", connections.as_slice().unwrap());
     let spike_buffer = gw.create_buffer("spikes", &spikes.as_slice().unwrap());
+    let synapses = vec![Synapses::default(); neurons.len()];
+    let synapse_buffer = gw.create_buffer("synapses", &synapses);
+    let resources = vec![Resources::default(); neurons.len()];
+    let resource_buffer = gw.create_buffer("resources", &resources);
+    // fraction of the connection weights released by each spike in the spike buffer
+    let release = Array2::<f32>::zeros((time_buffer_size, neurons.len()));
+    let release_buffer = gw.create_buffer("release", release.as_slice().unwrap());
+    let delay_buffer = gw.create_buffer("delays", delays.as_slice().unwrap());
 
     let config_buffer_size = std::mem::size_of::<Config>() as wgpu::BufferAddress;
 
@@ -48,7 +86,8 @@ pub(crate) async fn main(
         mapped_at_creation: false,
     });
 
-    let initial_thalamic_input = izhikevich::thalamic_input(excitatory, inhibitory);
+    // zeros rather than a draw of the noise so the inputs match the CPU's for the same seed
+    let initial_thalamic_input = Array1::<f32>::zeros(neurons.len());
     let thalamic_buffer_size =
         (initial_thalamic_input.len() * std::mem::size_of::<f32>()) as wgpu::BufferAddress;
 
@@ -71,6 +110,13 @@ pub(crate) async fn main(
             | wgpu::BufferUsages::COPY_SRC,
         mapped_at_creation: false,
     });
+    // the shader leaves each neuron's total input in the thalamic buffer for probing
+    let input_staging_buffer = gw.device().create_buffer(&wgpu::BufferDescriptor {
+        label: Some("input_staging"),
+        size: thalamic_buffer_size,
+        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
 
     let bind_group_layout =
         gw.device()
@@ -136,6 +182,50 @@ pub(crate) async fn main(
                             min_binding_size: None,
                         },
                     },
+                    // synapses
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 5,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        count: None,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                    },
+                    // short-term plasticity resources
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 6,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        count: None,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                    },
+                    // release
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 7,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        count: None,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                    },
+                    // delays
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 8,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        count: None,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                    },
                 ],
             });
 
@@ -167,6 +257,22 @@ pub(crate) async fn main(
                 binding: 4,
                 resource: connections_buffer.binding_resource(),
             },
+            wgpu::BindGroupEntry {
+                binding: 5,
+                resource: synapse_buffer.binding_resource(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 6,
+                resource: resource_buffer.binding_resource(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 7,
+                resource: release_buffer.binding_resource(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 8,
+                resource: delay_buffer.binding_resource(),
+            },
         ],
     });
 
@@ -189,19 +295,76 @@ pub(crate) async fn main(
 
     let mut voltages: Vec<f32> = Vec::with_capacity(time_buffer_size);
 
+    let (excitatory_tau_rise, excitatory_tau_decay) = projections.excitatory.synapse.shader_taus();
+    let (inhibitory_tau_rise, inhibitory_tau_decay) = projections.inhibitory.synapse.shader_taus();
+    let (excitatory_utilization, excitatory_tau_rec, excitatory_tau_facil) =
+        projections.excitatory.shader_stp();
+    let (inhibitory_utilization, inhibitory_tau_rec, inhibitory_tau_facil) =
+        projections.inhibitory.shader_stp();
+
+    // scale of the thalamic noise amplitudes
+    let mut noise: f32 = 1.0;
+
     let mut t: usize = 0;
-    let mut interval = tokio::time::interval(tokio::time::Duration::from_millis(1));
+    let mut time: u64 = 0;
     loop {
-        interval.tick().await;
+        match controller.next().await {
+            Next::Step => {}
+            Next::Restart(network) => {
+                log::info!("{}ms: restarting the network", time);
+                neurons = network.neurons;
+                connections = network.connections;
+                let delays = network
+                    .delays
+                    .unwrap_or_else(|| Array2::<u32>::ones(connections.raw_dim()));
+                gw.write_buffer(&neuron_buffer, neurons.as_slice().unwrap());
+                gw.write_buffer(&connections_buffer, connections.as_slice().unwrap());
+                gw.write_buffer(&delay_buffer, delays.as_slice().unwrap());
+                gw.write_buffer(&spike_buffer, spikes.as_slice().unwrap());
+                gw.write_buffer(&synapse_buffer, &synapses);
+                gw.write_buffer(&resource_buffer, &resources);
+                gw.write_buffer(&release_buffer, release.as_slice().unwrap());
+                noise = 1.0;
+                continue;
+            }
+            Next::Tweak(tweak) => {
+                log::info!("{}ms: {}", time, tweak);
+                tweak.apply(excitatory, &mut noise, &mut neurons, &mut connections);
+                match tweak {
+                    Tweak::Noise(_) => {}
+                    Tweak::ScaleWeights(..) => {
+                        gw.write_buffer(&connections_buffer, connections.as_slice().unwrap())
+                    }
+                    Tweak::Preset(..) => {
+                        gw.write_buffer(&neuron_buffer, neurons.as_slice().unwrap())
+                    }
+                }
+                continue;
+            }
+        }
         let _timer = time::Instant::now();
 
         let config = Config {
             neurons: neurons.len() as u32,
             total_time_steps: time_buffer_size as u32,
             time_step: t as u32,
+            excitatory: excitatory as u32,
+            excitatory_synapse: projections.excitatory.synapse.shader_code(),
+            inhibitory_synapse: projections.inhibitory.synapse.shader_code(),
+            excitatory_tau_rise,
+            excitatory_tau_decay,
+            inhibitory_tau_rise,
+            inhibitory_tau_decay,
+            excitatory_utilization,
+            excitatory_tau_rec,
+            excitatory_tau_facil,
+            inhibitory_utilization,
+            inhibitory_tau_rec,
+            inhibitory_tau_facil,
         };
 
-        let thalamic_input = izhikevich::thalamic_input(excitatory, inhibitory);
+        let thalamic_input = izhikevich::thalamic_input(excitatory, inhibitory, &mut rng) * noise;
+        let snapshot_weights = controller.weights_due(time);
 
         let mut encoder = gw
             .device()
@@ -253,12 +416,13 @@ pub(crate) async fn main(
             cpass.dispatch_workgroups(neurons.len() as u32, 1, 1);
         }
 
+        // every neuron's voltage goes into the LFP proxy
         encoder.copy_buffer_to_buffer(
             &neuron_buffer.storage,
             0,
             &neuron_buffer.staging,
             0,
-            std::mem::size_of::<izhikevich::Izhikevich>() as wgpu::BufferAddress,
+            neuron_buffer.size,
         );
 
         encoder.copy_buffer_to_buffer(
@@ -269,20 +433,56 @@ pub(crate) async fn main(
             (neurons.len() * std::mem::size_of::<u32>()) as wgpu::BufferAddress,
         );
 
+        encoder.copy_buffer_to_buffer(
+            &thalamic_storage_buffer,
+            0,
+            &input_staging_buffer,
+            0,
+            thalamic_buffer_size,
+        );
+
+        // the UI's weights are read back from the GPU so they show what it actually simulates
+        if snapshot_weights {
+            encoder.copy_buffer_to_buffer(
+                &connections_buffer.storage,
+                0,
+                &connections_buffer.staging,
+                0,
+                connections_buffer.size,
+            );
+        }
+
         gw.queue().submit(Some(encoder.finish()));
 
         {
             let (neuron_tx, mut neuron_rx) = oneshot::channel();
             let (spike_tx, mut spike_rx) = oneshot::channel();
-            //read first neuron voltage
+            let (input_tx, mut input_rx) = oneshot::channel();
             let neuron_time_slice = neuron_buffer.staging.slice(..);
             neuron_time_slice.map_async(wgpu::MapMode::Read, move |result| {
                 neuron_tx.send(result).unwrap();
             });
-            let spike_time_slice = spike_buffer.staging.slice(..);
+            // only this step's spikes were copied over
+            let spike_offset =
+                (t * neurons.len() * std::mem::size_of::<u32>()) as wgpu::BufferAddress;
+            let spike_size = (neurons.len() * std::mem::size_of::<u32>()) as wgpu::BufferAddress;
+            let spike_time_slice = spike_buffer
+                .staging
+                .slice(spike_offset..spike_offset + spike_size);
             spike_time_slice.map_async(wgpu::MapMode::Read, move |result| {
                 spike_tx.send(result).unwrap();
             });
+            let input_slice = input_staging_buffer.slice(..);
+            input_slice.map_async(wgpu::MapMode::Read, move |result| {
+                input_tx.send(result).unwrap();
+            });
+            let (weights_tx, mut weights_rx) = oneshot::channel();
+            let weights_slice = connections_buffer.staging.slice(..);
+            if snapshot_weights {
+                weights_slice.map_async(wgpu::MapMode::Read, move |result| {
+                    weights_tx.send(result).unwrap();
+                });
+            }
 
             gw.device().poll(wgpu::Maintain::Wait);
 
@@ -292,12 +492,16 @@ pub(crate) async fn main(
                 .chunks_exact(4)
                 .map(|b| f32::from_ne_bytes(b.try_into().unwrap()))
                 .collect();
+            // v is the fifth field of each neuron
+            let stride = std::mem::size_of::<izhikevich::Izhikevich>() / 4;
             let v = raw[4];
             voltages.push(v);
-
-            let vc = voltage_channel.clone();
-            if let Err(_) = vc.send(v).await {
-                println!("sending voltage failed");
+            let lfp = raw.iter().skip(4).step_by(stride).sum::<f32>() / neurons.len() as f32;
+            // keep the state of the neurons here current so tweaking their parameters and writing
+            // them back doesn't rewind them
+            for (neuron, state) in neurons.iter_mut().zip(raw.chunks_exact(stride)) {
+                neuron.v = state[4];
+                neuron.u = state[5];
             }
 
             spike_rx.try_recv().unwrap().unwrap();
@@ -308,16 +512,59 @@ pub(crate) async fn main(
                 .map(|v| if v > 0 { true } else { false })
                 .collect();
 
-            let sc = spike_channel.clone();
-            if let Err(_) = sc.send(spikes).await {
-                println!("sending spikes failed");
+            input_rx.try_recv().unwrap().unwrap();
+            let data = input_slice.get_mapped_range();
+            let input: Vec<f32> = data
+                .chunks_exact(4)
+                .map(|b| f32::from_ne_bytes(b.try_into().unwrap()))
+                .collect();
+
+            let weights = if snapshot_weights {
+                weights_rx.try_recv().unwrap().unwrap();
+                let data = weights_slice.get_mapped_range();
+                let raw: Vec<f32> = data
+                    .chunks_exact(4)
+                    .map(|b| f32::from_ne_bytes(b.try_into().unwrap()))
+                    .collect();
+                Some(
+                    Array2::from_shape_vec(connections.raw_dim(), raw)
+                        .expect("error reading back connections"),
+                )
+            } else {
+                None
+            };
+
+            let probes = controller
+                .probed()
+                .iter()
+                .map(|&neuron| Probe {
+                    neuron,
+                    state: neurons[neuron],
+                    input: input[neuron],
+                })
+                .collect();
+            let frame = StepFrame {
+                time,
+                voltage: v,
+                lfp,
+                spikes,
+                probes,
+                connections: weights,
+            };
+            if frame_channel.send(frame).await.is_err() {
+                println!("sending step failed");
             }
         }
 
         neuron_buffer.staging.unmap();
         spike_buffer.staging.unmap();
+        input_staging_buffer.unmap();
+        if snapshot_weights {
+            connections_buffer.staging.unmap();
+        }
 
         t = wrapping_inc(t, time_buffer_size);
+        time += 1;
 
         /*
             let elapsed = timer.elapsed();
@@ -3,6 +3,8 @@ use rand::prelude::*;
 use rand_distr::StandardNormal;
 use zerocopy::{AsBytes, FromBytes, FromZeroes};
 
+use super::synapse::Synapses;
+
 #[derive(Debug, Copy, Clone, FromZeroes, FromBytes, AsBytes)]
 #[repr(C)]
 pub struct Izhikevich {
@@ -19,7 +21,9 @@ pub struct Izhikevich {
 }
 
 impl Izhikevich {
-    pub fn compute_step(&mut self, i: f32) -> bool {
+    /// Advances the neuron by 1ms with external input current `i`, integrating the synaptic
+    /// conductances alongside the membrane potential
+    pub fn compute_step(&mut self, i: f32, synapses: &mut Synapses) -> bool {
         let spike = if self.v >= 30.0 {
             self.v = self.v_reset;
             self.u = self.u + self.u_reset;
@@ -28,22 +32,28 @@ impl Izhikevich {
             false
         };
 
-        self.v += 0.5 * (0.04 * self.v.powi(2) + 5.0 * self.v + 140.0 - self.u + i);
-        self.v += 0.5 * (0.04 * self.v.powi(2) + 5.0 * self.v + 140.0 - self.u + i);
+        let i_syn = i + synapses.current(self.v);
+        self.v += 0.5 * (0.04 * self.v.powi(2) + 5.0 * self.v + 140.0 - self.u + i_syn);
+        let i_syn = i + synapses.current(self.v);
+        self.v += 0.5 * (0.04 * self.v.powi(2) + 5.0 * self.v + 140.0 - self.u + i_syn);
         self.u += self.decay_rate * (self.sensitivity * self.v - self.u);
+        synapses.decay();
 
         spike
     }
 }
 
 /// Creates a randomized set of neurons in accordance with the example code from Izhikevich (2003)
-pub fn randomized_neurons(excitatory: usize, inhibitory: usize) -> Array1<Izhikevich> {
+pub fn randomized_neurons<R: Rng>(
+    excitatory: usize,
+    inhibitory: usize,
+    rng: &mut R,
+) -> Array1<Izhikevich> {
     let total = excitatory + inhibitory;
-    let mut rng = rand::thread_rng();
 
     Array::from_iter((0..total).map(|i| {
         let noise: f32 = rng.gen();
-        if i <= excitatory {
+        if i < excitatory {
             let b = 0.2;
             let v = -65.0;
             Izhikevich {
@@ -69,7 +79,11 @@ pub fn randomized_neurons(excitatory: usize, inhibitory: usize) -> Array1<Izhike
     }))
 }
 
-pub fn randomized_connections(excitatory: usize, inhibitory: usize) -> Array2<f32> {
+pub fn randomized_connections<R: Rng>(
+    excitatory: usize,
+    inhibitory: usize,
+    rng: &mut R,
+) -> Array2<f32> {
     // The Matlab code declares the connection matrix as
     // S=[0.5*rand(Ne+Ni,Ne), -rand(Ne+Ni,Ni)];
     // which results in a matrix of shape(Ne+Ni, Ne+Ni) with the second array
@@ -82,8 +96,6 @@ pub fn randomized_connections(excitatory: usize, inhibitory: usize) -> Array2<f3
     //    1   1   0
     //    1   1   0
 
-    let mut rng = rand::thread_rng();
-
     let total = excitatory + inhibitory;
 
     let mut connections: Array2<f32> = Array::zeros((total, total));
@@ -101,9 +113,8 @@ pub fn randomized_connections(excitatory: usize, inhibitory: usize) -> Array2<f3
     connections
 }
 
-pub fn thalamic_input(excitatory: usize, inhibitory: usize) -> Array1<f32> {
+pub fn thalamic_input<R: Rng>(excitatory: usize, inhibitory: usize, rng: &mut R) -> Array1<f32> {
     let total = excitatory + inhibitory;
-    let mut rng = rand::thread_rng();
 
     Array::from_iter((0..total).map(|i| {
         let noise: f32 = rng.sample(StandardNormal);
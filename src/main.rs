@@ -1,16 +1,46 @@
 use std::collections::VecDeque;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::sync::Mutex;
 use std::thread;
 
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use structopt::StructOpt;
 use tokio::sync::mpsc;
 
+mod analysis;
+mod commands;
+mod connectivity;
+mod control;
 mod cpu;
 mod gpu;
 mod izhikevich;
+mod network;
+mod plasticity;
+mod plot;
+mod recording;
+mod synapse;
+mod topology;
 mod ui;
 
+use analysis::pattern::{Classification, PatternParams, ProbeParams};
+use connectivity::{export, formats, Generator};
+use control::{Control, Controller};
+use network::Network;
+
+use plasticity::{HomeostasisParams, Plasticity, RewardSchedule};
+use recording::{AvalancheRecorder, SignalRecorder, SpikeRecorder, StepFrame};
+use synapse::{Projection, Projections, ShortTermPlasticity, SynapseKind};
+use topology::{Layout, Profile, Topology};
+use ui::dashboard::{Dashboard, Panel, RowOrder};
+use ui::headless;
+use ui::raster::Raster;
+use ui::traces::Traces;
+use ui::weights::Weights;
+
 #[derive(Debug, StructOpt, Clone)]
 #[structopt(name = "izhikevich")]
 struct Args {
@@ -29,10 +59,208 @@ struct Args {
     #[structopt(long = "ni", default_value = "200")]
     num_inhibitory: usize,
 
-    /// drawing the spike data live is incredibly slow, set this true to only see
-    /// the voltage of neuron 0 over time
-    #[structopt(long = "no-spikes", aliases = &["no-spike"])]
-    no_spikes: bool,
+    /// synapse kind for connections from excitatory neurons: delta, ampa, nmda, gaba-a, gaba-b,
+    /// exp:<tau>, alpha:<tau> or biexp:<tau rise>:<tau decay>
+    #[structopt(long = "exc-synapse", default_value = "delta")]
+    excitatory_synapse: SynapseKind,
+
+    /// synapse kind for connections from inhibitory neurons: delta, ampa, nmda, gaba-a, gaba-b,
+    /// exp:<tau>, alpha:<tau> or biexp:<tau rise>:<tau decay>
+    #[structopt(long = "inh-synapse", default_value = "delta")]
+    inhibitory_synapse: SynapseKind,
+
+    /// Tsodyks-Markram short-term plasticity for connections from excitatory neurons, given as
+    /// U:tau_rec:tau_facil with time constants in ms
+    #[structopt(long = "exc-stp")]
+    excitatory_stp: Option<ShortTermPlasticity>,
+
+    /// Tsodyks-Markram short-term plasticity for connections from inhibitory neurons, given as
+    /// U:tau_rec:tau_facil with time constants in ms
+    #[structopt(long = "inh-stp")]
+    inhibitory_stp: Option<ShortTermPlasticity>,
+
+    /// times in ms to deliver rewards to the network, enables dopamine modulated STDP on the
+    /// excitatory connections (CPU only)
+    #[structopt(long = "reward-at", use_delimiter = true)]
+    reward_times: Vec<u64>,
+
+    /// amount of dopamine delivered by each reward in --reward-at
+    #[structopt(long = "reward-amount", default_value = "0.5")]
+    reward_amount: f32,
+
+    /// run the delayed reward conditioning experiment from Izhikevich (2007), reinforcing a
+    /// random synapse with rewards 1-3s after it's activated, enables dopamine modulated STDP
+    /// (CPU only)
+    #[structopt(long = "distal-reward")]
+    distal_reward: bool,
+
+    /// time constant in ms of homeostatic scaling of each neuron's excitatory input weights
+    /// towards --target-rate (CPU only)
+    #[structopt(long = "scaling-tau")]
+    scaling_tau: Option<f32>,
+
+    /// time constant in ms of homeostatic adaptation of each neuron's u_reset towards
+    /// --target-rate (CPU only)
+    #[structopt(long = "intrinsic-tau")]
+    intrinsic_tau: Option<f32>,
+
+    /// firing rate in Hz that homeostatic plasticity regulates neurons towards
+    #[structopt(long = "target-rate", default_value = "5")]
+    target_rate: f32,
+
+    /// time constant in ms of the moving average used to estimate firing rates for homeostasis
+    #[structopt(long = "rate-tau", default_value = "1000")]
+    rate_tau: f32,
+
+    /// time in ms between homeostatic updates
+    #[structopt(long = "homeostasis-interval", default_value = "100")]
+    homeostasis_interval: u64,
+
+    /// connect neurons with a graph model instead of all to all: er:<p>, in-degree:<k>,
+    /// out-degree:<k>, ws:<k>:<beta> (Watts-Strogatz), ba:<m> (Barabasi-Albert) or
+    /// sbm:<blocks>:<p in>:<p out> (stochastic block model)
+    #[structopt(long = "generator", conflicts_with = "layout")]
+    generator: Option<Generator>,
+
+    /// load the connectivity from a .csv edge list (pre,post,weight[,delay]), Matrix Market .mtx
+    /// or NumPy .npy file instead of generating it. Only edge lists keep the delays, and the
+    /// neurons' parameters are randomized, the same way every time with --seed
+    #[structopt(
+        long = "connections",
+        parse(from_os_str),
+        conflicts_with_all = &["layout", "generator"]
+    )]
+    connections: Option<PathBuf>,
+
+    /// seed for the random network, neuron parameters and thalamic input so a run can be repeated
+    /// exactly, on the CPU or the GPU, and for the sampling done by the commands. A random seed is
+    /// logged when it's left out
+    #[structopt(long = "seed")]
+    seed: Option<u64>,
+
+    /// save the connectivity to a .csv edge list, Matrix Market .mtx or NumPy .npy file so the
+    /// same network can be loaded again with --connections
+    #[structopt(long = "save-connections", parse(from_os_str))]
+    save_connections: Option<PathBuf>,
+
+    /// export the neurons and connections to a GraphML (.graphml) or Graphviz (.dot) file
+    #[structopt(long = "export-graph", parse(from_os_str))]
+    export_graph: Option<PathBuf>,
+
+    /// only export connections with at least this weight magnitude with --export-graph
+    #[structopt(long = "export-threshold", default_value = "0")]
+    export_threshold: f32,
+
+    /// lay the neurons out in space on a grid or at random with distance dependent connectivity
+    #[structopt(long = "layout")]
+    layout: Option<Layout>,
+
+    /// number of spatial dimensions for --layout
+    #[structopt(long = "dimensions", default_value = "2", possible_values = &["2", "3"])]
+    dimensions: usize,
+
+    /// side length of the space for --layout, defaults to fitting a grid with neurons 1 apart
+    #[structopt(long = "extent")]
+    extent: Option<f32>,
+
+    /// wrap distances around the edges of the space for --layout
+    #[structopt(long = "periodic")]
+    periodic: bool,
+
+    /// how connection probability falls off with distance for --layout, gaussian:<sigma> or
+    /// exponential:<length>
+    #[structopt(long = "connection-profile", default_value = "gaussian:2")]
+    connection_profile: Profile,
+
+    /// probability of connecting neurons at the same position for --layout
+    #[structopt(long = "max-probability", default_value = "1")]
+    max_probability: f32,
+
+    /// how weights fall off with distance for --layout, gaussian:<sigma> or exponential:<length>
+    #[structopt(long = "weight-profile")]
+    weight_profile: Option<Profile>,
+
+    /// conduction velocity in units per ms for --layout, adds distance dependent delays
+    #[structopt(long = "velocity")]
+    velocity: Option<f32>,
+
+    /// write the position of each neuron to this CSV file when using --layout
+    #[structopt(long = "positions-out", parse(from_os_str))]
+    positions_out: Option<PathBuf>,
+
+    /// record every spike to this CSV file as time_ms,neuron for the analyze command
+    #[structopt(long = "record-spikes", parse(from_os_str))]
+    record_spikes: Option<PathBuf>,
+
+    /// record the mean membrane potential of every step to this CSV file as time_ms,lfp for the
+    /// spectrum command
+    #[structopt(long = "record-lfp", parse(from_os_str))]
+    record_lfp: Option<PathBuf>,
+
+    /// record the voltage of neuron 0 every step to this CSV file as time_ms,v
+    #[structopt(long = "record-voltage", parse(from_os_str))]
+    record_voltage: Option<PathBuf>,
+
+    /// detect neuronal avalanches in the live spikes and write each to this CSV file, the fitted
+    /// exponents and branching ratio are logged every 10s of simulated time
+    #[structopt(long = "record-avalanches", parse(from_os_str))]
+    record_avalanches: Option<PathBuf>,
+
+    /// time bin in ms for --record-avalanches
+    #[structopt(long = "avalanche-bin", default_value = "1")]
+    avalanche_bin: u64,
+
+    /// bins need more than this many spikes to be part of an avalanche for --record-avalanches
+    #[structopt(long = "avalanche-threshold", default_value = "0")]
+    avalanche_threshold: u64,
+
+    /// probe each neuron with a step current at startup and colour its spikes by the firing
+    /// pattern it shows, as the classify command does
+    #[structopt(long = "color-by-class")]
+    color_by_class: bool,
+
+    /// panels of the window in rows separated by semicolons, each with an optional height share
+    /// before a colon and comma separated panels with optional width shares after a `*`. Panels
+    /// are raster, voltages, rate, phase, spectrum, weights and heatmap
+    #[structopt(long = "dashboard", default_value = "4:raster;1:voltages*2,spectrum")]
+    dashboard: Dashboard,
+
+    /// order of the raster's rows from the bottom: index, population to group them by
+    /// population or firing class, or rate to put the busiest neurons at the top
+    #[structopt(long = "raster-order", default_value = "index")]
+    raster_order: RowOrder,
+
+    /// simulated ms between refreshing the weights and heatmap panels
+    #[structopt(long = "weights-interval", default_value = "1000")]
+    weights_interval: u64,
+
+    /// run without a window for `--duration` ms and save the dashboard to `--output`
+    #[structopt(long = "headless")]
+    headless: bool,
+
+    /// simulated ms to run for when headless, defaults to the number of steps kept
+    #[structopt(long = "duration")]
+    duration: Option<u64>,
+
+    /// simulated ms to also save the dashboard at when headless, each named after its time
+    #[structopt(long = "snapshot-at", use_delimiter = true)]
+    snapshot_times: Vec<u64>,
+
+    /// where the dashboard is saved at the end of a headless run, as SVG if it ends in .svg and
+    /// PNG otherwise
+    #[structopt(long = "output", default_value = "out.png", parse(from_os_str))]
+    output: PathBuf,
+
+    /// record an animated GIF of the scrolling raster when headless
+    #[structopt(long = "gif", parse(from_os_str))]
+    gif: Option<PathBuf>,
+
+    /// simulated ms between the frames of the GIF
+    #[structopt(long = "gif-interval", default_value = "10")]
+    gif_interval: u64,
+
+    #[structopt(subcommand)]
+    command: Option<commands::Command>,
 }
 
 fn main() {
@@ -45,78 +273,318 @@ fn main() {
     let args: Args = Args::from_args();
     log::info!("{:?}", args);
 
+    let seed = args.seed.unwrap_or_else(rand::random);
+    log::info!("seed {}", seed);
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    if let Some(command) = args.command.clone() {
+        commands::run(command, &mut rng);
+        return;
+    }
+
     let step_buffer_size = args.steps;
-    let total_neurons = args.num_excitatory + args.num_inhibitory;
+    let projections = Projections {
+        excitatory: Projection {
+            synapse: args.excitatory_synapse,
+            stp: args.excitatory_stp,
+        },
+        inhibitory: Projection {
+            synapse: args.inhibitory_synapse,
+            stp: args.inhibitory_stp,
+        },
+    };
 
-    let (voltage_tx, mut voltage_rx): (mpsc::Sender<f32>, mpsc::Receiver<f32>) = mpsc::channel(1);
+    let reward = if args.reward_times.is_empty() && !args.distal_reward {
+        None
+    } else {
+        Some(RewardSchedule {
+            times: args.reward_times.clone(),
+            amount: args.reward_amount,
+            distal_reward: args.distal_reward,
+        })
+    };
+
+    let homeostasis = if args.scaling_tau.is_none() && args.intrinsic_tau.is_none() {
+        None
+    } else {
+        // homeostasis divides by each of these
+        let positive = [
+            ("--target-rate", Some(args.target_rate)),
+            ("--rate-tau", Some(args.rate_tau)),
+            ("--scaling-tau", args.scaling_tau),
+            ("--intrinsic-tau", args.intrinsic_tau),
+        ];
+        for (option, value) in positive {
+            if let Some(value) = value.filter(|value| value.is_nan() || *value <= 0.0) {
+                panic!("{} has to be greater than 0, got {}", option, value);
+            }
+        }
+        Some(HomeostasisParams {
+            target_rate: args.target_rate,
+            tau_rate: args.rate_tau,
+            update_interval: args.homeostasis_interval.max(1),
+            tau_scaling: args.scaling_tau,
+            tau_intrinsic: args.intrinsic_tau,
+        })
+    };
+
+    let network = build_network(&args, &mut rng);
+
+    // spikes have to stay in the step buffer until they've arrived
+    if network.max_delay() as usize >= step_buffer_size {
+        panic!(
+            "the longest delay is {}ms but only {} steps are kept, increase the steps",
+            network.max_delay(),
+            step_buffer_size
+        );
+    }
+
+    if let Some(path) = &args.save_connections {
+        formats::save_network(path, &network)
+            .unwrap_or_else(|err| panic!("error saving {}: {}", path.display(), err));
+    }
+
+    if let Some(path) = &args.export_graph {
+        export::export_graph(path, &network, args.export_threshold)
+            .unwrap_or_else(|err| panic!("error exporting {}: {}", path.display(), err));
+    }
 
-    let voltages = Arc::new(Mutex::new(VecDeque::with_capacity(step_buffer_size)));
-    let voltage_pusher = Arc::clone(&voltages);
+    if let (Some(path), Some(positions)) = (&args.positions_out, &network.positions) {
+        let mut out = BufWriter::new(File::create(path).expect("error creating positions file"));
+        topology::write_positions(&mut out, positions, network.excitatory)
+            .expect("error writing positions");
+    }
+
+    let total_neurons = network.len();
+    let excitatory = network.excitatory;
+
+    let classes = if args.color_by_class {
+        let classification = Classification::probe_neurons(
+            network.neurons.as_slice().unwrap(),
+            network.excitatory,
+            &ProbeParams::default(),
+            &PatternParams::default(),
+        );
+        log::info!("firing patterns\n{}", classification);
+        Some(classification.classes)
+    } else {
+        None
+    };
+
+    let mut recorder = args.record_spikes.as_ref().map(|path| {
+        SpikeRecorder::create(path, network.excitatory, network.inhibitory)
+            .unwrap_or_else(|err| panic!("error creating {}: {}", path.display(), err))
+    });
+
+    let mut lfp_recorder = args.record_lfp.as_ref().map(|path| {
+        SignalRecorder::create(path, "lfp", |frame| frame.lfp)
+            .unwrap_or_else(|err| panic!("error creating {}: {}", path.display(), err))
+    });
+
+    let mut voltage_recorder = args.record_voltage.as_ref().map(|path| {
+        SignalRecorder::create(path, "v", |frame| frame.voltage)
+            .unwrap_or_else(|err| panic!("error creating {}: {}", path.display(), err))
+    });
+
+    let mut avalanche_recorder = args.record_avalanches.as_ref().map(|path| {
+        AvalancheRecorder::create(path, args.avalanche_bin.max(1), args.avalanche_threshold)
+            .unwrap_or_else(|err| panic!("error creating {}: {}", path.display(), err))
+    });
 
-    let (spikes_tx, mut spikes_rx): (mpsc::Sender<Vec<bool>>, mpsc::Receiver<Vec<bool>>) =
+    let (frame_tx, mut frame_rx): (mpsc::Sender<StepFrame>, mpsc::Receiver<StepFrame>) =
         mpsc::channel(1);
 
-    let spikes = Arc::new(Mutex::new(VecDeque::with_capacity(step_buffer_size)));
-    let spike_pusher = Arc::clone(&spikes);
+    let traces = Arc::new(Mutex::new(Traces::new(step_buffer_size)));
+    let trace_pusher = Arc::clone(&traces);
+
+    let lfp = Arc::new(Mutex::new(VecDeque::with_capacity(step_buffer_size)));
+    let lfp_pusher = Arc::clone(&lfp);
+
+    let raster = Arc::new(Mutex::new(Raster::new(total_neurons, step_buffer_size)));
+    let raster_pusher = Arc::clone(&raster);
+
+    let weights = Arc::new(Mutex::new(None));
+    let weights_pusher = Arc::clone(&weights);
     runtime.spawn(async move {
-        // doing them both simultaneously keeps the spiking and voltage data matched up
-        while let (Some(v), Some(s)) = (voltage_rx.recv().await, spikes_rx.recv().await) {
-            let mut voltage_guard = voltage_pusher.lock().unwrap();
-            let mut spike_guard = spike_pusher.lock().unwrap();
-
-            if voltage_guard.len() < step_buffer_size {
-                voltage_guard.push_back(v)
-            } else {
-                voltage_guard.pop_front();
-                voltage_guard.push_back(v);
+        while let Some(frame) = frame_rx.recv().await {
+            if let Some(recorder) = &mut recorder {
+                if let Err(err) = recorder.record(&frame) {
+                    log::error!("recording spikes failed: {}", err);
+                }
+            }
+            if let Some(recorder) = &mut lfp_recorder {
+                if let Err(err) = recorder.record(&frame) {
+                    log::error!("recording LFP failed: {}", err);
+                }
+            }
+            if let Some(recorder) = &mut voltage_recorder {
+                if let Err(err) = recorder.record(&frame) {
+                    log::error!("recording voltage failed: {}", err);
+                }
+            }
+            if let Some(recorder) = &mut avalanche_recorder {
+                if let Err(err) = recorder.record(&frame) {
+                    log::error!("recording avalanches failed: {}", err);
+                }
             }
-            let spike_indices = s
-                .iter()
-                .enumerate()
-                .filter(|(_i, &s)| s)
-                .map(|(i, _s)| i as i32)
-                .collect();
-            if spike_guard.len() < step_buffer_size {
-                spike_guard.push_back(spike_indices);
-            } else {
-                spike_guard.pop_front();
-                spike_guard.push_back(spike_indices);
+
+            trace_pusher.lock().unwrap().push(&frame.probes);
+            push_bounded(&lfp_pusher, frame.lfp, step_buffer_size);
+            if let Some(connections) = &frame.connections {
+                let snapshot = Weights::new(frame.time, connections.view(), excitatory);
+                *weights_pusher.lock().unwrap() = Some(snapshot);
             }
+            // the raster goes last so the step is everywhere once it's in the raster, headless
+            // rendering waits on it
+            raster_pusher.lock().unwrap().push(
+                frame.time,
+                frame.spike_indices().map(|i| i as u32).collect(),
+            );
         }
     });
 
+    let (control_tx, control_rx) = mpsc::unbounded_channel();
+    if args.headless {
+        // the headless renderer steps the simulation itself, the pause goes through before the
+        // first step
+        control_tx
+            .send(Control::Pause)
+            .expect("error pausing the simulation");
+    }
+    let reseed_args = args.clone();
+    let mut reseed_rng = StdRng::from_rng(&mut rng).expect("error seeding the reseed generator");
+    let simulation_rng = StdRng::from_rng(&mut rng).expect("error seeding the simulation");
+    // only read the weights back when there's a panel to show them
+    let weights_interval = (args.dashboard.shows(Panel::Weights)
+        || args.dashboard.shows(Panel::Heatmap))
+    .then_some(args.weights_interval);
+    let controller = Controller::new(control_rx, network.clone(), weights_interval, move || {
+        build_network(&reseed_args, &mut reseed_rng)
+    });
+
     if args.use_cpu {
         let runner_args = args.clone();
         runtime.spawn(async move {
             let args = runner_args;
             cpu::main(
                 args.steps,
-                args.num_excitatory,
-                args.num_inhibitory,
-                voltage_tx,
-                spikes_tx,
+                network,
+                projections,
+                Plasticity {
+                    reward,
+                    homeostasis,
+                },
+                controller,
+                frame_tx,
+                simulation_rng,
             )
             .await;
         });
     } else {
+        if reward.is_some() {
+            log::warn!("dopamine modulated STDP only runs on the CPU, ignoring rewards");
+        }
+        if homeostasis.is_some() {
+            log::warn!("homeostatic plasticity only runs on the CPU, ignoring it");
+        }
         let runner_args = args.clone();
         thread::spawn(move || {
             let args = runner_args;
             runtime.block_on(gpu::main(
                 args.steps,
-                args.num_excitatory,
-                args.num_inhibitory,
-                voltage_tx,
-                spikes_tx,
+                network,
+                projections,
+                controller,
+                frame_tx,
+                simulation_rng,
             ));
         });
     }
 
-    ui::draw(
-        step_buffer_size,
-        total_neurons,
-        args.no_spikes,
-        voltages,
-        spikes,
-    );
+    let buffers = ui::Buffers {
+        traces,
+        lfp,
+        raster,
+        weights,
+    };
+    let view = ui::View {
+        dashboard: args.dashboard.clone(),
+        order: args.raster_order,
+        classes,
+    };
+    if args.headless {
+        let output = headless::Output {
+            duration: args.duration.unwrap_or(step_buffer_size as u64),
+            snapshots: args.snapshot_times.clone(),
+            path: args.output.clone(),
+            gif: args.gif.clone().map(|path| (path, args.gif_interval)),
+        };
+        // the simulation carries on once every sender is gone, keeping this one until exiting
+        // stops it running past the duration and into the recordings
+        headless::render(
+            step_buffer_size,
+            excitatory,
+            total_neurons,
+            buffers,
+            control_tx.clone(),
+            view,
+            output,
+        );
+    } else {
+        ui::draw(
+            step_buffer_size,
+            excitatory,
+            total_neurons,
+            buffers,
+            control_tx,
+            view,
+        );
+    }
+}
+
+/// Generates or loads the network the options describe
+fn build_network(args: &Args, rng: &mut StdRng) -> Network {
+    match (args.layout, args.generator, &args.connections) {
+        (_, _, Some(path)) => {
+            formats::load_network(path, args.num_excitatory, args.num_inhibitory, rng)
+                .unwrap_or_else(|err| panic!("error loading {}: {}", path.display(), err))
+        }
+        (Some(layout), _, None) => {
+            // distances are divided by the velocity and scaled by the extent
+            let positive = [("--extent", args.extent), ("--velocity", args.velocity)];
+            for (option, value) in positive {
+                if let Some(value) = value.filter(|value| value.is_nan() || *value <= 0.0) {
+                    panic!("{} has to be greater than 0, got {}", option, value);
+                }
+            }
+            let topology = Topology {
+                layout,
+                dimensions: args.dimensions,
+                extent: args.extent,
+                periodic: args.periodic,
+                connection_profile: args.connection_profile,
+                max_probability: args.max_probability,
+                weight_profile: args.weight_profile,
+                velocity: args.velocity,
+            };
+            // spikes have to stay in the step buffer until they've arrived
+            let max_delay = (args.steps - 1).max(1) as u32;
+            let network = topology.build(args.num_excitatory, args.num_inhibitory, max_delay, rng);
+            log::info!("longest conduction delay {}ms", network.max_delay());
+            network
+        }
+        (None, Some(generator), None) => {
+            generator.build(args.num_excitatory, args.num_inhibitory, rng)
+        }
+        (None, None, None) => Network::randomized(args.num_excitatory, args.num_inhibitory, rng),
+    }
+}
+
+/// Adds to the end of a buffer shared with the UI, dropping the oldest value once it's full
+fn push_bounded<T>(buffer: &Mutex<VecDeque<T>>, value: T, capacity: usize) {
+    let mut guard = buffer.lock().unwrap();
+    if guard.len() >= capacity {
+        guard.pop_front();
+    }
+    guard.push_back(value);
 }
@@ -0,0 +1,77 @@
+use std::fmt;
+use std::ops::Range;
+
+use ndarray::prelude::*;
+use rand::Rng;
+
+use super::izhikevich;
+use super::izhikevich::Izhikevich;
+
+/// A population of neurons and the connections between them. Excitatory neurons always come
+/// before the inhibitory ones.
+#[derive(Debug, Clone)]
+pub struct Network {
+    pub excitatory: usize,
+    pub inhibitory: usize,
+    pub neurons: Array1<Izhikevich>,
+    /// connection weights with a row for each postsynaptic neuron and a column for each
+    /// presynaptic neuron
+    pub connections: Array2<f32>,
+    /// conduction delays in ms with the same layout as `connections`, every connection takes a
+    /// single step when there are none
+    pub delays: Option<Array2<u32>>,
+    /// (x, y, z) position of each neuron for networks laid out in space
+    pub positions: Option<Array2<f32>>,
+}
+
+impl Network {
+    /// The randomized network from the example code in Izhikevich (2003)
+    pub fn randomized<R: Rng>(excitatory: usize, inhibitory: usize, rng: &mut R) -> Self {
+        Network {
+            excitatory,
+            inhibitory,
+            neurons: izhikevich::randomized_neurons(excitatory, inhibitory, rng),
+            connections: izhikevich::randomized_connections(excitatory, inhibitory, rng),
+            delays: None,
+            positions: None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.excitatory + self.inhibitory
+    }
+
+    /// The longest conduction delay in ms
+    pub fn max_delay(&self) -> u32 {
+        match &self.delays {
+            Some(delays) => delays.iter().copied().max().unwrap_or(1),
+            None => 1,
+        }
+    }
+}
+
+/// The excitatory or inhibitory neurons of a network
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Population {
+    Excitatory,
+    Inhibitory,
+}
+
+impl Population {
+    /// Indices of the population's neurons in a network of `total` neurons
+    pub fn range(self, excitatory: usize, total: usize) -> Range<usize> {
+        match self {
+            Population::Excitatory => 0..excitatory,
+            Population::Inhibitory => excitatory..total,
+        }
+    }
+}
+
+impl fmt::Display for Population {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Population::Excitatory => "E",
+            Population::Inhibitory => "I",
+        })
+    }
+}
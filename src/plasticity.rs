@@ -0,0 +1,573 @@
+use ndarray::prelude::*;
+use ndarray::Zip;
+use rand::prelude::*;
+
+use super::izhikevich::Izhikevich;
+
+/// Parameters for dopamine modulated STDP from Izhikevich (2007), "Solving the distal reward
+/// problem through linkage of STDP and dopamine signaling"
+#[derive(Debug, Copy, Clone)]
+pub struct RewardStdpParams {
+    /// eligibility added when a presynaptic spike is followed by a postsynaptic one
+    pub a_plus: f32,
+    /// eligibility removed when a postsynaptic spike is followed by a presynaptic one
+    pub a_minus: f32,
+    /// STDP window time constants in ms
+    pub tau_plus: f32,
+    pub tau_minus: f32,
+    /// eligibility trace decay time constant in ms
+    pub tau_eligibility: f32,
+    /// dopamine decay time constant in ms
+    pub tau_dopamine: f32,
+    /// scale of the weight change per ms, `learning_rate * eligibility * dopamine`
+    pub learning_rate: f32,
+    /// plastic weights are kept within `0..=max_weight`
+    pub max_weight: f32,
+}
+
+impl Default for RewardStdpParams {
+    fn default() -> Self {
+        RewardStdpParams {
+            a_plus: 1.0,
+            a_minus: 1.5,
+            tau_plus: 20.0,
+            tau_minus: 20.0,
+            tau_eligibility: 1000.0,
+            tau_dopamine: 200.0,
+            learning_rate: 0.01,
+            max_weight: 1.0,
+        }
+    }
+}
+
+/// Reward modulated STDP on the connections out of the excitatory neurons. STDP doesn't change
+/// the weights directly but builds up a per-synapse eligibility trace, and weights only change
+/// while dopamine is present.
+pub struct RewardStdp {
+    params: RewardStdpParams,
+    excitatory: usize,
+    /// STDP traces of recent spikes for each neuron as a pre and postsynaptic neuron
+    pre_trace: Array1<f32>,
+    post_trace: Array1<f32>,
+    /// same layout as the connection matrix but only covering the excitatory columns
+    eligibility: Array2<f32>,
+    /// which of the connections exist so learning doesn't create new ones in sparse networks
+    existing: Array2<bool>,
+    dopamine: f32,
+}
+
+impl RewardStdp {
+    /// Learning only applies to the connections that have a non-zero weight in `connections`
+    pub fn new(params: RewardStdpParams, excitatory: usize, connections: &Array2<f32>) -> Self {
+        let total = connections.nrows();
+        RewardStdp {
+            params,
+            excitatory,
+            pre_trace: Array1::zeros(total),
+            post_trace: Array1::zeros(total),
+            eligibility: Array2::zeros((total, excitatory)),
+            existing: connections
+                .slice(s![.., ..excitatory])
+                .mapv(|weight| weight != 0.0),
+            dopamine: 0.0,
+        }
+    }
+
+    /// Delivers a reward by increasing the dopamine concentration
+    pub fn reward(&mut self, amount: f32) {
+        self.dopamine += amount;
+    }
+
+    pub fn dopamine(&self) -> f32 {
+        self.dopamine
+    }
+
+    /// Eligibility of the synapse from `pre` onto `post`, `pre` must be excitatory
+    pub fn eligibility(&self, pre: usize, post: usize) -> f32 {
+        self.eligibility[[post, pre]]
+    }
+
+    /// Advances the traces and dopamine by one 1ms step with this step's spikes and applies
+    /// the weight changes to `connections`
+    pub fn step(&mut self, spikes: &ArrayView1<bool>, connections: &mut Array2<f32>) {
+        let params = self.params;
+
+        self.pre_trace *= (-1.0 / params.tau_plus).exp();
+        self.post_trace *= (-1.0 / params.tau_minus).exp();
+        let eligibility_decay = (-1.0 / params.tau_eligibility).exp();
+        let dopamine = self.dopamine;
+
+        let pre_trace = self.pre_trace.slice(s![..self.excitatory]);
+        let pre_spikes = spikes.slice(s![..self.excitatory]);
+
+        Zip::from(self.eligibility.rows_mut())
+            .and(connections.slice_mut(s![.., ..self.excitatory]).rows_mut())
+            .and(self.existing.rows())
+            .and(spikes)
+            .and(&self.post_trace)
+            .par_for_each(
+                |mut eligibility, mut weights, existing, &post_spiked, &post_trace| {
+                    Zip::from(&mut eligibility)
+                        .and(&mut weights)
+                        .and(existing)
+                        .and(&pre_trace)
+                        .and(&pre_spikes)
+                        .for_each(|c, w, &existing, &pre_trace, &pre_spiked| {
+                            if !existing {
+                                return;
+                            }
+                            *c *= eligibility_decay;
+                            if post_spiked {
+                                *c += params.a_plus * pre_trace;
+                            }
+                            if pre_spiked {
+                                *c -= params.a_minus * post_trace;
+                            }
+                            *w = (*w + params.learning_rate * *c * dopamine)
+                                .clamp(0.0, params.max_weight);
+                        });
+                },
+            );
+
+        // this step's spikes are only added after the update so a neuron isn't paired with
+        // itself in the same step
+        Zip::from(&mut self.pre_trace)
+            .and(&mut self.post_trace)
+            .and(spikes)
+            .for_each(|pre, post, &spiked| {
+                if spiked {
+                    *pre += 1.0;
+                    *post += 1.0;
+                }
+            });
+
+        self.dopamine *= (-1.0 / params.tau_dopamine).exp();
+    }
+}
+
+/// The delayed reward conditioning experiment from Izhikevich (2007). A single synapse between
+/// two excitatory neurons starts at 0 and every time its postsynaptic neuron fires shortly after
+/// the presynaptic one, a reward is delivered after a random 1-3 second delay. Reinforcing the
+/// synapse should make it grow to the maximum weight even though most of the spikes in between
+/// are unrelated.
+pub struct DistalReward {
+    pub pre: usize,
+    pub post: usize,
+    last_pre_spike: Option<u64>,
+    pending_reward: Option<u64>,
+}
+
+/// postsynaptic spikes within this many ms after a presynaptic one trigger a reward
+const DISTAL_REWARD_WINDOW: u64 = 10;
+/// amount of dopamine delivered per reward
+pub const DISTAL_REWARD_AMOUNT: f32 = 0.5;
+
+impl DistalReward {
+    /// Picks an existing synapse between two excitatory neurons to be reinforced and zeros its
+    /// weight, or returns `None` if there aren't any
+    pub fn new<R: Rng>(
+        excitatory: usize,
+        connections: &mut Array2<f32>,
+        rng: &mut R,
+    ) -> Option<Self> {
+        let synapses: Vec<(usize, usize)> = connections
+            .slice(s![..excitatory, ..excitatory])
+            .indexed_iter()
+            .filter(|((post, pre), weight)| post != pre && **weight != 0.0)
+            .map(|((post, pre), _)| (pre, post))
+            .collect();
+        let &(pre, post) = synapses.choose(rng)?;
+        connections[[post, pre]] = 0.0;
+
+        Some(DistalReward {
+            pre,
+            post,
+            last_pre_spike: None,
+            pending_reward: None,
+        })
+    }
+
+    /// Checks this step's spikes, returning whether a reward should be delivered at `time`
+    pub fn step<R: Rng>(&mut self, time: u64, spikes: &ArrayView1<bool>, rng: &mut R) -> bool {
+        if spikes[self.pre] {
+            self.last_pre_spike = Some(time);
+        }
+
+        let paired = match self.last_pre_spike {
+            Some(pre_time) => spikes[self.post] && time - pre_time <= DISTAL_REWARD_WINDOW,
+            None => false,
+        };
+        if paired && self.pending_reward.is_none() {
+            let delay = rng.gen_range(1000..=3000);
+            self.pending_reward = Some(time + delay);
+        }
+
+        match self.pending_reward {
+            Some(reward_time) if reward_time <= time => {
+                self.pending_reward = None;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Plasticity applied to the network as it runs, only supported on the CPU
+#[derive(Debug, Clone, Default)]
+pub struct Plasticity {
+    pub reward: Option<RewardSchedule>,
+    pub homeostasis: Option<HomeostasisParams>,
+}
+
+/// When rewards are delivered to a network learning with `RewardStdp`
+#[derive(Debug, Clone)]
+pub struct RewardSchedule {
+    /// times in ms to deliver `amount` of dopamine
+    pub times: Vec<u64>,
+    pub amount: f32,
+    /// run the `DistalReward` experiment alongside the scheduled rewards
+    pub distal_reward: bool,
+}
+
+/// Parameters for the homeostatic mechanisms that keep firing rates near a target
+#[derive(Debug, Copy, Clone)]
+pub struct HomeostasisParams {
+    /// firing rate each neuron is regulated towards in Hz
+    pub target_rate: f32,
+    /// time constant in ms of the moving average used to estimate each neuron's rate
+    pub tau_rate: f32,
+    /// time in ms between the slow updates to weights and neuron parameters
+    pub update_interval: u64,
+    /// time constant in ms of multiplicative scaling of each neuron's excitatory input weights,
+    /// `None` disables synaptic scaling
+    pub tau_scaling: Option<f32>,
+    /// time constant in ms of adapting each neuron's `u_reset`, `None` disables intrinsic
+    /// plasticity
+    pub tau_intrinsic: Option<f32>,
+}
+
+/// Bounds on the multiplicative change applied by a single homeostatic update, keeps a neuron
+/// far from its target from wiping out or blowing up its weights in one go
+const MIN_HOMEOSTATIC_FACTOR: f32 = 0.5;
+const MAX_HOMEOSTATIC_FACTOR: f32 = 2.0;
+
+/// Synaptic scaling from Turrigiano et al. (1998) and intrinsic plasticity of the after-spike
+/// reset. Both run on a much slower timescale than the simulation step and only look at the
+/// estimated firing rates.
+pub struct Homeostasis {
+    params: HomeostasisParams,
+    excitatory: usize,
+    /// estimated firing rates in Hz
+    rates: Array1<f32>,
+    /// time in ms of the next slow update
+    next_update: u64,
+}
+
+impl Homeostasis {
+    pub fn new(params: HomeostasisParams, excitatory: usize, inhibitory: usize) -> Self {
+        Homeostasis {
+            params,
+            excitatory,
+            // start at the target so neurons aren't pushed around before there's an estimate
+            rates: Array1::from_elem(excitatory + inhibitory, params.target_rate),
+            next_update: params.update_interval,
+        }
+    }
+
+    /// Updates the rate estimates with this step's spikes and, every `update_interval` ms,
+    /// moves the weights and neuron parameters towards the target rate
+    pub fn step(
+        &mut self,
+        time: u64,
+        spikes: &ArrayView1<bool>,
+        neurons: &mut Array1<Izhikevich>,
+        connections: &mut Array2<f32>,
+    ) {
+        let tau_rate = self.params.tau_rate;
+        Zip::from(&mut self.rates)
+            .and(spikes)
+            .for_each(|rate, &spiked| {
+                // a spike in a 1ms step is an instantaneous rate of 1000Hz
+                let instantaneous = if spiked { 1000.0 } else { 0.0 };
+                *rate += (instantaneous - *rate) / tau_rate;
+            });
+
+        if time < self.next_update {
+            return;
+        }
+        self.next_update = time + self.params.update_interval;
+
+        let interval = self.params.update_interval as f32;
+        let target = self.params.target_rate;
+        let factor = |tau: f32, rate: f32, sign: f32| {
+            (1.0 + sign * (interval / tau) * (target - rate) / target)
+                .clamp(MIN_HOMEOSTATIC_FACTOR, MAX_HOMEOSTATIC_FACTOR)
+        };
+
+        if let Some(tau) = self.params.tau_scaling {
+            // only excitatory weights are scaled, rows are the postsynaptic neurons
+            Zip::from(connections.slice_mut(s![.., ..self.excitatory]).rows_mut())
+                .and(&self.rates)
+                .par_for_each(|mut weights, &rate| weights *= factor(tau, rate, 1.0));
+        }
+
+        if let Some(tau) = self.params.tau_intrinsic {
+            // a larger reset of u means more adaptation after each spike so a lower rate
+            Zip::from(neurons)
+                .and(&self.rates)
+                .for_each(|neuron, &rate| neuron.u_reset *= factor(tau, rate, -1.0));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// two excitatory neurons connected both ways and an inhibitory one, no self connections
+    fn connections() -> Array2<f32> {
+        array![[0.0, 0.5, -1.0], [0.5, 0.0, -1.0], [0.5, 0.5, 0.0]]
+    }
+
+    /// runs R-STDP for `steps` ms with the given neurons spiking at the given times
+    fn run(
+        rstdp: &mut RewardStdp,
+        connections: &mut Array2<f32>,
+        spikes: &[(u64, usize)],
+        steps: u64,
+    ) {
+        for time in 0..steps {
+            let mut step = Array1::from_elem(3, false);
+            for &(_, neuron) in spikes.iter().filter(|(t, _)| *t == time) {
+                step[neuron] = true;
+            }
+            rstdp.step(&step.view(), connections);
+        }
+    }
+
+    #[test]
+    fn stdp_sets_the_eligibility_sign() {
+        let mut connections = connections();
+        let mut rstdp = RewardStdp::new(RewardStdpParams::default(), 2, &connections);
+        // 0 fires 5ms before 1
+        run(&mut rstdp, &mut connections, &[(0, 0), (5, 1)], 10);
+
+        let params = RewardStdpParams::default();
+        let decay = |ms: f32, tau: f32| (-ms / tau).exp();
+        let causal = params.a_plus * decay(5.0, params.tau_plus) * decay(4.0, 1000.0);
+        let acausal = -params.a_minus * decay(5.0, params.tau_minus) * decay(4.0, 1000.0);
+        assert!((rstdp.eligibility(0, 1) - causal).abs() < 1e-5);
+        assert!((rstdp.eligibility(1, 0) - acausal).abs() < 1e-5);
+        // the inhibitory neuron didn't fire so its inputs aren't eligible
+        assert_eq!(rstdp.eligibility(0, 2), 0.0);
+    }
+
+    #[test]
+    fn weights_only_change_with_dopamine() {
+        let mut connections = connections();
+        let mut rstdp = RewardStdp::new(RewardStdpParams::default(), 2, &connections);
+        run(&mut rstdp, &mut connections, &[(0, 0), (5, 1)], 10);
+        assert_eq!(connections, self::connections());
+
+        rstdp.reward(1.0);
+        run(&mut rstdp, &mut connections, &[], 100);
+        assert!(connections[[1, 0]] > 0.5);
+        assert!(connections[[0, 1]] < 0.5);
+        // the inhibitory columns aren't plastic
+        assert_eq!(connections.column(2), self::connections().column(2));
+    }
+
+    #[test]
+    fn weights_stay_in_bounds() {
+        let params = RewardStdpParams {
+            learning_rate: 10.0,
+            ..RewardStdpParams::default()
+        };
+        let mut connections = connections();
+        let mut rstdp = RewardStdp::new(params, 2, &connections);
+        rstdp.reward(10.0);
+        run(&mut rstdp, &mut connections, &[(0, 0), (5, 1)], 50);
+
+        assert_eq!(connections[[1, 0]], params.max_weight);
+        assert_eq!(connections[[0, 1]], 0.0);
+    }
+
+    #[test]
+    fn only_existing_synapses_learn() {
+        let mut connections = connections();
+        connections[[1, 0]] = 0.0;
+        let mut rstdp = RewardStdp::new(RewardStdpParams::default(), 2, &connections);
+        rstdp.reward(1.0);
+        // every neuron fires together and then in sequence
+        let spikes = [(0, 0), (0, 1), (0, 2), (3, 0), (6, 1), (9, 0), (12, 1)];
+        run(&mut rstdp, &mut connections, &spikes, 100);
+
+        for neuron in 0..3 {
+            assert_eq!(connections[[neuron, neuron]], 0.0);
+        }
+        assert_eq!(connections[[1, 0]], 0.0);
+        assert_eq!(rstdp.eligibility(0, 1), 0.0);
+        assert_ne!(connections[[0, 1]], 0.5);
+    }
+
+    #[test]
+    fn distal_reward_picks_an_existing_synapse() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..10 {
+            let mut connections = connections();
+            let experiment = DistalReward::new(2, &mut connections, &mut rng).unwrap();
+            assert!(
+                (experiment.pre, experiment.post) == (0, 1)
+                    || (experiment.pre, experiment.post) == (1, 0)
+            );
+            assert_eq!(connections[[experiment.post, experiment.pre]], 0.0);
+        }
+
+        let mut unconnected = connections();
+        unconnected.slice_mut(s![..2, ..2]).fill(0.0);
+        assert!(DistalReward::new(2, &mut unconnected, &mut rng).is_none());
+        assert!(DistalReward::new(0, &mut connections(), &mut rng).is_none());
+    }
+
+    #[test]
+    fn distal_rewards_come_1_to_3s_later() {
+        let mut rng = StdRng::seed_from_u64(2);
+        for _ in 0..20 {
+            let mut connections = connections();
+            let mut experiment = DistalReward::new(2, &mut connections, &mut rng).unwrap();
+            let mut spikes = Array1::from_elem(3, false);
+
+            let mut rewards = Vec::new();
+            for time in 0..4000 {
+                spikes.fill(false);
+                if time == 100 {
+                    spikes[experiment.pre] = true;
+                }
+                // paired within the window, a second pairing while a reward is pending is
+                // ignored
+                if time == 105 || time == 500 {
+                    spikes[experiment.post] = true;
+                }
+                if time == 495 {
+                    spikes[experiment.pre] = true;
+                }
+                if experiment.step(time, &spikes.view(), &mut rng) {
+                    rewards.push(time);
+                }
+            }
+            assert_eq!(rewards.len(), 1);
+            assert!(
+                (1105..=3105).contains(&rewards[0]),
+                "reward at {}",
+                rewards[0]
+            );
+        }
+    }
+
+    #[test]
+    fn unpaired_spikes_are_not_rewarded() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut connections = connections();
+        let mut experiment = DistalReward::new(2, &mut connections, &mut rng).unwrap();
+        let mut spikes = Array1::from_elem(3, false);
+        for time in 0..5000 {
+            spikes.fill(false);
+            // the postsynaptic neuron fires too late after, or before, the presynaptic one
+            spikes[experiment.pre] = time % 100 == 0;
+            spikes[experiment.post] = time % 100 == 50 || time % 100 == 99;
+            assert!(!experiment.step(time, &spikes.view(), &mut rng));
+        }
+    }
+
+    fn homeostasis(tau_scaling: Option<f32>, tau_intrinsic: Option<f32>) -> Homeostasis {
+        let params = HomeostasisParams {
+            target_rate: 10.0,
+            tau_rate: 100.0,
+            update_interval: 1000,
+            tau_scaling,
+            tau_intrinsic,
+        };
+        Homeostasis::new(params, 2, 1)
+    }
+
+    fn neurons() -> Array1<Izhikevich> {
+        let neuron = Izhikevich {
+            decay_rate: 0.02,
+            sensitivity: 0.2,
+            v_reset: -65.0,
+            u_reset: 8.0,
+            v: -65.0,
+            u: -13.0,
+        };
+        Array1::from_elem(3, neuron)
+    }
+
+    /// runs homeostasis over `times` with neuron 0 silent, neuron 1 at 100Hz and
+    /// neuron 2 at the 10Hz target
+    fn regulate(
+        homeostasis: &mut Homeostasis,
+        times: std::ops::RangeInclusive<u64>,
+        neurons: &mut Array1<Izhikevich>,
+        connections: &mut Array2<f32>,
+    ) {
+        for time in times {
+            let spikes = array![false, time % 10 == 5, time % 100 == 50];
+            homeostasis.step(time, &spikes.view(), neurons, connections);
+        }
+    }
+
+    #[test]
+    fn scaling_moves_towards_the_target_rate() {
+        let mut homeostasis = homeostasis(Some(100_000.0), None);
+        let mut neurons = neurons();
+        let mut connections = connections();
+
+        regulate(&mut homeostasis, 0..=999, &mut neurons, &mut connections);
+        assert_eq!(connections, self::connections());
+
+        regulate(
+            &mut homeostasis,
+            1000..=1000,
+            &mut neurons,
+            &mut connections,
+        );
+        // the silent neuron's excitatory inputs grow and the busy neuron's shrink
+        assert!(connections[[0, 1]] > 0.5 && connections[[0, 1]] <= 0.5 * 1.01);
+        assert!(connections[[1, 0]] < 0.5 && connections[[1, 0]] > 0.5 * 0.9);
+        // the neuron firing at the target is barely touched
+        assert!((connections[[2, 0]] - 0.5).abs() < 0.5 * 0.01);
+        // inhibitory weights and neuron parameters aren't scaled
+        assert_eq!(connections.column(2), self::connections().column(2));
+        assert_eq!(neurons[0].u_reset, 8.0);
+    }
+
+    #[test]
+    fn scaling_factors_are_clamped() {
+        let mut homeostasis = homeostasis(Some(1.0), None);
+        let mut neurons = neurons();
+        let mut connections = connections();
+        regulate(&mut homeostasis, 0..=1000, &mut neurons, &mut connections);
+
+        assert_eq!(connections[[0, 1]], 0.5 * MAX_HOMEOSTATIC_FACTOR);
+        assert_eq!(connections[[1, 0]], 0.5 * MIN_HOMEOSTATIC_FACTOR);
+    }
+
+    #[test]
+    fn intrinsic_plasticity_adapts_the_reset() {
+        let mut homeostasis = homeostasis(None, Some(100_000.0));
+        let mut neurons = neurons();
+        let mut connections = connections();
+        regulate(&mut homeostasis, 0..=1000, &mut neurons, &mut connections);
+
+        // less adaptation for the silent neuron and more for the busy one
+        assert!(neurons[0].u_reset < 8.0);
+        assert!(neurons[1].u_reset > 8.0);
+        assert_eq!(connections, self::connections());
+
+        let mut clamped = self::homeostasis(None, Some(1.0));
+        let mut neurons = self::neurons();
+        regulate(&mut clamped, 0..=1000, &mut neurons, &mut connections);
+        assert_eq!(neurons[0].u_reset, 8.0 * MIN_HOMEOSTATIC_FACTOR);
+        assert_eq!(neurons[1].u_reset, 8.0 * MAX_HOMEOSTATIC_FACTOR);
+    }
+}
@@ -0,0 +1,204 @@
+use std::path::Path;
+
+use plotters::coord::Shift;
+use plotters::prelude::*;
+
+use super::analysis::correlogram::{Histogram, TriggeredAverage};
+use super::analysis::polychrony::PolychronousGroup;
+
+/// Size of the images written by the offline tools
+const SIZE: (u32, u32) = (1200, 900);
+
+/// Runs the drawing closure on an SVG backend for `.svg` paths and a bitmap backend for anything
+/// else, the closure is expanded for each so it can take either drawing area. Images are `SIZE`
+/// unless another size is given.
+macro_rules! render {
+    ($path:expr, $draw:expr) => {
+        render!($path, SIZE, $draw)
+    };
+    ($path:expr, $size:expr, $draw:expr) => {{
+        let path: &Path = $path;
+        if path.extension().is_some_and(|e| e == "svg") {
+            let root = SVGBackend::new(path, $size).into_drawing_area();
+            root.fill(&WHITE).expect("error filling plot background");
+            ($draw)(&root);
+            root.present().expect("error writing plot");
+        } else {
+            let root = BitMapBackend::new(path, $size).into_drawing_area();
+            root.fill(&WHITE).expect("error filling plot background");
+            ($draw)(&root);
+            root.present().expect("error writing plot");
+        }
+    }};
+}
+pub(crate) use render;
+
+fn neuron_color(neuron: usize, excitatory: usize) -> RGBColor {
+    if neuron < excitatory {
+        RED
+    } else {
+        BLUE
+    }
+}
+
+/// Draws up to 9 groups as time locked spike patterns, each spike joined to the spikes that
+/// caused it
+pub fn polychronous_groups(path: &Path, groups: &[PolychronousGroup], excitatory: usize) {
+    render!(path, |root: &DrawingArea<_, Shift>| {
+        let shown = groups.len().min(9);
+        let columns = (shown as f64).sqrt().ceil().max(1.0) as usize;
+        let rows = shown.div_ceil(columns).max(1);
+        let panels = root.split_evenly((rows, columns));
+
+        for ((index, group), panel) in groups.iter().enumerate().take(shown).zip(&panels) {
+            draw_group(panel, index, group, excitatory);
+        }
+    });
+}
+
+fn draw_group<DB: DrawingBackend>(
+    area: &DrawingArea<DB, Shift>,
+    index: usize,
+    group: &PolychronousGroup,
+    excitatory: usize,
+) {
+    let lowest = group.spikes.iter().map(|s| s.neuron).min().unwrap_or(0) as i32;
+    let highest = group.spikes.iter().map(|s| s.neuron).max().unwrap_or(0) as i32;
+
+    let mut chart = ChartBuilder::on(area)
+        .caption(
+            format!(
+                "group {}: {} spikes, {} layers, {}ms",
+                index,
+                group.size(),
+                group.length(),
+                group.span()
+            ),
+            ("sans-serif", 14),
+        )
+        .margin(5)
+        .x_label_area_size(25)
+        .y_label_area_size(35)
+        .build_cartesian_2d(-1i32..group.span() as i32 + 2, lowest - 1..highest + 2)
+        .expect("error building chart");
+    chart
+        .configure_mesh()
+        .x_desc("ms")
+        .y_desc("neuron")
+        .draw()
+        .expect("error drawing mesh");
+
+    for spike in &group.spikes {
+        for &cause in &spike.causes {
+            let from = &group.spikes[cause];
+            chart
+                .draw_series(LineSeries::new(
+                    [
+                        (from.time as i32, from.neuron as i32),
+                        (spike.time as i32, spike.neuron as i32),
+                    ],
+                    &BLACK.mix(0.3),
+                ))
+                .expect("error drawing connection");
+        }
+    }
+    chart
+        .draw_series(group.spikes.iter().map(|spike| {
+            Circle::new(
+                (spike.time as i32, spike.neuron as i32),
+                3,
+                neuron_color(spike.neuron, excitatory).filled(),
+            )
+        }))
+        .expect("error drawing spikes");
+}
+
+/// Draws a histogram's rates as bars
+pub fn histogram(path: &Path, caption: &str, histogram: &Histogram) {
+    let rates = histogram.rates();
+    let left = histogram.start as f64;
+    let right = left + (histogram.bin as usize * rates.len()) as f64;
+    let top = rates.iter().copied().fold(0.0, f64::max).max(f64::EPSILON) * 1.1;
+
+    render!(path, |root: &DrawingArea<_, Shift>| {
+        let mut chart = ChartBuilder::on(root)
+            .caption(caption, ("sans-serif", 20))
+            .margin(10)
+            .x_label_area_size(40)
+            .y_label_area_size(60)
+            .build_cartesian_2d(left..right, 0.0..top)
+            .expect("error building chart");
+        chart
+            .configure_mesh()
+            .x_desc("ms")
+            .y_desc("Hz")
+            .draw()
+            .expect("error drawing mesh");
+        chart
+            .draw_series(histogram.offsets().zip(&rates).map(|(offset, rate)| {
+                let offset = offset as f64;
+                Rectangle::new(
+                    [(offset, 0.0), (offset + histogram.bin as f64, *rate)],
+                    BLUE.mix(0.6).filled(),
+                )
+            }))
+            .expect("error drawing bars");
+    });
+}
+
+/// Draws a triggered average with a band of one standard deviation either side
+pub fn triggered_average(path: &Path, caption: &str, average: &TriggeredAverage) {
+    let points: Vec<(f64, f64, f64)> = average
+        .mean
+        .iter()
+        .zip(&average.std)
+        .enumerate()
+        .map(|(i, (mean, std))| ((average.start + i as i64) as f64, *mean, *std))
+        .collect();
+    let left = average.start as f64;
+    let right = left + points.len().saturating_sub(1).max(1) as f64;
+    let bottom = points
+        .iter()
+        .map(|(_, m, s)| m - s)
+        .fold(f64::MAX, f64::min);
+    let top = points
+        .iter()
+        .map(|(_, m, s)| m + s)
+        .fold(f64::MIN, f64::max);
+    let (bottom, top) = if bottom < top {
+        (bottom, top)
+    } else {
+        (bottom - 1.0, bottom + 1.0)
+    };
+
+    render!(path, |root: &DrawingArea<_, Shift>| {
+        let mut chart = ChartBuilder::on(root)
+            .caption(caption, ("sans-serif", 20))
+            .margin(10)
+            .x_label_area_size(40)
+            .y_label_area_size(60)
+            .build_cartesian_2d(left..right, bottom..top)
+            .expect("error building chart");
+        chart
+            .configure_mesh()
+            .x_desc("ms from spike")
+            .draw()
+            .expect("error drawing mesh");
+        chart
+            .draw_series(std::iter::once(Polygon::new(
+                points
+                    .iter()
+                    .map(|(t, m, s)| (*t, m + s))
+                    .chain(points.iter().rev().map(|(t, m, s)| (*t, m - s)))
+                    .collect::<Vec<_>>(),
+                BLUE.mix(0.2).filled(),
+            )))
+            .expect("error drawing deviation");
+        chart
+            .draw_series(LineSeries::new(
+                points.iter().map(|(t, m, _)| (*t, *m)),
+                &BLUE,
+            ))
+            .expect("error drawing average");
+    });
+}
@@ -0,0 +1,319 @@
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use ndarray::Array2;
+
+use crate::analysis::avalanche;
+use crate::analysis::avalanche::{Avalanche, AvalancheDetector, AvalancheStats};
+use crate::izhikevich::Izhikevich;
+
+/// Everything a backend reports about a single simulation step
+#[derive(Debug, Clone)]
+pub struct StepFrame {
+    /// time of the step in ms since the simulation started
+    pub time: u64,
+    /// voltage of neuron 0
+    pub voltage: f32,
+    /// mean membrane potential of every neuron, a rough proxy for the local field potential
+    pub lfp: f32,
+    /// whether each neuron spiked during the step
+    pub spikes: Vec<bool>,
+    /// state of the neurons picked in the UI
+    pub probes: Vec<Probe>,
+    /// every connection weight, only on the steps the UI asked for a snapshot of them
+    pub connections: Option<Array2<f32>>,
+}
+
+/// State of a probed neuron after a step
+#[derive(Debug, Copy, Clone)]
+pub struct Probe {
+    pub neuron: usize,
+    /// its parameters along with the membrane potential and recovery variable
+    pub state: Izhikevich,
+    /// total input current, thalamic and synaptic, at the end of the step
+    pub input: f32,
+}
+
+impl StepFrame {
+    /// indices of the neurons that spiked
+    pub fn spike_indices(&self) -> impl Iterator<Item = usize> + '_ {
+        self.spikes
+            .iter()
+            .enumerate()
+            .filter(|(_i, &s)| s)
+            .map(|(i, _s)| i)
+    }
+}
+
+/// Writes spikes as they happen to a CSV file with a row of `time_ms,neuron` for each spike.
+/// The population sizes go in a comment at the top so the file can be analysed on its own.
+pub struct SpikeRecorder {
+    out: BufWriter<File>,
+}
+
+impl SpikeRecorder {
+    pub fn create(path: &Path, excitatory: usize, inhibitory: usize) -> io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        writeln!(out, "# excitatory={} inhibitory={}", excitatory, inhibitory)?;
+        writeln!(out, "time_ms,neuron")?;
+        Ok(SpikeRecorder { out })
+    }
+
+    pub fn record(&mut self, frame: &StepFrame) -> io::Result<()> {
+        for neuron in frame.spike_indices() {
+            writeln!(self.out, "{},{}", frame.time, neuron)?;
+        }
+        // the simulation runs until the window is closed so nothing gets a chance to flush on exit
+        self.out.flush()
+    }
+}
+
+/// Writes a value from every step to a CSV file as `time_ms,<name>`, like the LFP proxy or a
+/// neuron's voltage
+pub struct SignalRecorder {
+    out: BufWriter<File>,
+    signal: fn(&StepFrame) -> f32,
+}
+
+impl SignalRecorder {
+    pub fn create(path: &Path, name: &str, signal: fn(&StepFrame) -> f32) -> io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        writeln!(out, "time_ms,{}", name)?;
+        Ok(SignalRecorder { out, signal })
+    }
+
+    pub fn record(&mut self, frame: &StepFrame) -> io::Result<()> {
+        writeln!(self.out, "{},{}", frame.time, (self.signal)(frame))?;
+        // flushed every step for the same reason as the spikes
+        self.out.flush()
+    }
+}
+
+/// Detects avalanches in the live spikes and writes each one to a CSV file as it ends, logging
+/// the fitted exponents every `REPORT_INTERVAL` ms
+pub struct AvalancheRecorder {
+    out: BufWriter<File>,
+    detector: AvalancheDetector,
+    bin: u64,
+    avalanches: Vec<Avalanche>,
+}
+
+impl AvalancheRecorder {
+    const REPORT_INTERVAL: u64 = 10_000;
+
+    pub fn create(path: &Path, bin: u64, threshold: u64) -> io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        avalanche::write_header(&mut out)?;
+        Ok(AvalancheRecorder {
+            out,
+            detector: AvalancheDetector::new(bin, threshold),
+            bin,
+            avalanches: Vec::new(),
+        })
+    }
+
+    pub fn record(&mut self, frame: &StepFrame) -> io::Result<()> {
+        let spikes = frame.spike_indices().count() as u64;
+        if let Some(found) = self.detector.push(frame.time, spikes) {
+            avalanche::write_row(&mut self.out, &found)?;
+            self.out.flush()?;
+            self.avalanches.push(found);
+        }
+
+        if frame.time % Self::REPORT_INTERVAL == Self::REPORT_INTERVAL - 1 {
+            log::info!(
+                "at {}ms: {}",
+                frame.time + 1,
+                AvalancheStats::compute(&self.avalanches, self.bin, 1)
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Loads the values of a file written by `SignalRecorder` in time order
+pub fn load_signal(path: &Path) -> Result<Vec<f64>, RecordingError> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut samples = Vec::new();
+
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with("time") {
+            continue;
+        }
+        let parse_err = |message: &str| RecordingError::Parse {
+            line: index + 1,
+            message: message.to_string(),
+        };
+
+        let (time, value) = line
+            .split_once(',')
+            .ok_or_else(|| parse_err("expected time_ms,value"))?;
+        let time = time
+            .trim()
+            .parse::<u64>()
+            .map_err(|_| parse_err("invalid time"))?;
+        let value = value
+            .trim()
+            .parse::<f64>()
+            .map_err(|_| parse_err("invalid value"))?;
+        samples.push((time, value));
+    }
+
+    samples.sort_by_key(|(time, _)| *time);
+    Ok(samples.into_iter().map(|(_, value)| value).collect())
+}
+
+/// Loads event times in ms from the first column of a file, one event per line
+pub fn load_events(path: &Path) -> Result<Vec<u64>, RecordingError> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut events = Vec::new();
+
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with("time") {
+            continue;
+        }
+        let time = line.split(',').next().unwrap_or_default().trim();
+        events.push(time.parse::<u64>().map_err(|_| RecordingError::Parse {
+            line: index + 1,
+            message: "invalid event time".to_string(),
+        })?);
+    }
+
+    events.sort_unstable();
+    Ok(events)
+}
+
+/// Spikes loaded from a file written by `SpikeRecorder`
+#[derive(Debug, Clone)]
+pub struct SpikeRecording {
+    pub excitatory: usize,
+    pub inhibitory: usize,
+    /// length of the recording in ms
+    pub duration: u64,
+    /// spike times in ms for each neuron, in order
+    pub trains: Vec<Vec<u64>>,
+}
+
+impl SpikeRecording {
+    pub fn len(&self) -> usize {
+        self.excitatory + self.inhibitory
+    }
+
+    /// Loads a spike file, the duration defaults to just after the last spike when `None`.
+    /// Files without the population comment are treated as all excitatory.
+    pub fn load(path: &Path, duration: Option<u64>) -> Result<Self, RecordingError> {
+        let reader = BufReader::new(File::open(path)?);
+        let mut populations = None;
+        let mut spikes = Vec::new();
+
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            let parse_err = |message: &str| RecordingError::Parse {
+                line: index + 1,
+                message: message.to_string(),
+            };
+
+            if let Some(comment) = line.strip_prefix('#') {
+                populations = parse_populations(comment).or(populations);
+                continue;
+            }
+            if line.is_empty() || line.starts_with("time") {
+                continue;
+            }
+
+            let (time, neuron) = line
+                .split_once(',')
+                .ok_or_else(|| parse_err("expected time_ms,neuron"))?;
+            let time = time
+                .trim()
+                .parse::<u64>()
+                .map_err(|_| parse_err("invalid spike time"))?;
+            let neuron = neuron
+                .trim()
+                .parse::<usize>()
+                .map_err(|_| parse_err("invalid neuron index"))?;
+            spikes.push((time, neuron));
+        }
+
+        let highest = spikes
+            .iter()
+            .map(|(_, neuron)| neuron + 1)
+            .max()
+            .unwrap_or(0);
+        let (excitatory, inhibitory) = populations.unwrap_or((highest, 0));
+        if highest > excitatory + inhibitory {
+            return Err(RecordingError::Neuron {
+                neuron: highest - 1,
+                neurons: excitatory + inhibitory,
+            });
+        }
+
+        let last = spikes.iter().map(|(time, _)| time + 1).max().unwrap_or(0);
+        let duration = duration.unwrap_or(last);
+
+        let mut trains = vec![Vec::new(); excitatory + inhibitory];
+        for (time, neuron) in spikes {
+            if time < duration {
+                trains[neuron].push(time);
+            }
+        }
+        for train in &mut trains {
+            train.sort_unstable();
+        }
+
+        Ok(SpikeRecording {
+            excitatory,
+            inhibitory,
+            duration,
+            trains,
+        })
+    }
+}
+
+fn parse_populations(comment: &str) -> Option<(usize, usize)> {
+    let mut excitatory = None;
+    let mut inhibitory = None;
+    for field in comment.split_whitespace() {
+        match field.split_once('=') {
+            Some(("excitatory", n)) => excitatory = n.parse().ok(),
+            Some(("inhibitory", n)) => inhibitory = n.parse().ok(),
+            _ => {}
+        }
+    }
+    Some((excitatory?, inhibitory?))
+}
+
+#[derive(Debug)]
+pub enum RecordingError {
+    Io(io::Error),
+    Parse { line: usize, message: String },
+    Neuron { neuron: usize, neurons: usize },
+}
+
+impl From<io::Error> for RecordingError {
+    fn from(err: io::Error) -> Self {
+        RecordingError::Io(err)
+    }
+}
+
+impl fmt::Display for RecordingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RecordingError::Io(err) => write!(f, "{}", err),
+            RecordingError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+            RecordingError::Neuron { neuron, neurons } => write!(
+                f,
+                "spike from neuron {} but the recording only has {} neurons",
+                neuron, neurons
+            ),
+        }
+    }
+}
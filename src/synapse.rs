@@ -0,0 +1,617 @@
+use std::fmt;
+use std::str::FromStr;
+
+use zerocopy::{AsBytes, FromBytes, FromZeroes};
+
+/// Receptor types with the kinetics used in Izhikevich & Edelman (2008)
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Receptor {
+    Ampa,
+    Nmda,
+    GabaA,
+    GabaB,
+}
+
+impl Receptor {
+    /// decay time constant of the conductance in ms
+    pub fn tau(self) -> f32 {
+        match self {
+            Receptor::Ampa => 5.0,
+            Receptor::Nmda => 150.0,
+            Receptor::GabaA => 6.0,
+            Receptor::GabaB => 150.0,
+        }
+    }
+
+    /// reversal potential in mV
+    pub fn reversal(self) -> f32 {
+        match self {
+            Receptor::Ampa | Receptor::Nmda => 0.0,
+            Receptor::GabaA => -70.0,
+            Receptor::GabaB => -90.0,
+        }
+    }
+}
+
+/// How the summed weights of presynaptic spikes along a projection reach the postsynaptic neuron
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SynapseKind {
+    /// added directly to the input current for a single step, as in Izhikevich (2003)
+    Delta,
+    /// current jumps by the weight then decays with time constant `tau` (ms)
+    Exponential { tau: f32 },
+    /// alpha function current peaking at the weight `tau` ms after the spike
+    Alpha { tau: f32 },
+    /// difference of exponentials normalized to peak at the weight
+    BiExponential { tau_rise: f32, tau_decay: f32 },
+    /// treated as a conductance increment for the receptor, sign is ignored since the reversal
+    /// potential decides whether it excites or inhibits
+    Conductance(Receptor),
+}
+
+impl SynapseKind {
+    /// encoding used for the synapse kinds in the compute shader's config
+    pub fn shader_code(self) -> u32 {
+        match self {
+            SynapseKind::Delta => 0,
+            SynapseKind::Conductance(Receptor::Ampa) => 1,
+            SynapseKind::Conductance(Receptor::Nmda) => 2,
+            SynapseKind::Conductance(Receptor::GabaA) => 3,
+            SynapseKind::Conductance(Receptor::GabaB) => 4,
+            SynapseKind::Exponential { .. } => 5,
+            SynapseKind::Alpha { .. } => 6,
+            SynapseKind::BiExponential { .. } => 7,
+        }
+    }
+
+    /// rise and decay time constants passed to the compute shader alongside `shader_code`
+    pub fn shader_taus(self) -> (f32, f32) {
+        match self {
+            SynapseKind::Exponential { tau } | SynapseKind::Alpha { tau } => (0.0, tau),
+            SynapseKind::BiExponential {
+                tau_rise,
+                tau_decay,
+            } => (tau_rise, tau_decay),
+            _ => (0.0, 0.0),
+        }
+    }
+}
+
+/// Scale applied to a spike's weight so a bi-exponential current peaks at the weight
+pub fn bi_exponential_norm(tau_rise: f32, tau_decay: f32) -> f32 {
+    let peak_time = (tau_rise * tau_decay / (tau_decay - tau_rise)) * (tau_decay / tau_rise).ln();
+    1.0 / ((-peak_time / tau_decay).exp() - (-peak_time / tau_rise).exp())
+}
+
+impl FromStr for SynapseKind {
+    type Err = ParseSynapseError;
+
+    /// Parses `delta`, a receptor name, or a current kernel with its time constants in ms
+    /// separated by colons, i.e. `exp:5`, `alpha:2` or `biexp:1:5`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseSynapseError {
+            input: s.to_string(),
+            expected: "one of delta, ampa, nmda, gaba-a, gaba-b, exp:<tau>, alpha:<tau> or \
+                biexp:<tau rise>:<tau decay>",
+        };
+        let lower = s.to_lowercase();
+        let mut parts = lower.split(':');
+        let name = parts.next().unwrap_or_default();
+        let taus = parts
+            .map(|tau| tau.parse::<f32>().ok().filter(|tau| *tau > 0.0))
+            .collect::<Option<Vec<f32>>>()
+            .ok_or_else(err)?;
+
+        match (name, taus.as_slice()) {
+            ("delta", []) => Ok(SynapseKind::Delta),
+            ("ampa", []) => Ok(SynapseKind::Conductance(Receptor::Ampa)),
+            ("nmda", []) => Ok(SynapseKind::Conductance(Receptor::Nmda)),
+            ("gaba-a" | "gaba_a" | "gabaa", []) => Ok(SynapseKind::Conductance(Receptor::GabaA)),
+            ("gaba-b" | "gaba_b" | "gabab", []) => Ok(SynapseKind::Conductance(Receptor::GabaB)),
+            ("exp", &[tau]) => Ok(SynapseKind::Exponential { tau }),
+            ("alpha", &[tau]) => Ok(SynapseKind::Alpha { tau }),
+            ("biexp", &[tau_rise, tau_decay]) if tau_rise < tau_decay => {
+                Ok(SynapseKind::BiExponential {
+                    tau_rise,
+                    tau_decay,
+                })
+            }
+            _ => Err(err()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ParseSynapseError {
+    input: String,
+    expected: &'static str,
+}
+
+impl fmt::Display for ParseSynapseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "invalid value \"{}\", expected {}",
+            self.input, self.expected
+        )
+    }
+}
+
+/// Parameters of Tsodyks-Markram short-term plasticity, see Tsodyks, Pawelzik & Markram (1998)
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ShortTermPlasticity {
+    /// utilization of the available resources by a spike, `U`
+    pub utilization: f32,
+    /// time constant for recovery from depression in ms
+    pub tau_rec: f32,
+    /// time constant for the decay of facilitation in ms, 0 disables facilitation
+    pub tau_facil: f32,
+}
+
+impl FromStr for ShortTermPlasticity {
+    type Err = ParseSynapseError;
+
+    /// Parses `U:tau_rec:tau_facil`, i.e. `0.5:800:0` for a depressing synapse
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseSynapseError {
+            input: s.to_string(),
+            expected: "<U>:<tau rec>:<tau facil> with U in (0, 1]",
+        };
+        let values = s
+            .split(':')
+            .map(|v| v.parse::<f32>().ok().filter(|v| *v >= 0.0))
+            .collect::<Option<Vec<f32>>>()
+            .ok_or_else(err)?;
+
+        match values.as_slice() {
+            &[utilization, tau_rec, tau_facil]
+                if utilization > 0.0 && utilization <= 1.0 && tau_rec > 0.0 =>
+            {
+                Ok(ShortTermPlasticity {
+                    utilization,
+                    tau_rec,
+                    tau_facil,
+                })
+            }
+            _ => Err(err()),
+        }
+    }
+}
+
+/// Short-term plasticity state of a presynaptic neuron, shared by all of its outgoing synapses
+#[derive(Debug, Copy, Clone, FromZeroes, FromBytes, AsBytes)]
+#[repr(C)]
+pub struct Resources {
+    /// fraction of the available resources used by the next spike
+    pub u: f32,
+    /// fraction of resources available
+    pub x: f32,
+}
+
+impl Default for Resources {
+    fn default() -> Self {
+        Resources { u: 0.0, x: 1.0 }
+    }
+}
+
+impl Resources {
+    /// Advances the state by one 1ms step and returns the fraction of the connection weights
+    /// that reaches the postsynaptic neurons, which is 0 when the neuron didn't spike and always
+    /// 1 when it did without short-term plasticity
+    pub fn release(&mut self, stp: Option<ShortTermPlasticity>, spiked: bool) -> f32 {
+        let stp = match stp {
+            Some(stp) => stp,
+            None if spiked => return 1.0,
+            None => return 0.0,
+        };
+
+        let released = if spiked {
+            self.u += stp.utilization * (1.0 - self.u);
+            let released = self.u * self.x;
+            self.x -= released;
+            released
+        } else {
+            0.0
+        };
+
+        self.x += (1.0 - self.x) * (1.0 - (-1.0 / stp.tau_rec).exp());
+        self.u = if stp.tau_facil > 0.0 {
+            self.u * (-1.0 / stp.tau_facil).exp()
+        } else {
+            0.0
+        };
+
+        released
+    }
+}
+
+/// Connections out of one population of neurons
+#[derive(Debug, Copy, Clone)]
+pub struct Projection {
+    pub synapse: SynapseKind,
+    pub stp: Option<ShortTermPlasticity>,
+}
+
+impl Projection {
+    /// utilization, recovery and facilitation time constants passed to the compute shader,
+    /// all 0 without short-term plasticity
+    pub fn shader_stp(&self) -> (f32, f32, f32) {
+        match self.stp {
+            Some(stp) => (stp.utilization, stp.tau_rec, stp.tau_facil),
+            None => (0.0, 0.0, 0.0),
+        }
+    }
+}
+
+impl Default for Projection {
+    fn default() -> Self {
+        Projection {
+            synapse: SynapseKind::Delta,
+            stp: None,
+        }
+    }
+}
+
+/// Connections out of the excitatory and inhibitory populations
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Projections {
+    pub excitatory: Projection,
+    pub inhibitory: Projection,
+}
+
+/// Synaptic state of a single postsynaptic neuron
+#[derive(Debug, Default, Copy, Clone, FromZeroes, FromBytes, AsBytes)]
+#[repr(C)]
+pub struct Synapses {
+    pub g_ampa: f32,
+    pub g_nmda: f32,
+    pub g_gaba_a: f32,
+    pub g_gaba_b: f32,
+
+    // state of the current kernels for each projection, the first element is the current
+    // applied this step for exponential kernels, for alpha kernels it's the rising component
+    // and the current is the second element, and for bi-exponential kernels the current is
+    // their difference
+    pub excitatory_kernel: [f32; 2],
+    pub inhibitory_kernel: [f32; 2],
+}
+
+impl Synapses {
+    /// Takes the summed weights of last step's spikes from each population and returns the
+    /// current it produces this step that doesn't depend on the membrane potential
+    pub fn receive(&mut self, projections: &Projections, excitatory: f32, inhibitory: f32) -> f32 {
+        let (mut e_kernel, mut i_kernel) = (self.excitatory_kernel, self.inhibitory_kernel);
+        let current =
+            self.receive_projection(projections.excitatory.synapse, &mut e_kernel, excitatory)
+                + self.receive_projection(
+                    projections.inhibitory.synapse,
+                    &mut i_kernel,
+                    inhibitory,
+                );
+        self.excitatory_kernel = e_kernel;
+        self.inhibitory_kernel = i_kernel;
+        current
+    }
+
+    fn receive_projection(&mut self, kind: SynapseKind, kernel: &mut [f32; 2], weight: f32) -> f32 {
+        match kind {
+            SynapseKind::Delta => weight,
+            SynapseKind::Conductance(receptor) => {
+                *self.conductance_mut(receptor) += weight.abs();
+                0.0
+            }
+            SynapseKind::Exponential { tau } => {
+                kernel[0] = kernel[0] * (-1.0 / tau).exp() + weight;
+                kernel[0]
+            }
+            SynapseKind::Alpha { tau } => {
+                // exact integration of the alpha function's two coupled first order equations
+                let decay = (-1.0 / tau).exp();
+                kernel[1] = decay * (kernel[1] + kernel[0] / tau);
+                kernel[0] = decay * kernel[0] + std::f32::consts::E * weight;
+                kernel[1]
+            }
+            SynapseKind::BiExponential {
+                tau_rise,
+                tau_decay,
+            } => {
+                let scaled = bi_exponential_norm(tau_rise, tau_decay) * weight;
+                kernel[0] = kernel[0] * (-1.0 / tau_decay).exp() + scaled;
+                kernel[1] = kernel[1] * (-1.0 / tau_rise).exp() + scaled;
+                kernel[0] - kernel[1]
+            }
+        }
+    }
+
+    fn conductance_mut(&mut self, receptor: Receptor) -> &mut f32 {
+        match receptor {
+            Receptor::Ampa => &mut self.g_ampa,
+            Receptor::Nmda => &mut self.g_nmda,
+            Receptor::GabaA => &mut self.g_gaba_a,
+            Receptor::GabaB => &mut self.g_gaba_b,
+        }
+    }
+
+    /// Current flowing through the conductances at membrane potential `v`
+    pub fn current(&self, v: f32) -> f32 {
+        // voltage dependent magnesium block of the NMDA channel
+        let mg = ((v + 80.0) / 60.0).powi(2);
+        let nmda_block = mg / (1.0 + mg);
+
+        self.g_ampa * (Receptor::Ampa.reversal() - v)
+            + self.g_nmda * nmda_block * (Receptor::Nmda.reversal() - v)
+            + self.g_gaba_a * (Receptor::GabaA.reversal() - v)
+            + self.g_gaba_b * (Receptor::GabaB.reversal() - v)
+    }
+
+    /// Exponential decay of every conductance over one 1ms step, current kernels are advanced in
+    /// `receive` instead since they don't depend on the membrane potential
+    pub fn decay(&mut self) {
+        for receptor in [
+            Receptor::Ampa,
+            Receptor::Nmda,
+            Receptor::GabaA,
+            Receptor::GabaB,
+        ] {
+            *self.conductance_mut(receptor) *= (-1.0 / receptor.tau()).exp();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn projections(excitatory: SynapseKind, inhibitory: SynapseKind) -> Projections {
+        Projections {
+            excitatory: Projection {
+                synapse: excitatory,
+                stp: None,
+            },
+            inhibitory: Projection {
+                synapse: inhibitory,
+                stp: None,
+            },
+        }
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() <= 1e-5 * a.abs().max(b.abs()).max(1.0)
+    }
+
+    #[test]
+    fn conductances_decay_at_receptor_tau() {
+        for receptor in [
+            Receptor::Ampa,
+            Receptor::Nmda,
+            Receptor::GabaA,
+            Receptor::GabaB,
+        ] {
+            let kind = SynapseKind::Conductance(receptor);
+            let mut synapses = Synapses::default();
+            // the sign of the weight doesn't matter, the reversal potential does
+            let current = synapses.receive(&projections(kind, kind), 0.5, -0.25);
+            assert_eq!(current, 0.0);
+            assert!(close(*synapses.conductance_mut(receptor), 0.75));
+
+            for step in 1..=20 {
+                synapses.decay();
+                let expected = 0.75 * (-step as f32 / receptor.tau()).exp();
+                assert!(
+                    close(*synapses.conductance_mut(receptor), expected),
+                    "{:?} after {}ms",
+                    receptor,
+                    step
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn reversal_potentials_decide_the_sign() {
+        let at = |receptor: Receptor, v: f32| {
+            let mut synapses = Synapses::default();
+            *synapses.conductance_mut(receptor) = 1.0;
+            synapses.current(v)
+        };
+        // at rest AMPA and NMDA depolarize and both GABA receptors hyperpolarize
+        assert!(at(Receptor::Ampa, -65.0) > 0.0);
+        assert!(at(Receptor::Nmda, -65.0) > 0.0);
+        assert!(at(Receptor::GabaA, -65.0) < 0.0);
+        assert!(at(Receptor::GabaB, -65.0) < 0.0);
+        // between the two GABA reversal potentials only GABA-B still hyperpolarizes
+        assert!(at(Receptor::GabaA, -80.0) > 0.0);
+        assert!(at(Receptor::GabaB, -80.0) < 0.0);
+        // no current at the reversal potential
+        for receptor in [Receptor::Ampa, Receptor::GabaA, Receptor::GabaB] {
+            assert_eq!(at(receptor, receptor.reversal()), 0.0);
+        }
+        // above 0mV the excitatory receptors reverse
+        assert!(at(Receptor::Ampa, 20.0) < 0.0);
+    }
+
+    #[test]
+    fn nmda_magnesium_block() {
+        let nmda = Synapses {
+            g_nmda: 1.0,
+            ..Synapses::default()
+        };
+        // fully blocked at -80mV, half at -20mV where ((v + 80) / 60)^2 is 1, 4/5 at 40mV
+        assert_eq!(nmda.current(-80.0), 0.0);
+        assert!(close(nmda.current(-20.0), 0.5 * 20.0));
+        assert!(close(nmda.current(40.0), 0.8 * -40.0));
+        // the block lifts as the neuron depolarizes
+        let block = |v: f32| nmda.current(v) / (Receptor::Nmda.reversal() - v);
+        assert!(block(-70.0) < block(-50.0) && block(-50.0) < block(-30.0));
+    }
+
+    /// current on each step after a single spike of `weight` on the excitatory projection
+    fn response(kind: SynapseKind, weight: f32, steps: usize) -> Vec<f32> {
+        let projections = projections(kind, SynapseKind::Delta);
+        let mut synapses = Synapses::default();
+        (0..steps)
+            .map(|step| {
+                let input = if step == 0 { weight } else { 0.0 };
+                synapses.receive(&projections, input, 0.0)
+            })
+            .collect()
+    }
+
+    fn peak(current: &[f32]) -> (usize, f32) {
+        current
+            .iter()
+            .copied()
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap()
+    }
+
+    #[test]
+    fn exponential_decays_each_step() {
+        let tau = 5.0;
+        let current = response(SynapseKind::Exponential { tau }, 2.0, 30);
+        assert_eq!(current[0], 2.0);
+        for pair in current.windows(2) {
+            assert!(close(pair[1] / pair[0], (-1.0 / tau).exp()));
+        }
+    }
+
+    #[test]
+    fn alpha_peaks_at_weight_after_tau() {
+        for tau in [2.0, 5.0, 10.0] {
+            let current = response(SynapseKind::Alpha { tau }, 1.5, 100);
+            let (time, value) = peak(&current);
+            assert_eq!(time, tau as usize);
+            assert!(close(value, 1.5), "peak {} for tau {}", value, tau);
+            // w t / tau e^(1 - t / tau) on every step
+            for (t, value) in current.iter().enumerate() {
+                let t = t as f32;
+                assert!(close(*value, 1.5 * t / tau * (1.0 - t / tau).exp()));
+            }
+        }
+    }
+
+    #[test]
+    fn bi_exponential_peaks_at_weight() {
+        let (tau_rise, tau_decay): (f32, f32) = (2.0, 8.0);
+        let peak_time = tau_rise * tau_decay / (tau_decay - tau_rise) * (tau_decay / tau_rise).ln();
+        let norm = bi_exponential_norm(tau_rise, tau_decay);
+        // the continuous kernel peaks at exactly the weight
+        let at_peak = norm * ((-peak_time / tau_decay).exp() - (-peak_time / tau_rise).exp());
+        assert!(close(at_peak, 1.0));
+
+        let kind = SynapseKind::BiExponential {
+            tau_rise,
+            tau_decay,
+        };
+        let current = response(kind, 3.0, 100);
+        let (time, value) = peak(&current);
+        // sampled each ms so the highest step is the one nearest the peak and just under it
+        assert_eq!(time, peak_time.round() as usize);
+        assert!(value <= 3.0 && value > 0.99 * 3.0, "peak {}", value);
+        assert!(current.iter().all(|i| *i >= 0.0));
+    }
+
+    #[test]
+    fn kernels_add_up() {
+        // linear kernels, two spikes give the sum of their responses
+        let kind = SynapseKind::Alpha { tau: 4.0 };
+        let projections = projections(kind, kind);
+        let mut both = Synapses::default();
+        let mut excitatory = Synapses::default();
+        let mut inhibitory = Synapses::default();
+        for step in 0..20 {
+            let (e, i) = if step == 0 { (1.0, -0.5) } else { (0.0, 0.0) };
+            let sum =
+                excitatory.receive(&projections, e, 0.0) + inhibitory.receive(&projections, 0.0, i);
+            assert!(close(both.receive(&projections, e, i), sum));
+        }
+    }
+
+    #[test]
+    fn release_without_stp() {
+        let mut resources = Resources::default();
+        assert_eq!(resources.release(None, true), 1.0);
+        assert_eq!(resources.release(None, false), 0.0);
+        assert_eq!(resources.release(None, true), 1.0);
+    }
+
+    #[test]
+    fn pure_depression() {
+        let stp = Some(ShortTermPlasticity {
+            utilization: 0.5,
+            tau_rec: 800.0,
+            tau_facil: 0.0,
+        });
+        let mut resources = Resources::default();
+        let released: Vec<f32> = (0..10).map(|_| resources.release(stp, true)).collect();
+
+        // every spike uses U of what's left
+        assert!(close(released[0], 0.5));
+        assert!(released.windows(2).all(|pair| pair[1] < pair[0]));
+        for (i, r) in released.iter().enumerate() {
+            assert!(close(*r, 0.5 * resources_before(0.5, 800.0, i)));
+        }
+        // without facilitation u doesn't carry over between steps
+        assert_eq!(resources.u, 0.0);
+    }
+
+    /// x just before the `n`th spike of a train with a spike every step
+    fn resources_before(utilization: f32, tau_rec: f32, n: usize) -> f32 {
+        let recovery = 1.0 - (-1.0 / tau_rec).exp();
+        (0..n).fold(1.0, |x, _| {
+            let x = x * (1.0 - utilization);
+            x + (1.0 - x) * recovery
+        })
+    }
+
+    #[test]
+    fn facilitation() {
+        let stp = Some(ShortTermPlasticity {
+            utilization: 0.1,
+            tau_rec: 20.0,
+            tau_facil: 500.0,
+        });
+        let mut resources = Resources::default();
+        let mut utilization = Vec::new();
+        let mut released = Vec::new();
+        for step in 0..200 {
+            let spiked = step % 20 == 0;
+            let r = resources.release(stp, spiked);
+            if spiked {
+                released.push(r);
+                utilization.push(resources.u);
+            }
+        }
+        assert!(utilization.windows(2).all(|pair| pair[1] > pair[0]));
+        // the resources mostly recover between spikes so the release grows too
+        assert!(released.windows(2).all(|pair| pair[1] > pair[0]));
+        assert!(close(released[0], 0.1));
+    }
+
+    #[test]
+    fn resources_recover() {
+        let tau_rec = 100.0;
+        let stp = Some(ShortTermPlasticity {
+            utilization: 0.8,
+            tau_rec,
+            tau_facil: 0.0,
+        });
+        let mut resources = Resources::default();
+        resources.release(stp, true);
+        let depleted = resources.x;
+        assert!(depleted < 0.25);
+
+        for step in 1..=500 {
+            assert_eq!(resources.release(stp, false), 0.0);
+            let expected = 1.0 - (1.0 - depleted) * (-step as f32 / tau_rec).exp();
+            assert!(
+                close(resources.x, expected),
+                "x {} at {}ms",
+                resources.x,
+                step
+            );
+        }
+        assert!(resources.x > 0.99);
+    }
+}
@@ -0,0 +1,391 @@
+use std::fmt;
+use std::io;
+use std::io::Write;
+use std::str::FromStr;
+
+use ndarray::prelude::*;
+use rand::prelude::*;
+use rayon::prelude::*;
+
+use super::izhikevich;
+use super::network::Network;
+
+/// How neurons are placed in space
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Layout {
+    /// on the smallest square or cubic grid that holds every neuron, with excitatory and
+    /// inhibitory neurons randomly spread over the grid points
+    Grid,
+    /// uniformly at random in the volume
+    Random,
+}
+
+impl FromStr for Layout {
+    type Err = ParseTopologyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "grid" => Ok(Layout::Grid),
+            "random" => Ok(Layout::Random),
+            _ => Err(ParseTopologyError {
+                input: s.to_string(),
+                expected: "grid or random",
+            }),
+        }
+    }
+}
+
+/// How a property of a connection falls off with the distance between the neurons, 1 at a
+/// distance of 0
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Profile {
+    Gaussian { sigma: f32 },
+    Exponential { length: f32 },
+}
+
+impl Profile {
+    pub fn at(self, distance: f32) -> f32 {
+        match self {
+            Profile::Gaussian { sigma } => (-distance.powi(2) / (2.0 * sigma.powi(2))).exp(),
+            Profile::Exponential { length } => (-distance / length).exp(),
+        }
+    }
+}
+
+impl FromStr for Profile {
+    type Err = ParseTopologyError;
+
+    /// Parses `gaussian:<sigma>` or `exponential:<length>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseTopologyError {
+            input: s.to_string(),
+            expected: "gaussian:<sigma> or exponential:<length>",
+        };
+        let lower = s.to_lowercase();
+        let (name, scale) = lower.split_once(':').ok_or_else(err)?;
+        let scale = scale
+            .parse::<f32>()
+            .ok()
+            .filter(|scale| *scale > 0.0)
+            .ok_or_else(err)?;
+
+        match name {
+            "gaussian" => Ok(Profile::Gaussian { sigma: scale }),
+            "exponential" | "exp" => Ok(Profile::Exponential { length: scale }),
+            _ => Err(err()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ParseTopologyError {
+    input: String,
+    expected: &'static str,
+}
+
+impl fmt::Display for ParseTopologyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "invalid value \"{}\", expected {}",
+            self.input, self.expected
+        )
+    }
+}
+
+/// Neurons laid out in a 2D or 3D space with distance dependent connectivity
+#[derive(Debug, Copy, Clone)]
+pub struct Topology {
+    pub layout: Layout,
+    /// 2 or 3
+    pub dimensions: usize,
+    /// side length of the space, defaults to the grid's size with neurons 1 unit apart
+    pub extent: Option<f32>,
+    /// wrap distances around the edges of the space
+    pub periodic: bool,
+    /// connection probability is `max_probability` scaled by this profile
+    pub connection_profile: Profile,
+    pub max_probability: f32,
+    /// scales the random weights from Izhikevich (2003), weights don't depend on distance when
+    /// this is `None`
+    pub weight_profile: Option<Profile>,
+    /// conduction velocity in units per ms, every connection has a 1ms delay when this is `None`
+    pub velocity: Option<f32>,
+}
+
+impl Topology {
+    /// Creates a network with the neurons from Izhikevich (2003) placed in space, delays are
+    /// limited to `max_delay` ms
+    pub fn build<R: Rng>(
+        &self,
+        excitatory: usize,
+        inhibitory: usize,
+        max_delay: u32,
+        rng: &mut R,
+    ) -> Network {
+        let total = excitatory + inhibitory;
+        let side = self.grid_side(total);
+        let extent = self.extent.unwrap_or(side as f32);
+        let positions = self.positions(total, extent, rng);
+        // each row is connected in parallel with its own generator, seeded in order so the
+        // network only depends on `rng`
+        let seeds: Vec<u64> = (0..total).map(|_| rng.gen()).collect();
+
+        let mut connections = Array2::<f32>::zeros((total, total));
+        let mut delays = Array2::<u32>::ones((total, total));
+
+        connections
+            .axis_iter_mut(Axis(0))
+            .into_par_iter()
+            .zip(delays.axis_iter_mut(Axis(0)))
+            .enumerate()
+            .for_each(|(post, (mut weights, mut delays))| {
+                let mut rng = StdRng::seed_from_u64(seeds[post]);
+                for pre in 0..total {
+                    if pre == post {
+                        continue;
+                    }
+                    let distance = self.distance(&positions.row(pre), &positions.row(post), extent);
+                    let probability = self.max_probability * self.connection_profile.at(distance);
+                    if rng.gen::<f32>() >= probability {
+                        continue;
+                    }
+
+                    // keeps the sign convention of `izhikevich::randomized_connections`
+                    let noise: f32 = rng.gen();
+                    let weight = if pre < excitatory {
+                        0.5 * noise
+                    } else {
+                        -noise
+                    };
+                    weights[pre] = match self.weight_profile {
+                        Some(profile) => weight * profile.at(distance),
+                        None => weight,
+                    };
+
+                    if let Some(velocity) = self.velocity {
+                        // clamped before adding so slow conduction can't overflow
+                        let steps = (distance / velocity).round() as u32;
+                        delays[pre] = steps.min(max_delay.saturating_sub(1)) + 1;
+                    }
+                }
+            });
+
+        Network {
+            excitatory,
+            inhibitory,
+            neurons: izhikevich::randomized_neurons(excitatory, inhibitory, rng),
+            connections,
+            delays: self.velocity.map(|_| delays),
+            positions: Some(positions),
+        }
+    }
+
+    /// number of grid points along each side needed to hold `total` neurons
+    fn grid_side(&self, total: usize) -> usize {
+        let side = (total as f32).powf(1.0 / self.dimensions as f32).ceil() as usize;
+        // guard against the root landing just under a whole number
+        if side.pow(self.dimensions as u32) < total {
+            side + 1
+        } else {
+            side
+        }
+    }
+
+    fn positions<R: Rng>(&self, total: usize, extent: f32, rng: &mut R) -> Array2<f32> {
+        let mut positions = Array2::<f32>::zeros((total, 3));
+
+        match self.layout {
+            Layout::Grid => {
+                let side = self.grid_side(total);
+                let spacing = extent / side as f32;
+                let mut cells: Vec<usize> = (0..total).collect();
+                cells.shuffle(rng);
+
+                for (mut position, cell) in positions.axis_iter_mut(Axis(0)).zip(cells) {
+                    let coordinates = [cell % side, (cell / side) % side, cell / (side * side)];
+                    for axis in 0..self.dimensions {
+                        position[axis] = coordinates[axis] as f32 * spacing;
+                    }
+                }
+            }
+            Layout::Random => {
+                for mut position in positions.axis_iter_mut(Axis(0)) {
+                    for axis in 0..self.dimensions {
+                        position[axis] = rng.gen::<f32>() * extent;
+                    }
+                }
+            }
+        }
+
+        positions
+    }
+
+    fn distance(&self, a: &ArrayView1<f32>, b: &ArrayView1<f32>, extent: f32) -> f32 {
+        a.iter()
+            .zip(b.iter())
+            .map(|(a, b)| {
+                let d = (a - b).abs();
+                if self.periodic {
+                    d.min(extent - d)
+                } else {
+                    d
+                }
+            })
+            .map(|d| d.powi(2))
+            .sum::<f32>()
+            .sqrt()
+    }
+}
+
+/// Writes each neuron's position as CSV with whether it's excitatory
+pub fn write_positions<W: Write>(
+    out: &mut W,
+    positions: &Array2<f32>,
+    excitatory: usize,
+) -> io::Result<()> {
+    writeln!(out, "neuron,type,x,y,z")?;
+    for (i, position) in positions.axis_iter(Axis(0)).enumerate() {
+        let kind = if i < excitatory {
+            "excitatory"
+        } else {
+            "inhibitory"
+        };
+        writeln!(
+            out,
+            "{},{},{},{},{}",
+            i, kind, position[0], position[1], position[2]
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn topology(layout: Layout, dimensions: usize) -> Topology {
+        Topology {
+            layout,
+            dimensions,
+            extent: None,
+            periodic: false,
+            connection_profile: Profile::Gaussian { sigma: 2.0 },
+            max_probability: 1.0,
+            weight_profile: None,
+            velocity: None,
+        }
+    }
+
+    #[test]
+    fn grid_fills_one_point_per_neuron() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for (dimensions, total, side) in [(2, 9, 3), (2, 10, 4), (3, 27, 3), (3, 28, 4)] {
+            let topology = topology(Layout::Grid, dimensions);
+            assert_eq!(topology.grid_side(total), side);
+
+            let positions = topology.positions(total, side as f32 * 2.0, &mut rng);
+            let mut points: Vec<Vec<i32>> = positions
+                .axis_iter(Axis(0))
+                .map(|position| position.iter().map(|&x| x as i32).collect())
+                .collect();
+            for point in &points {
+                // spaced 2 apart inside the extent, flat in the unused dimension
+                assert!(point.iter().all(|&x| x % 2 == 0 && x < side as i32 * 2));
+                assert!(dimensions == 3 || point[2] == 0);
+            }
+            points.sort();
+            points.dedup();
+            assert_eq!(points.len(), total);
+        }
+    }
+
+    #[test]
+    fn random_layout_stays_inside_the_extent() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let positions = topology(Layout::Random, 2).positions(500, 5.0, &mut rng);
+        assert!(positions.column(0).iter().all(|&x| (0.0..5.0).contains(&x)));
+        assert!(positions.column(1).iter().all(|&y| (0.0..5.0).contains(&y)));
+        assert!(positions.column(2).iter().all(|&z| z == 0.0));
+    }
+
+    #[test]
+    fn periodic_distances_wrap() {
+        let a = array![1.0, 1.0, 0.0];
+        let b = array![9.0, 4.0, 0.0];
+        let mut topology = topology(Layout::Random, 2);
+        assert_eq!(
+            topology.distance(&a.view(), &b.view(), 10.0),
+            73.0f32.sqrt()
+        );
+
+        topology.periodic = true;
+        assert_eq!(
+            topology.distance(&a.view(), &b.view(), 10.0),
+            13.0f32.sqrt()
+        );
+    }
+
+    #[test]
+    fn profiles_fall_off_with_distance() {
+        let gaussian = Profile::Gaussian { sigma: 2.0 };
+        let exponential = Profile::Exponential { length: 2.0 };
+        assert_eq!(gaussian.at(0.0), 1.0);
+        assert_eq!(exponential.at(0.0), 1.0);
+        assert!((gaussian.at(2.0) - (-0.5f32).exp()).abs() < 1e-6);
+        assert!((exponential.at(2.0) - (-1.0f32).exp()).abs() < 1e-6);
+
+        assert_eq!(
+            "gaussian:3".parse::<Profile>().unwrap(),
+            Profile::Gaussian { sigma: 3.0 }
+        );
+        assert_eq!(
+            "exp:0.5".parse::<Profile>().unwrap(),
+            Profile::Exponential { length: 0.5 }
+        );
+        assert!("gaussian:0".parse::<Profile>().is_err());
+        assert!("gaussian".parse::<Profile>().is_err());
+        assert!("cosine:1".parse::<Profile>().is_err());
+    }
+
+    #[test]
+    fn delays_follow_distance() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let topology = Topology {
+            connection_profile: Profile::Exponential { length: 1000.0 },
+            velocity: Some(0.5),
+            ..topology(Layout::Grid, 2)
+        };
+        let network = topology.build(20, 5, 20, &mut rng);
+        let positions = network.positions.unwrap();
+        let delays = network.delays.unwrap();
+
+        for ((post, pre), &weight) in network.connections.indexed_iter() {
+            if weight == 0.0 {
+                continue;
+            }
+            let distance = topology.distance(&positions.row(pre), &positions.row(post), 5.0);
+            let expected = 1 + (distance / 0.5).round() as u32;
+            assert_eq!(delays[[post, pre]], expected);
+        }
+    }
+
+    #[test]
+    fn slow_conduction_is_capped_at_the_max_delay() {
+        let mut rng = StdRng::seed_from_u64(4);
+        for velocity in [1e-3, 1e-30, f32::MIN_POSITIVE] {
+            let topology = Topology {
+                velocity: Some(velocity),
+                ..topology(Layout::Random, 3)
+            };
+            let network = topology.build(20, 5, 10, &mut rng);
+            assert_eq!(network.max_delay(), 10);
+            let delays = network.delays.unwrap();
+            for ((post, pre), &weight) in network.connections.indexed_iter() {
+                if weight != 0.0 {
+                    assert!((1..=10).contains(&delays[[post, pre]]));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,402 @@
+use minifb::{Key, KeyRepeat, MouseButton, MouseMode, Window};
+use plotters::coord::Shift;
+use plotters::prelude::*;
+use tokio::sync::mpsc;
+
+use crate::analysis::pattern::FiringClass;
+use crate::control::{Control, Pace, Tweak};
+use crate::network::Population;
+
+/// height in pixels of each row of the toolbar
+const ROW_HEIGHT: i32 = 24;
+/// height in pixels of the toolbar along the top of the window, the simulation controls above
+/// the parameters
+pub const TOOLBAR_HEIGHT: u32 = 2 * ROW_HEIGHT as u32;
+const BUTTON_WIDTH: i32 = 70;
+const BUTTON_GAP: i32 = 4;
+/// width of each parameter with its value and buttons
+const PARAMETER_WIDTH: i32 = 195;
+const SMALL_BUTTON_WIDTH: i32 = 18;
+
+/// top left and bottom right corners of a button in pixels
+type Corners = [(i32, i32); 2];
+
+/// most neurons probed at once
+pub const MAX_PROBES: usize = 6;
+
+/// change in the thalamic noise scale for each press
+const NOISE_STEP: f32 = 0.25;
+/// factor the weights are scaled by for each press
+const WEIGHT_FACTOR: f32 = 1.1;
+
+/// Something asked for with a key or a toolbar button
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Action {
+    TogglePause,
+    Step,
+    Pace(Pace),
+    Reset,
+    Reseed,
+    Screenshot,
+    Lower(Parameter),
+    Raise(Parameter),
+}
+
+/// A parameter of the running network that can be tweaked
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Parameter {
+    Noise,
+    Weights(Population),
+    /// steps through the cortical classes' parameters
+    Preset(Population),
+}
+
+/// Toolbar buttons in order with the key that does the same
+const BUTTONS: [(Action, Key, &str); 8] = [
+    (Action::TogglePause, Key::Space, "pause"),
+    (Action::Step, Key::Right, "step"),
+    (Action::Pace(Pace::RealTime), Key::Key1, "1x"),
+    (Action::Pace(Pace::Fast), Key::Key2, "10x"),
+    (Action::Pace(Pace::Max), Key::Key3, "max"),
+    (Action::Reset, Key::R, "reset"),
+    (Action::Reseed, Key::N, "reseed"),
+    (Action::Screenshot, Key::P, "screenshot"),
+];
+
+/// Parameters along the second row with the keys that lower and raise them
+const PARAMETERS: [(Parameter, Key, Key); 5] = [
+    (Parameter::Noise, Key::Minus, Key::Equal),
+    (
+        Parameter::Weights(Population::Excitatory),
+        Key::LeftBracket,
+        Key::RightBracket,
+    ),
+    (
+        Parameter::Weights(Population::Inhibitory),
+        Key::Semicolon,
+        Key::Apostrophe,
+    ),
+    (
+        Parameter::Preset(Population::Excitatory),
+        Key::Comma,
+        Key::Period,
+    ),
+    (
+        Parameter::Preset(Population::Inhibitory),
+        Key::Key9,
+        Key::Key0,
+    ),
+];
+
+/// What the rest of the UI has to do after a frame's input
+#[derive(Debug, Default)]
+pub struct Input {
+    pub screenshot: bool,
+    /// pixel clicked outside the toolbar and whether shift was held
+    pub click: Option<(i32, i32, bool)>,
+}
+
+/// Keyboard and mouse controls for the simulation, sent to the backend as `Control`s
+pub struct Toolbar {
+    controls: mpsc::UnboundedSender<Control>,
+    paused: bool,
+    pace: Pace,
+    mouse_was_down: bool,
+    noise: f32,
+    /// total scale of the excitatory and inhibitory weights
+    weights: [f32; 2],
+    /// index into `FiringClass::CORTICAL` of each population's preset, none while they still
+    /// have the network's own parameters
+    presets: [Option<usize>; 2],
+    probed: Vec<usize>,
+}
+
+impl Toolbar {
+    pub fn new(controls: mpsc::UnboundedSender<Control>) -> Self {
+        Toolbar {
+            controls,
+            paused: false,
+            pace: Pace::RealTime,
+            mouse_was_down: false,
+            noise: 1.0,
+            weights: [1.0; 2],
+            presets: [None; 2],
+            probed: vec![0],
+        }
+    }
+
+    /// Sends the controls for the keys pressed and buttons clicked since the last frame, passing
+    /// on everything else
+    pub fn handle_input(&mut self, window: &Window) -> Input {
+        let mut input = Input::default();
+        let mut actions: Vec<Action> = window
+            .get_keys_pressed(KeyRepeat::No)
+            .iter()
+            .filter_map(|key| key_action(*key))
+            .collect();
+
+        let mouse_down = window.get_mouse_down(MouseButton::Left);
+        if mouse_down && !self.mouse_was_down {
+            if let Some((x, y)) = window.get_mouse_pos(MouseMode::Discard) {
+                let (x, y) = (x as i32, y as i32);
+                if y < TOOLBAR_HEIGHT as i32 {
+                    actions.extend(
+                        buttons()
+                            .find(|(_, [(left, top), (right, bottom)])| {
+                                (*left..*right).contains(&x) && (*top..*bottom).contains(&y)
+                            })
+                            .map(|(action, _)| action),
+                    );
+                } else {
+                    let shift =
+                        window.is_key_down(Key::LeftShift) || window.is_key_down(Key::RightShift);
+                    input.click = Some((x, y, shift));
+                }
+            }
+        }
+        self.mouse_was_down = mouse_down;
+
+        for action in actions {
+            match action {
+                Action::TogglePause => {
+                    self.paused = !self.paused;
+                    self.send(if self.paused {
+                        Control::Pause
+                    } else {
+                        Control::Resume
+                    });
+                }
+                Action::Step => {
+                    // stepping a running simulation pauses it first
+                    if !self.paused {
+                        self.paused = true;
+                        self.send(Control::Pause);
+                    }
+                    self.send(Control::Step);
+                }
+                Action::Pace(pace) => {
+                    self.pace = pace;
+                    self.send(Control::Pace(pace));
+                }
+                Action::Reset => {
+                    self.reset_parameters();
+                    self.send(Control::Reset);
+                }
+                Action::Reseed => {
+                    self.reset_parameters();
+                    self.send(Control::Reseed);
+                }
+                Action::Screenshot => input.screenshot = true,
+                Action::Lower(parameter) => self.tweak(parameter, false),
+                Action::Raise(parameter) => self.tweak(parameter, true),
+            }
+        }
+        input
+    }
+
+    /// Probes just `neuron`, or with `extend` adds it to the probed neurons or removes it if it's
+    /// already one of them
+    pub fn probe(&mut self, neuron: usize, extend: bool) {
+        if !extend {
+            self.probed = vec![neuron];
+        } else if let Some(i) = self.probed.iter().position(|n| *n == neuron) {
+            if self.probed.len() > 1 {
+                self.probed.remove(i);
+            }
+        } else if self.probed.len() < MAX_PROBES {
+            self.probed.push(neuron);
+        } else {
+            log::warn!("already probing {} neurons", MAX_PROBES);
+            return;
+        }
+        self.send(Control::Probe(self.probed.clone()));
+    }
+
+    fn tweak(&mut self, parameter: Parameter, raise: bool) {
+        let tweak = match parameter {
+            Parameter::Noise => {
+                self.noise = if raise {
+                    self.noise + NOISE_STEP
+                } else {
+                    (self.noise - NOISE_STEP).max(0.0)
+                };
+                Tweak::Noise(self.noise)
+            }
+            Parameter::Weights(population) => {
+                let factor = if raise {
+                    WEIGHT_FACTOR
+                } else {
+                    1.0 / WEIGHT_FACTOR
+                };
+                self.weights[population as usize] *= factor;
+                Tweak::ScaleWeights(population, factor)
+            }
+            Parameter::Preset(population) => {
+                let count = FiringClass::CORTICAL.len();
+                let preset = &mut self.presets[population as usize];
+                let index = match (*preset, raise) {
+                    (None, true) => 0,
+                    (None, false) => count - 1,
+                    (Some(i), true) => (i + 1) % count,
+                    (Some(i), false) => (i + count - 1) % count,
+                };
+                *preset = Some(index);
+                Tweak::Preset(population, FiringClass::CORTICAL[index])
+            }
+        };
+        self.send(Control::Tweak(tweak));
+    }
+
+    /// Restarting the network undoes every tweak
+    fn reset_parameters(&mut self) {
+        self.noise = 1.0;
+        self.weights = [1.0; 2];
+        self.presets = [None; 2];
+    }
+
+    fn value(&self, parameter: Parameter) -> String {
+        match parameter {
+            Parameter::Noise => format!("noise {:.2}x", self.noise),
+            Parameter::Weights(population) => {
+                format!(
+                    "{} weights {:.2}x",
+                    population, self.weights[population as usize]
+                )
+            }
+            Parameter::Preset(population) => match self.presets[population as usize] {
+                Some(i) => format!("{} preset {}", population, FiringClass::CORTICAL[i]),
+                None => format!("{} preset mixed", population),
+            },
+        }
+    }
+
+    fn send(&self, control: Control) {
+        if self.controls.send(control).is_err() {
+            log::warn!("the simulation has stopped, ignoring controls");
+        }
+    }
+
+    /// Draws the buttons with the current pace and pause state highlighted, followed by the
+    /// simulated time
+    pub fn draw<DB: DrawingBackend>(&self, area: &DrawingArea<DB, Shift>, time: u64) {
+        for (i, (action, _, label)) in BUTTONS.iter().enumerate() {
+            let (left, right) = button_span(i);
+            let active = match action {
+                Action::TogglePause => self.paused,
+                Action::Pace(pace) => *pace == self.pace,
+                _ => false,
+            };
+            let label = match action {
+                Action::TogglePause if self.paused => "resume",
+                _ => label,
+            };
+            let background = if active {
+                RGBColor(200, 200, 200)
+            } else {
+                WHITE
+            };
+
+            draw_button(
+                area,
+                [(left, 2), (right, ROW_HEIGHT - 2)],
+                label,
+                background,
+            );
+        }
+
+        for (i, (parameter, _, _)) in PARAMETERS.iter().enumerate() {
+            let left = BUTTON_GAP + i as i32 * PARAMETER_WIDTH;
+            area.draw(&Text::new(
+                self.value(*parameter),
+                (left, ROW_HEIGHT + 7),
+                ("sans-serif", 12),
+            ))
+            .expect("error drawing toolbar");
+            let (lower, raise) = parameter_buttons(i);
+            draw_button(area, lower, "-", WHITE);
+            draw_button(area, raise, "+", WHITE);
+        }
+
+        let status = format!(
+            "{}ms, {}{}",
+            time,
+            self.pace,
+            if self.paused { ", paused" } else { "" }
+        );
+        let left = button_span(BUTTONS.len()).0 + 6;
+        area.draw(&Text::new(status, (left, 7), ("sans-serif", 12)))
+            .expect("error drawing toolbar");
+    }
+}
+
+fn draw_button<DB: DrawingBackend>(
+    area: &DrawingArea<DB, Shift>,
+    corners: Corners,
+    label: &str,
+    background: RGBColor,
+) {
+    area.draw(&Rectangle::new(corners, background.filled()))
+        .expect("error drawing toolbar");
+    area.draw(&Rectangle::new(corners, BLACK))
+        .expect("error drawing toolbar");
+    area.draw(&Text::new(
+        label,
+        (corners[0].0 + 6, corners[0].1 + 5),
+        ("sans-serif", 12),
+    ))
+    .expect("error drawing toolbar");
+}
+
+fn button_span(index: usize) -> (i32, i32) {
+    let left = BUTTON_GAP + index as i32 * (BUTTON_WIDTH + BUTTON_GAP);
+    (left, left + BUTTON_WIDTH)
+}
+
+/// Corners of the buttons lowering and raising a parameter on the second row
+fn parameter_buttons(index: usize) -> (Corners, Corners) {
+    let right = BUTTON_GAP + (index as i32 + 1) * PARAMETER_WIDTH - 2 * BUTTON_GAP;
+    let (top, bottom) = (ROW_HEIGHT + 2, 2 * ROW_HEIGHT - 2);
+    let raise = right - SMALL_BUTTON_WIDTH;
+    let lower = raise - BUTTON_GAP - SMALL_BUTTON_WIDTH;
+    (
+        [(lower, top), (lower + SMALL_BUTTON_WIDTH, bottom)],
+        [(raise, top), (right, bottom)],
+    )
+}
+
+/// Every button with its corners
+fn buttons() -> impl Iterator<Item = (Action, Corners)> {
+    let controls = BUTTONS.iter().enumerate().map(|(i, (action, _, _))| {
+        let (left, right) = button_span(i);
+        (*action, [(left, 2), (right, ROW_HEIGHT - 2)])
+    });
+    let parameters = PARAMETERS
+        .iter()
+        .enumerate()
+        .flat_map(|(i, (parameter, _, _))| {
+            let (lower, raise) = parameter_buttons(i);
+            [
+                (Action::Lower(*parameter), lower),
+                (Action::Raise(*parameter), raise),
+            ]
+        });
+    controls.chain(parameters)
+}
+
+fn key_action(key: Key) -> Option<Action> {
+    BUTTONS
+        .iter()
+        .find(|(_, k, _)| *k == key)
+        .map(|(action, _, _)| *action)
+        .or_else(|| {
+            PARAMETERS.iter().find_map(|(parameter, lower, raise)| {
+                if *lower == key {
+                    Some(Action::Lower(*parameter))
+                } else if *raise == key {
+                    Some(Action::Raise(*parameter))
+                } else {
+                    None
+                }
+            })
+        })
+}
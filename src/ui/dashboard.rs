@@ -0,0 +1,265 @@
+use std::fmt;
+use std::str::FromStr;
+
+use plotters::coord::Shift;
+use plotters::prelude::*;
+
+/// smallest width or height in pixels a panel is drawn at, charts can't fit in less
+const MIN_PANEL_SIZE: u32 = 40;
+
+/// A chart the dashboard can show
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Panel {
+    /// spike raster of every neuron
+    Raster,
+    /// voltages of the probed neurons
+    Voltages,
+    /// firing rate of the whole network
+    Rate,
+    /// trajectories of the probed neurons in the (v, u) plane
+    PhasePlane,
+    /// power spectrum of the LFP proxy
+    Spectrum,
+    /// histograms of the E→E, E→I, I→E and I→I weights
+    Weights,
+    /// downsampled connection matrix
+    Heatmap,
+}
+
+impl FromStr for Panel {
+    type Err = ParseDashboardError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "raster" | "spikes" => Ok(Panel::Raster),
+            "voltages" | "voltage" | "probes" => Ok(Panel::Voltages),
+            "rate" => Ok(Panel::Rate),
+            "phase" | "phase-plane" => Ok(Panel::PhasePlane),
+            "spectrum" => Ok(Panel::Spectrum),
+            "weights" => Ok(Panel::Weights),
+            "heatmap" | "connections" => Ok(Panel::Heatmap),
+            _ => Err(ParseDashboardError {
+                input: s.to_string(),
+                expected: "raster, voltages, rate, phase, spectrum, weights or heatmap",
+            }),
+        }
+    }
+}
+
+/// A row of panels and their relative widths
+#[derive(Debug, Clone, PartialEq)]
+struct Row {
+    height: u32,
+    panels: Vec<(Panel, u32)>,
+}
+
+/// Which panels the UI shows, arranged in rows that each take a share of the window's height
+/// with their panels each taking a share of the row's width
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dashboard {
+    rows: Vec<Row>,
+}
+
+impl Dashboard {
+    /// Whether any of the dashboard's panels is `panel`
+    pub fn shows(&self, panel: Panel) -> bool {
+        self.rows
+            .iter()
+            .any(|row| row.panels.iter().any(|(shown, _)| *shown == panel))
+    }
+
+    /// Splits an area into the dashboard's panels, leaving out any too small to draw
+    pub fn areas<DB: DrawingBackend>(
+        &self,
+        area: &DrawingArea<DB, Shift>,
+    ) -> Vec<(Panel, DrawingArea<DB, Shift>)> {
+        let (width, height) = area.dim_in_pixel();
+        let mut areas = Vec::new();
+        let row_edges = edges(self.rows.iter().map(|row| row.height), height);
+        for (row, (top, bottom)) in self.rows.iter().zip(row_edges) {
+            let panel_edges = edges(row.panels.iter().map(|(_, width)| *width), width);
+            for ((panel, _), (left, right)) in row.panels.iter().zip(panel_edges) {
+                if right - left < MIN_PANEL_SIZE || bottom - top < MIN_PANEL_SIZE {
+                    continue;
+                }
+                let cell = area
+                    .clone()
+                    .shrink((left, top), (right - left, bottom - top));
+                areas.push((*panel, cell));
+            }
+        }
+        areas
+    }
+}
+
+/// Where each share of `total` pixels starts and ends
+fn edges(shares: impl Iterator<Item = u32> + Clone, total: u32) -> Vec<(u32, u32)> {
+    let sum: u32 = shares.clone().sum();
+    let mut start = 0;
+    shares
+        .scan(0, |before, share| {
+            *before += share;
+            Some(*before)
+        })
+        .map(|before| {
+            let end = (total as u64 * before as u64 / sum as u64) as u32;
+            let edge = (start, end);
+            start = end;
+            edge
+        })
+        .collect()
+}
+
+impl FromStr for Dashboard {
+    type Err = ParseDashboardError;
+
+    /// Parses rows separated by semicolons, each an optional height share followed by a colon and
+    /// comma separated panels with an optional width share after a `*`, i.e.
+    /// `4:raster;1:voltages*2,spectrum`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseDashboardError {
+            input: s.to_string(),
+            expected: "rows like <height>:<panel>*<width>,<panel> separated by semicolons",
+        };
+        let share = |share: Option<&str>| match share {
+            Some(share) => share.trim().parse::<u32>().ok().filter(|share| *share > 0),
+            None => Some(1),
+        };
+
+        let rows = s
+            .split(';')
+            .map(|row| {
+                let (height, panels) = match row.split_once(':') {
+                    Some((height, panels)) => (share(Some(height)).ok_or_else(err)?, panels),
+                    None => (1, row),
+                };
+                let panels = panels
+                    .split(',')
+                    .map(|cell| {
+                        let mut parts = cell.splitn(2, '*');
+                        let panel = parts.next().unwrap_or_default().parse::<Panel>()?;
+                        let width = share(parts.next()).ok_or_else(err)?;
+                        Ok((panel, width))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Row { height, panels })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Dashboard { rows })
+    }
+}
+
+/// How the raster's rows of neurons are ordered from the bottom
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum RowOrder {
+    /// by index, which puts the excitatory neurons first
+    Index,
+    /// grouped by the colour of their spikes, their population or firing class
+    Population,
+    /// by firing rate over the buffered steps with the busiest at the top
+    Rate,
+}
+
+impl FromStr for RowOrder {
+    type Err = ParseDashboardError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "index" => Ok(RowOrder::Index),
+            "population" | "class" => Ok(RowOrder::Population),
+            "rate" => Ok(RowOrder::Rate),
+            _ => Err(ParseDashboardError {
+                input: s.to_string(),
+                expected: "index, population or rate",
+            }),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ParseDashboardError {
+    input: String,
+    expected: &'static str,
+}
+
+impl fmt::Display for ParseDashboardError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "invalid value \"{}\", expected {}",
+            self.input, self.expected
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_default_layout() {
+        let dashboard: Dashboard = "4:raster;1:voltages*2,spectrum".parse().unwrap();
+        assert_eq!(
+            dashboard.rows,
+            vec![
+                Row {
+                    height: 4,
+                    panels: vec![(Panel::Raster, 1)],
+                },
+                Row {
+                    height: 1,
+                    panels: vec![(Panel::Voltages, 2), (Panel::Spectrum, 1)],
+                },
+            ]
+        );
+        assert!(dashboard.shows(Panel::Spectrum));
+        assert!(!dashboard.shows(Panel::Heatmap));
+
+        // shares default to 1 and names are forgiving
+        let dashboard: Dashboard = " Spikes , phase-plane*3 ".parse().unwrap();
+        assert_eq!(
+            dashboard.rows,
+            vec![Row {
+                height: 1,
+                panels: vec![(Panel::Raster, 1), (Panel::PhasePlane, 3)],
+            }]
+        );
+    }
+
+    #[test]
+    fn rejects_bad_layouts() {
+        for layout in [
+            "0:raster",
+            "raster*0",
+            "raster*-1",
+            "x:raster",
+            "raster;histogram",
+            "raster,",
+            "raster;;rate",
+            "",
+        ] {
+            assert!(layout.parse::<Dashboard>().is_err(), "{:?}", layout);
+        }
+    }
+
+    #[test]
+    fn edges_cover_the_whole_width() {
+        for (shares, total) in [
+            (vec![1], 100),
+            (vec![4, 1], 600),
+            (vec![2, 1], 100),
+            (vec![1, 1, 1], 1000),
+            (vec![3, 5, 7, 11], 33),
+            (vec![1, 1000], 10),
+        ] {
+            let edges = edges(shares.iter().copied(), total);
+            assert_eq!(edges.len(), shares.len());
+            assert_eq!(edges.first().unwrap().0, 0);
+            assert_eq!(edges.last().unwrap().1, total);
+            for pair in edges.windows(2) {
+                assert_eq!(pair[0].1, pair[1].0);
+            }
+        }
+        assert_eq!(edges([2, 1].iter().copied(), 100), vec![(0, 66), (66, 100)]);
+    }
+}
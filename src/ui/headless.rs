@@ -0,0 +1,169 @@
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use plotters::coord::Shift;
+use plotters::prelude::*;
+use tokio::sync::mpsc;
+
+use super::raster::Raster;
+use super::{
+    draw_legend, draw_legends, draw_panels, draw_spike_chart, frame_window, prepare_raster,
+    Buffers, Frame, View, HEIGHT, WIDTH,
+};
+use crate::control::Control;
+use crate::plot::render;
+
+/// size of the snapshots, the same as the window when it opens
+const SIZE: (u32, u32) = (WIDTH as u32, HEIGHT as u32);
+/// size of each frame of the raster GIF
+const GIF_SIZE: (u32, u32) = (WIDTH as u32, 400);
+/// real ms each frame of the raster GIF is shown for
+const GIF_FRAME_DELAY: u32 = 40;
+
+/// What to save when rendering without a window
+pub struct Output {
+    /// simulated ms to run for, the final snapshot is taken after the last step
+    pub duration: u64,
+    /// simulated ms to take snapshots at along the way
+    pub snapshots: Vec<u64>,
+    /// the final snapshot, with earlier ones named after their time. SVG if it ends in `.svg`
+    /// and PNG otherwise
+    pub path: PathBuf,
+    /// animated GIF of the scrolling raster and the simulated ms between its frames
+    pub gif: Option<(PathBuf, u64)>,
+}
+
+/// Runs the simulation for `output.duration` ms and saves the dashboard at the snapshot times
+/// and at the end, along with the raster GIF. The simulation has to start out paused so it only
+/// takes the steps asked for here.
+pub(crate) fn render(
+    time_buffer_size: usize,
+    excitatory: usize,
+    neuron_count: usize,
+    buffers: Buffers,
+    controls: mpsc::UnboundedSender<Control>,
+    view: View,
+    output: Output,
+) {
+    let legend = prepare_raster(&buffers.raster, excitatory, neuron_count, &view);
+    let mut next_rate_sort = 0;
+
+    let gif_times: Vec<u64> = match &output.gif {
+        Some((_, interval)) => {
+            let interval = (*interval).max(1);
+            (interval..=output.duration)
+                .step_by(interval as usize)
+                .collect()
+        }
+        None => Vec::new(),
+    };
+    let mut times: Vec<u64> = output
+        .snapshots
+        .iter()
+        .copied()
+        .filter(|time| *time <= output.duration)
+        .chain(gif_times.iter().copied())
+        .chain(Some(output.duration))
+        .collect();
+    times.sort_unstable();
+    times.dedup();
+
+    let gif = output.gif.as_ref().map(|(path, _)| {
+        BitMapBackend::gif(path, GIF_SIZE, GIF_FRAME_DELAY)
+            .expect("error creating gif")
+            .into_drawing_area()
+    });
+
+    let mut stepped = 0;
+    for time in times {
+        for _ in stepped..time {
+            if controls.send(Control::Step).is_err() {
+                log::error!("the simulation stopped at {}ms", stepped);
+                return;
+            }
+        }
+        stepped = time;
+        if !wait_for(&buffers.raster, time, &controls) {
+            log::error!("the simulation stopped before {}ms", time);
+            return;
+        }
+
+        let (latest, window_ms) = frame_window(
+            &buffers.raster,
+            view.order,
+            time_buffer_size,
+            &mut next_rate_sort,
+        );
+        let frame = Frame {
+            neuron_count,
+            time: latest,
+            window_ms,
+            buffers: &buffers,
+        };
+        if let (Some(gif), true) = (&gif, gif_times.binary_search(&time).is_ok()) {
+            gif.fill(&WHITE).expect("error filling gif frame");
+            draw_spike_chart(
+                gif,
+                frame.window_ms.clone(),
+                neuron_count,
+                Some(&buffers.raster),
+            );
+            draw_legend(gif, &legend);
+            gif.present().expect("error writing gif frame");
+        }
+        if output.snapshots.contains(&time) {
+            let path = timed_path(&output.path, time);
+            render!(&path, SIZE, |root: &DrawingArea<_, Shift>| {
+                draw_snapshot(root, &view, &frame, &legend)
+            });
+            log::info!("saved snapshot to {}", path.display());
+        }
+        if time == output.duration {
+            render!(&output.path, SIZE, |root: &DrawingArea<_, Shift>| {
+                draw_snapshot(root, &view, &frame, &legend)
+            });
+            log::info!("saved snapshot to {}", output.path.display());
+        }
+    }
+}
+
+/// Draws the dashboard's panels as the window would, without the toolbar
+fn draw_snapshot<DB: DrawingBackend>(
+    root: &DrawingArea<DB, Shift>,
+    view: &View,
+    frame: &Frame,
+    legend: &[(String, RGBColor)],
+) {
+    draw_panels(root, &view.dashboard, frame, true);
+    draw_legends(root, &view.dashboard, legend);
+}
+
+/// Waits until the raster has every step before `time`, or returns false if the simulation
+/// stopped first. The raster is filled in last from each frame, so the other buffers have the
+/// steps too.
+fn wait_for(raster: &Mutex<Raster>, time: u64, controls: &mpsc::UnboundedSender<Control>) -> bool {
+    loop {
+        {
+            let raster = raster.lock().unwrap();
+            if time == 0 || (raster.steps() > 0 && raster.time() + 1 >= time) {
+                return true;
+            }
+        }
+        if controls.is_closed() {
+            return false;
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+/// `path` with the time added to the end of its name, so `out.png` at 500ms is `out-500ms.png`
+fn timed_path(path: &Path, time: u64) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{}-{}ms.{}", stem, time, extension.to_string_lossy()),
+        None => format!("{}-{}ms", stem, time),
+    };
+    path.with_file_name(name)
+}
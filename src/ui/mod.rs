@@ -0,0 +1,852 @@
+use std::borrow::Borrow;
+use std::borrow::BorrowMut;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use minifb::{ScaleMode, Window, WindowOptions};
+use plotters::backend::BGRXPixel;
+use plotters::coord::types::{RangedCoordf32, RangedCoordi64};
+use plotters::coord::{ReverseCoordTranslate, Shift};
+use plotters::prelude::*;
+use tokio::sync::mpsc;
+
+use super::analysis::pattern::FiringClass;
+use super::analysis::rhythm;
+use super::control::Control;
+use super::network::Population;
+
+mod controls;
+pub mod dashboard;
+pub mod headless;
+pub mod raster;
+pub mod traces;
+pub mod weights;
+
+use controls::{Toolbar, MAX_PROBES, TOOLBAR_HEIGHT};
+use dashboard::{Dashboard, Panel, RowOrder};
+use raster::Raster;
+use traces::Traces;
+use weights::Weights;
+
+/// size of the window when it opens, it can be resized after
+const WIDTH: usize = 1000;
+const HEIGHT: usize = 1000;
+
+/// colours of the spikes of each population when they aren't coloured by firing class
+const EXCITATORY_COLOR: RGBColor = RED;
+const INHIBITORY_COLOR: RGBColor = BLUE;
+/// colour of each probed neuron's trace
+const TRACE_COLORS: [RGBColor; MAX_PROBES] =
+    [RED, BLUE, GREEN, MAGENTA, CYAN, RGBColor(255, 140, 0)];
+/// steps the population rate is averaged over
+const RATE_WINDOW: usize = 5;
+/// simulated ms between sorting the raster by rate again
+const RATE_SORT_INTERVAL: u64 = 1000;
+/// dashes the u-nullcline of the phase plane is drawn with
+const U_NULLCLINE_DASHES: usize = 20;
+/// Welch segment length for the live spectrum
+const SPECTRUM_SEGMENT: usize = 256;
+/// highest frequency shown in the live spectrum, covering gamma
+const SPECTRUM_MAX_FREQUENCY: f64 = 100.0;
+
+/// coordinates of a spike chart, milliseconds across and rows of neurons up
+type SpikeCoords = Cartesian2d<RangedCoordi64, RangedCoordf32>;
+/// the pixels a spike chart's plotting area covers and its coordinates
+type SpikeChart = ((Range<i32>, Range<i32>), SpikeCoords);
+
+/// The latest steps of the simulation, filled in as frames arrive and read by the UI
+pub struct Buffers {
+    pub traces: Arc<Mutex<Traces>>,
+    pub lfp: Arc<Mutex<VecDeque<f32>>>,
+    pub raster: Arc<Mutex<Raster>>,
+    /// the latest snapshot of the weights, if one has arrived
+    pub weights: Arc<Mutex<Option<Weights>>>,
+}
+
+/// How the UI shows the simulation
+pub struct View {
+    pub dashboard: Dashboard,
+    pub order: RowOrder,
+    /// firing class of each neuron to colour the raster by instead of their population
+    pub classes: Option<Vec<FiringClass>>,
+}
+
+/// The state of the simulation a frame of the panels is drawn from
+struct Frame<'a> {
+    neuron_count: usize,
+    /// simulated time of the latest step in ms
+    time: u64,
+    /// simulated ms the spike charts cover
+    window_ms: Range<i64>,
+    buffers: &'a Buffers,
+}
+
+pub(crate) fn draw(
+    time_buffer_size: usize,
+    excitatory: usize,
+    neuron_count: usize,
+    buffers: Buffers,
+    controls: mpsc::UnboundedSender<Control>,
+    view: View,
+) {
+    let mut img_buf = BufferWrapper(vec![0; WIDTH * HEIGHT]);
+
+    let mut window = Window::new(
+        "Izhikevich",
+        WIDTH,
+        HEIGHT,
+        WindowOptions {
+            resize: true,
+            scale_mode: ScaleMode::UpperLeft,
+            ..WindowOptions::default()
+        },
+    )
+    .expect("error creating window");
+    window.limit_update_rate(Some(std::time::Duration::from_micros(16600)));
+
+    let mut toolbar = Toolbar::new(controls);
+    // the spike charts' coordinates from the last frame for working out which neuron was clicked
+    let mut spike_coords: Vec<SpikeCoords> = Vec::new();
+
+    let legend = prepare_raster(&buffers.raster, excitatory, neuron_count, &view);
+    let mut next_rate_sort = 0;
+
+    while window.is_open() {
+        let (width, height) = window.get_size();
+        if width == 0 || height == 0 {
+            // minimized
+            window.update();
+            continue;
+        }
+        if img_buf.0.len() != width * height {
+            img_buf = BufferWrapper(vec![0; width * height]);
+        }
+        let size = (width as u32, height as u32);
+
+        let input = toolbar.handle_input(&window);
+        if let Some((x, y, extend)) = input.click {
+            // each neuron's row runs from its position up to the next one
+            let position = spike_coords
+                .iter()
+                .find_map(|coords| coords.reverse_translate((x, y)));
+            if let Some((_, position)) = position {
+                let neuron = buffers
+                    .raster
+                    .lock()
+                    .unwrap()
+                    .neuron_at(position.max(0.0) as usize);
+                toolbar.probe(neuron, extend);
+            }
+        }
+
+        let (time, window_ms) = frame_window(
+            &buffers.raster,
+            view.order,
+            time_buffer_size,
+            &mut next_rate_sort,
+        );
+        let frame = Frame {
+            neuron_count,
+            time,
+            window_ms,
+            buffers: &buffers,
+        };
+
+        let spike_charts = {
+            let root =
+                BitMapBackend::<BGRXPixel>::with_buffer_and_format(img_buf.borrow_mut(), size)
+                    .expect("error creating bitmap backend")
+                    .into_drawing_area();
+            root.fill(&WHITE).expect("error filling bitmap background");
+
+            let (toolbar_area, panel_area) = root.split_vertically(TOOLBAR_HEIGHT);
+            toolbar.draw(&toolbar_area, time);
+            let spike_charts = draw_panels(&panel_area, &view.dashboard, &frame, false);
+
+            root.present().expect("error presenting ui");
+            spike_charts
+        };
+
+        // the spikes go straight into the pixels over the meshes plotters drew
+        {
+            let mut raster = buffers.raster.lock().unwrap();
+            for ((x, y), _) in &spike_charts {
+                raster.blit(&mut img_buf.0, width, x.clone(), y.clone());
+            }
+        }
+        spike_coords = spike_charts.into_iter().map(|(_, coords)| coords).collect();
+
+        {
+            let root =
+                BitMapBackend::<BGRXPixel>::with_buffer_and_format(img_buf.borrow_mut(), size)
+                    .expect("error creating bitmap backend")
+                    .into_drawing_area();
+            let (_, panel_area) = root.split_vertically(TOOLBAR_HEIGHT);
+            draw_legends(&panel_area, &view.dashboard, &legend);
+            root.present().expect("error presenting ui");
+        }
+
+        if input.screenshot {
+            let path = format!("screenshot-{}ms.png", time);
+            match save_screenshot(Path::new(&path), &img_buf.0, size) {
+                Ok(()) => log::info!("saved screenshot to {}", path),
+                Err(err) => log::error!("error saving screenshot {}: {}", path, err),
+            }
+        }
+
+        window
+            .update_with_buffer(img_buf.borrow(), width, height)
+            .expect("error updating buffer");
+    }
+}
+
+/// Colours the raster's spikes and puts its rows in their starting order, returning the legend
+fn prepare_raster(
+    raster: &Mutex<Raster>,
+    excitatory: usize,
+    neuron_count: usize,
+    view: &View,
+) -> Vec<(String, RGBColor)> {
+    let (groups, legend) = neuron_groups(excitatory, neuron_count, view.classes.as_deref());
+    let mut raster = raster.lock().unwrap();
+    raster.set_colors(groups.iter().map(|g| pixel(legend[*g].1)).collect());
+    if view.order == RowOrder::Population {
+        let mut rows: Vec<usize> = (0..neuron_count).collect();
+        rows.sort_by_key(|neuron| groups[*neuron]);
+        raster.set_order(rows);
+    }
+    legend
+}
+
+/// Sorts the raster by rate again if it's due, returning the time of the latest step and the
+/// window of simulated ms the spike charts cover
+fn frame_window(
+    raster: &Mutex<Raster>,
+    order: RowOrder,
+    time_buffer_size: usize,
+    next_rate_sort: &mut u64,
+) -> (u64, Range<i64>) {
+    let mut raster = raster.lock().unwrap();
+    if order == RowOrder::Rate && raster.time() >= *next_rate_sort {
+        let counts = raster.spike_counts();
+        let mut rows: Vec<usize> = (0..counts.len()).collect();
+        rows.sort_by_key(|neuron| counts[*neuron]);
+        raster.set_order(rows);
+        *next_rate_sort = raster.time() + RATE_SORT_INTERVAL;
+    }
+    // the raster fills from the left until the buffer's full then scrolls
+    let start = (raster.time() + 1).saturating_sub(raster.steps() as u64) as i64;
+    (raster.time(), start..start + time_buffer_size as i64)
+}
+
+/// Draws every panel of the dashboard, returning the pixels and coordinates of each spike chart.
+/// The spikes are drawn with plotters when `draw_spikes` is set, otherwise they're left for the
+/// raster to be copied in.
+fn draw_panels<DB: DrawingBackend>(
+    area: &DrawingArea<DB, Shift>,
+    dashboard: &Dashboard,
+    frame: &Frame,
+    draw_spikes: bool,
+) -> Vec<SpikeChart> {
+    let Buffers {
+        traces,
+        lfp,
+        raster,
+        weights,
+    } = frame.buffers;
+    let window_ms = &frame.window_ms;
+    let spikes = if draw_spikes { Some(&**raster) } else { None };
+
+    let mut spike_charts = Vec::new();
+    for (panel, area) in dashboard.areas(area) {
+        match panel {
+            Panel::Raster => spike_charts.push(draw_spike_chart(
+                &area,
+                window_ms.clone(),
+                frame.neuron_count,
+                spikes,
+            )),
+            Panel::Voltages => draw_voltages(&area, window_ms.clone(), frame.time, traces),
+            Panel::Rate => draw_rate(&area, window_ms.clone(), frame.neuron_count, raster),
+            Panel::PhasePlane => draw_phase_plane(&area, traces),
+            Panel::Spectrum => draw_spectrum(&area, lfp),
+            Panel::Weights => draw_weights(&area, weights),
+            Panel::Heatmap => draw_heatmap(&area, weights),
+        }
+    }
+    spike_charts
+}
+
+/// Draws the legend over every raster of the dashboard, once the spikes are in
+fn draw_legends<DB: DrawingBackend>(
+    area: &DrawingArea<DB, Shift>,
+    dashboard: &Dashboard,
+    legend: &[(String, RGBColor)],
+) {
+    for (_, area) in dashboard
+        .areas(area)
+        .iter()
+        .filter(|(panel, _)| *panel == Panel::Raster)
+    {
+        draw_legend(area, legend);
+    }
+}
+
+/// Writes a frame of BGRX pixels to a PNG
+fn save_screenshot(path: &Path, pixels: &[u32], size: (u32, u32)) -> Result<(), Box<dyn Error>> {
+    let rgb: Vec<u8> = pixels
+        .iter()
+        .flat_map(|pixel| [(pixel >> 16) as u8, (pixel >> 8) as u8, *pixel as u8])
+        .collect();
+    let mut backend = BitMapBackend::new(path, size);
+    backend.blit_bitmap((0, 0), size, &rgb)?;
+    backend.present()?;
+    Ok(())
+}
+
+/// Legend group of each neuron and the legend, by firing class when the neurons have been
+/// classified and by population otherwise
+fn neuron_groups(
+    excitatory: usize,
+    neuron_count: usize,
+    classes: Option<&[FiringClass]>,
+) -> (Vec<usize>, Vec<(String, RGBColor)>) {
+    match classes {
+        Some(classes) => {
+            let present: Vec<FiringClass> = FiringClass::ALL
+                .iter()
+                .copied()
+                .filter(|class| classes.contains(class))
+                .collect();
+            let groups = classes
+                .iter()
+                .map(|class| present.iter().position(|c| c == class).unwrap())
+                .collect();
+            let legend = present
+                .iter()
+                .map(|class| (class.to_string(), class_color(*class)))
+                .collect();
+            (groups, legend)
+        }
+        None => {
+            let groups = (0..neuron_count)
+                .map(|neuron| if neuron < excitatory { 0 } else { 1 })
+                .collect();
+            let legend = vec![
+                ("E".to_string(), EXCITATORY_COLOR),
+                ("I".to_string(), INHIBITORY_COLOR),
+            ];
+            (groups, legend)
+        }
+    }
+}
+
+/// Draws the mesh of a spike chart over a window of simulated time, returning the pixels its
+/// plotting area covers and its coordinates. The spikes are drawn too if a raster is given,
+/// otherwise the raster is copied over afterwards.
+fn draw_spike_chart<DB: DrawingBackend>(
+    area: &DrawingArea<DB, Shift>,
+    window_ms: Range<i64>,
+    neuron_count: usize,
+    spikes: Option<&Mutex<Raster>>,
+) -> SpikeChart {
+    let mut spike_chart = ChartBuilder::on(area)
+        .caption("Spikes", ("sans-serif", 10))
+        .x_label_area_size(35)
+        .build_cartesian_2d(window_ms, 0f32..neuron_count as f32)
+        .expect("error building chart");
+    spike_chart
+        .configure_mesh()
+        .x_desc("ms")
+        .draw()
+        .expect("error drawing spike chart mesh");
+    if let Some(raster) = spikes {
+        let raster = raster.lock().unwrap();
+        // drawn in pixels so each spike covers at least one pixel row and column like the
+        // window's raster, rather than rounding away to nothing with more neurons than rows
+        let coords = spike_chart.as_coord_spec();
+        let plotting_area = spike_chart.plotting_area();
+        let (x, y) = plotting_area.get_pixel_range();
+        let pixels = plotting_area.strip_coord_spec();
+        for (time, row, color) in raster.spikes() {
+            let (time, row) = (time as i64, row as f32);
+            let (left, top) = coords.translate(&(time, row + 1.0));
+            let (right, bottom) = coords.translate(&(time + 1, row));
+            pixels
+                .draw(&Rectangle::new(
+                    [
+                        (left - x.start, top - y.start),
+                        (right.max(left + 1) - x.start, bottom.max(top + 1) - y.start),
+                    ],
+                    pixel_color(color).filled(),
+                ))
+                .expect("error drawing spikes");
+        }
+    }
+    (
+        spike_chart.plotting_area().get_pixel_range(),
+        spike_chart.as_coord_spec().clone(),
+    )
+}
+
+fn class_color(class: FiringClass) -> RGBColor {
+    match class {
+        FiringClass::RegularSpiking => RED,
+        FiringClass::IntrinsicallyBursting => RGBColor(255, 140, 0),
+        FiringClass::Chattering => MAGENTA,
+        FiringClass::FastSpiking => BLUE,
+        FiringClass::LowThresholdSpiking => CYAN,
+        FiringClass::PhasicSpiking | FiringClass::PhasicBursting => GREEN,
+        FiringClass::Silent => BLACK,
+    }
+}
+
+/// A colour as a BGRX pixel value for writing straight into the buffer
+fn pixel(color: RGBColor) -> u32 {
+    ((color.0 as u32) << 16) | ((color.1 as u32) << 8) | color.2 as u32
+}
+
+/// The colour of a BGRX pixel value
+fn pixel_color(pixel: u32) -> RGBColor {
+    RGBColor((pixel >> 16) as u8, (pixel >> 8) as u8, pixel as u8)
+}
+
+/// v of each probed neuron over a window of simulated time ending at `time`, with a legend once
+/// there's more than one
+fn draw_voltages<DB: DrawingBackend>(
+    area: &DrawingArea<DB, Shift>,
+    window_ms: Range<i64>,
+    time: u64,
+    traces: &Mutex<Traces>,
+) {
+    let traces = traces.lock().unwrap();
+    let caption = match traces.neurons() {
+        [neuron] => format!("Neuron {} voltage", neuron),
+        _ => "Probed voltages".to_string(),
+    };
+
+    let mut neuron_chart = ChartBuilder::on(area)
+        .caption(caption, ("sans-serif", 10))
+        .build_cartesian_2d(window_ms, -100f32..30f32)
+        .expect("error building chart");
+    neuron_chart
+        .configure_mesh()
+        .draw()
+        .expect("error drawing voltage chart mesh");
+
+    let mut legend = Vec::new();
+    for (i, neuron) in traces.neurons().iter().enumerate() {
+        let color = TRACE_COLORS[i % TRACE_COLORS.len()];
+        let samples = traces.samples(i);
+        let start = time as i64 + 1 - samples.len() as i64;
+        neuron_chart
+            .draw_series(LineSeries::new(
+                samples
+                    .iter()
+                    .enumerate()
+                    .map(|(t, (v, _))| (start + t as i64, *v)),
+                &color,
+            ))
+            .expect("error drawing voltage");
+        legend.push((neuron.to_string(), color));
+    }
+    if legend.len() > 1 {
+        draw_legend(area, &legend);
+    }
+}
+
+/// Mean firing rate of every neuron in Hz over the buffered steps, which start a window of
+/// simulated time, smoothed over a few steps
+fn draw_rate<DB: DrawingBackend>(
+    area: &DrawingArea<DB, Shift>,
+    window_ms: Range<i64>,
+    neuron_count: usize,
+    raster: &Mutex<Raster>,
+) {
+    let counts: Vec<usize> = raster.lock().unwrap().counts().collect();
+    // each step is 1ms
+    let rates: Vec<f32> = (0..counts.len())
+        .map(|t| {
+            let window = &counts[t.saturating_sub(RATE_WINDOW - 1)..=t];
+            window.iter().sum::<usize>() as f32 * 1000.0
+                / (window.len() * neuron_count.max(1)) as f32
+        })
+        .collect();
+    let mean = counts.iter().sum::<usize>() as f32 * 1000.0
+        / (counts.len().max(1) * neuron_count.max(1)) as f32;
+    let max_rate = rates.iter().copied().fold(10.0, f32::max);
+
+    let mut rate_chart = ChartBuilder::on(area)
+        .caption(
+            format!("Population rate, mean {:.1}Hz", mean),
+            ("sans-serif", 10),
+        )
+        .y_label_area_size(30)
+        .build_cartesian_2d(window_ms.clone(), 0f32..max_rate * 1.1)
+        .expect("error building chart");
+    rate_chart
+        .configure_mesh()
+        .y_desc("Hz")
+        .draw()
+        .expect("error drawing rate chart mesh");
+    rate_chart
+        .draw_series(LineSeries::new(
+            rates
+                .iter()
+                .enumerate()
+                .map(|(t, rate)| (window_ms.start + t as i64, *rate)),
+            &BLACK,
+        ))
+        .expect("error drawing rate");
+}
+
+/// Trajectory of each probed neuron in the (v, u) plane over the buffered steps, its latest
+/// state marked with a dot, over its nullclines at its current input and with a cross where it
+/// would reset to if it spiked
+fn draw_phase_plane<DB: DrawingBackend>(area: &DrawingArea<DB, Shift>, traces: &Mutex<Traces>) {
+    const V_RANGE: std::ops::Range<f32> = -100.0..40.0;
+    let traces = traces.lock().unwrap();
+    let probed = 0..traces.neurons().len();
+
+    // fit the trajectories, the reset points and the bottom of the v-nullcline's parabola, which
+    // is at v = -62.5
+    let (u_min, u_max) = probed
+        .clone()
+        .flat_map(|i| {
+            let latest = traces.latest(i);
+            let reset = latest.u + latest.u_reset;
+            let vertex = v_nullcline(-62.5, traces.input(i));
+            traces
+                .samples(i)
+                .iter()
+                .map(|(_, u)| *u)
+                .chain([reset, vertex])
+        })
+        .fold((f32::MAX, f32::MIN), |(min, max), u| {
+            (min.min(u), max.max(u))
+        });
+    let (u_min, u_max) = if u_min < u_max {
+        let margin = (u_max - u_min) * 0.1;
+        (u_min - margin, u_max + margin)
+    } else {
+        (-20.0, 0.0)
+    };
+
+    let caption = match traces.neurons() {
+        [neuron] => format!("Phase plane, neuron {}, I = {:.1}", neuron, traces.input(0)),
+        _ => "Phase plane".to_string(),
+    };
+    let mut phase_chart = ChartBuilder::on(area)
+        .caption(caption, ("sans-serif", 10))
+        .x_label_area_size(20)
+        .y_label_area_size(30)
+        .build_cartesian_2d(V_RANGE, u_min..u_max)
+        .expect("error building chart");
+    phase_chart
+        .configure_mesh()
+        .x_desc("v")
+        .y_desc("u")
+        .draw()
+        .expect("error drawing phase plane mesh");
+
+    // the nullclines go underneath every trajectory, cut off where they leave the chart since
+    // plotters would flatten them along its edges
+    for i in probed.clone() {
+        let color = TRACE_COLORS[i % TRACE_COLORS.len()].mix(0.5);
+        let input = traces.input(i);
+        // the parabola is only below u_max between the roots of 0.04v² + 5v + 140 + I - u_max
+        let discriminant = 25.0 - 0.16 * (140.0 + input - u_max);
+        if discriminant > 0.0 {
+            let (left, right) = (
+                (-5.0 - discriminant.sqrt()) / 0.08,
+                (-5.0 + discriminant.sqrt()) / 0.08,
+            );
+            let v_nullcline_points =
+                sample_between(left.max(V_RANGE.start), right.min(V_RANGE.end))
+                    .map(|v| (v, v_nullcline(v, input)));
+            phase_chart
+                .draw_series(LineSeries::new(v_nullcline_points, color.stroke_width(1)))
+                .expect("error drawing v-nullcline");
+        }
+
+        let b = traces.latest(i).sensitivity;
+        let (left, right) = (u_min / b, u_max / b);
+        let (left, right) = (left.min(right), left.max(right));
+        let (left, right) = (left.max(V_RANGE.start), right.min(V_RANGE.end));
+        if left < right {
+            // dashed to tell it apart from the v-nullcline, drawn a dash at a time since this
+            // version of plotters has no dashed series
+            let dash = (right - left) / (2 * U_NULLCLINE_DASHES - 1) as f32;
+            phase_chart
+                .draw_series((0..U_NULLCLINE_DASHES).map(|i| {
+                    let start = left + 2.0 * dash * i as f32;
+                    let end = start + dash;
+                    PathElement::new(
+                        vec![(start, b * start), (end, b * end)],
+                        color.stroke_width(1),
+                    )
+                }))
+                .expect("error drawing u-nullcline");
+        }
+    }
+
+    for i in probed {
+        let color = TRACE_COLORS[i % TRACE_COLORS.len()];
+        let samples = traces.samples(i);
+        phase_chart
+            .draw_series(LineSeries::new(samples.iter().copied(), &color))
+            .expect("error drawing trajectory");
+        if let Some(latest) = samples.back() {
+            phase_chart
+                .draw_series(std::iter::once(Circle::new(*latest, 3, color.filled())))
+                .expect("error drawing trajectory");
+        }
+        // where the neuron would land if it spiked now
+        let latest = traces.latest(i);
+        phase_chart
+            .draw_series(std::iter::once(Cross::new(
+                (latest.v_reset, latest.u + latest.u_reset),
+                4,
+                color.stroke_width(2),
+            )))
+            .expect("error drawing reset point");
+    }
+}
+
+/// 100 evenly spaced values from `start` to `end`, or none if the range is empty
+fn sample_between(start: f32, end: f32) -> impl Iterator<Item = f32> {
+    let steps = if start < end { 100 } else { 0 };
+    (0..steps).map(move |step| start + (end - start) * step as f32 / (steps - 1) as f32)
+}
+
+/// u on the v-nullcline, where the membrane potential holds steady with input current `input`
+fn v_nullcline(v: f32, input: f32) -> f32 {
+    0.04 * v.powi(2) + 5.0 * v + 140.0 + input
+}
+
+/// Draws a box of coloured dots and labels in the top right corner of the area
+fn draw_legend<DB: DrawingBackend>(area: &DrawingArea<DB, Shift>, entries: &[(String, RGBColor)]) {
+    const LINE: i32 = 14;
+    let (width, _) = area.dim_in_pixel();
+    let longest = entries
+        .iter()
+        .map(|(label, _)| label.len())
+        .max()
+        .unwrap_or(0);
+    let box_width = 30 + 6 * longest as i32;
+    let (left, top) = (width as i32 - box_width - 10, 20);
+    let bottom = top + LINE * entries.len() as i32 + 6;
+
+    area.draw(&Rectangle::new(
+        [(left, top), (left + box_width, bottom)],
+        WHITE.mix(0.8).filled(),
+    ))
+    .expect("error drawing legend");
+    area.draw(&Rectangle::new(
+        [(left, top), (left + box_width, bottom)],
+        BLACK,
+    ))
+    .expect("error drawing legend");
+    for (i, (label, color)) in entries.iter().enumerate() {
+        let y = top + 10 + LINE * i as i32;
+        area.draw(&Circle::new((left + 10, y), 3, color.filled()))
+            .expect("error drawing legend");
+        area.draw(&Text::new(
+            label.as_str(),
+            (left + 20, y - 5),
+            ("sans-serif", 10),
+        ))
+        .expect("error drawing legend");
+    }
+}
+
+/// Welch spectrum of the LFP proxy over the buffered steps
+fn draw_spectrum<DB: DrawingBackend>(area: &DrawingArea<DB, Shift>, lfp: &Mutex<VecDeque<f32>>) {
+    let signal: Vec<f64> = lfp.lock().unwrap().iter().map(|v| *v as f64).collect();
+    let spectrum = rhythm::welch(&signal, SPECTRUM_SEGMENT);
+
+    let caption = match spectrum.as_ref().and_then(|spectrum| spectrum.peak(1.0)) {
+        Some((frequency, _)) => format!("LFP spectrum, peak {:.0}Hz", frequency),
+        None => "LFP spectrum".to_string(),
+    };
+    let points: Vec<(f64, f64)> = match &spectrum {
+        Some(spectrum) => spectrum
+            .frequencies
+            .iter()
+            .copied()
+            .zip(spectrum.power.iter().copied())
+            .filter(|(frequency, _)| *frequency <= SPECTRUM_MAX_FREQUENCY)
+            .collect(),
+        None => Vec::new(),
+    };
+    let max_power = points
+        .iter()
+        .map(|(_, power)| *power)
+        .fold(0.0, f64::max)
+        .max(f64::EPSILON);
+
+    let mut spectrum_chart = ChartBuilder::on(area)
+        .caption(caption, ("sans-serif", 10))
+        .x_label_area_size(20)
+        .build_cartesian_2d(0f64..SPECTRUM_MAX_FREQUENCY, 0f64..max_power)
+        .expect("error building chart");
+
+    spectrum_chart
+        .configure_mesh()
+        .y_labels(0)
+        .x_desc("Hz")
+        .draw()
+        .expect("error drawing spectrum chart mesh");
+    spectrum_chart
+        .draw_series(LineSeries::new(points, &BLUE))
+        .expect("error drawing spectrum");
+}
+
+/// Histograms of the weights between each pair of populations in a 2×2 grid, coloured by the
+/// presynaptic population
+fn draw_weights<DB: DrawingBackend>(
+    area: &DrawingArea<DB, Shift>,
+    weights: &Mutex<Option<Weights>>,
+) {
+    let weights = weights.lock().unwrap();
+    let weights = match weights.as_ref() {
+        Some(weights) => weights,
+        None => return,
+    };
+
+    for (histogram, area) in weights.histograms.iter().zip(area.split_evenly((2, 2))) {
+        let color = match histogram.pre {
+            Population::Excitatory => EXCITATORY_COLOR,
+            Population::Inhibitory => INHIBITORY_COLOR,
+        };
+        let range = histogram.range.clone();
+        let bin_width = (range.end - range.start) / histogram.counts.len() as f32;
+        // leave some room over the tallest bar
+        let max_count = histogram.counts.iter().copied().max().unwrap_or(0).max(1) * 11 / 10 + 1;
+
+        let mut histogram_chart = ChartBuilder::on(&area)
+            .caption(
+                format!(
+                    "{}→{} weights, mean {:.2}",
+                    histogram.pre, histogram.post, histogram.mean
+                ),
+                ("sans-serif", 10),
+            )
+            .x_label_area_size(20)
+            .y_label_area_size(40)
+            .build_cartesian_2d(range.clone(), 0..max_count)
+            .expect("error building chart");
+        histogram_chart
+            .configure_mesh()
+            .disable_mesh()
+            .x_labels(5)
+            .y_labels(4)
+            .draw()
+            .expect("error drawing weight histogram mesh");
+        histogram_chart
+            .draw_series(histogram.counts.iter().enumerate().map(|(bin, count)| {
+                let left = range.start + bin_width * bin as f32;
+                Rectangle::new(
+                    [(left, 0), (left + bin_width, *count)],
+                    color.mix(0.7).filled(),
+                )
+            }))
+            .expect("error drawing weight histogram");
+    }
+}
+
+/// The connection matrix averaged over blocks of neurons, excitatory weights in red and
+/// inhibitory ones in blue, with presynaptic neurons across and postsynaptic neurons up
+fn draw_heatmap<DB: DrawingBackend>(
+    area: &DrawingArea<DB, Shift>,
+    weights: &Mutex<Option<Weights>>,
+) {
+    let weights = weights.lock().unwrap();
+    let weights = match weights.as_ref() {
+        Some(weights) => weights,
+        None => return,
+    };
+    let scale = weights
+        .heatmap
+        .iter()
+        .fold(0f32, |max, weight| max.max(weight.abs()))
+        .max(f32::EPSILON);
+    let total = weights.total as u32;
+    let block = weights.block as u32;
+
+    let mut heatmap_chart = ChartBuilder::on(area)
+        .caption(
+            format!(
+                "Connections at {}ms, {}×{} neurons per cell",
+                weights.time, block, block
+            ),
+            ("sans-serif", 10),
+        )
+        .x_label_area_size(35)
+        .y_label_area_size(45)
+        .build_cartesian_2d(0..total, 0..total)
+        .expect("error building chart");
+    heatmap_chart
+        .configure_mesh()
+        .disable_mesh()
+        .x_desc("pre")
+        .y_desc("post")
+        .draw()
+        .expect("error drawing heatmap mesh");
+    heatmap_chart
+        .draw_series(
+            weights
+                .heatmap
+                .indexed_iter()
+                .map(|((row, column), weight)| {
+                    let (x, y) = (column as u32 * block, row as u32 * block);
+                    Rectangle::new(
+                        [(x, y), ((x + block).min(total), (y + block).min(total))],
+                        weight_color(*weight / scale).filled(),
+                    )
+                }),
+        )
+        .expect("error drawing heatmap");
+}
+
+/// White for no weight through to the excitatory colour for positive weights and the
+/// inhibitory colour for negative ones, with `weight` scaled to -1..1
+fn weight_color(weight: f32) -> RGBColor {
+    let (color, strength) = if weight >= 0.0 {
+        (EXCITATORY_COLOR, weight.min(1.0))
+    } else {
+        (INHIBITORY_COLOR, (-weight).min(1.0))
+    };
+    let fade = |channel: u8| (255.0 - (255.0 - channel as f32) * strength) as u8;
+    RGBColor(fade(color.0), fade(color.1), fade(color.2))
+}
+
+struct BufferWrapper(Vec<u32>);
+impl Borrow<[u8]> for BufferWrapper {
+    fn borrow(&self) -> &[u8] {
+        // Safe for alignment: align_of(u8) <= align_of(u32)
+        // Safe for cast: u32 can be thought of as being transparent over [u8; 4]
+        unsafe { std::slice::from_raw_parts(self.0.as_ptr() as *const u8, self.0.len() * 4) }
+    }
+}
+impl BorrowMut<[u8]> for BufferWrapper {
+    fn borrow_mut(&mut self) -> &mut [u8] {
+        // Safe for alignment: align_of(u8) <= align_of(u32)
+        // Safe for cast: u32 can be thought of as being transparent over [u8; 4]
+        unsafe { std::slice::from_raw_parts_mut(self.0.as_mut_ptr() as *mut u8, self.0.len() * 4) }
+    }
+}
+impl Borrow<[u32]> for BufferWrapper {
+    fn borrow(&self) -> &[u32] {
+        self.0.as_slice()
+    }
+}
+impl BorrowMut<[u32]> for BufferWrapper {
+    fn borrow_mut(&mut self) -> &mut [u32] {
+        self.0.as_mut_slice()
+    }
+}
@@ -0,0 +1,175 @@
+use std::collections::VecDeque;
+use std::ops::Range;
+
+/// pixel value of a row with no spikes, the chart background
+const EMPTY: u32 = 0x00ff_ffff;
+/// BGRX pixel value of the default spike colour
+const RED: u32 = 0x00ff_0000;
+
+/// Scrolling spike raster of the last `capacity` steps. Each step is rasterized once into a
+/// column of pixel rows when it arrives, so drawing a frame only copies pixels however many
+/// neurons and spikes there are.
+pub struct Raster {
+    neurons: usize,
+    capacity: usize,
+    /// indices of the neurons that spiked at each step, kept to rasterize again when the colours
+    /// or the number of rows change
+    spikes: VecDeque<Vec<u32>>,
+    /// BGRX pixel value for each neuron
+    colors: Vec<u32>,
+    /// neuron in each row of neurons from the bottom
+    order: Vec<usize>,
+    /// row of neurons from the bottom of each neuron
+    positions: Vec<usize>,
+    rows: usize,
+    columns: VecDeque<Vec<u32>>,
+    /// simulated time in ms of the latest step
+    time: u64,
+}
+
+impl Raster {
+    pub fn new(neurons: usize, capacity: usize) -> Self {
+        Raster {
+            neurons,
+            capacity: capacity.max(1),
+            spikes: VecDeque::with_capacity(capacity),
+            colors: vec![RED; neurons],
+            order: (0..neurons).collect(),
+            positions: (0..neurons).collect(),
+            rows: 0,
+            columns: VecDeque::with_capacity(capacity),
+            time: 0,
+        }
+    }
+
+    /// Adds the neurons that spiked in the step at `time`, dropping the oldest step when full
+    pub fn push(&mut self, time: u64, spikes: Vec<u32>) {
+        self.time = time;
+        if self.spikes.len() == self.capacity {
+            self.spikes.pop_front();
+            self.columns.pop_front();
+        }
+        self.columns.push_back(self.rasterize(&spikes));
+        self.spikes.push_back(spikes);
+    }
+
+    /// Simulated time in ms of the latest step
+    pub fn time(&self) -> u64 {
+        self.time
+    }
+
+    /// Number of steps buffered, up to the capacity
+    pub fn steps(&self) -> usize {
+        self.spikes.len()
+    }
+
+    /// Number of neurons that spiked at each buffered step, oldest first
+    pub fn counts(&self) -> impl Iterator<Item = usize> + '_ {
+        self.spikes.iter().map(|spikes| spikes.len())
+    }
+
+    /// Number of spikes of each neuron over the buffered steps
+    pub fn spike_counts(&self) -> Vec<usize> {
+        let mut counts = vec![0; self.neurons];
+        for &neuron in self.spikes.iter().flatten() {
+            counts[neuron as usize] += 1;
+        }
+        counts
+    }
+
+    /// Time in ms, row of neurons from the bottom and BGRX colour of every buffered spike, for
+    /// drawing the raster on backends it can't be copied into
+    pub fn spikes(&self) -> impl Iterator<Item = (u64, usize, u32)> + '_ {
+        let start = (self.time + 1).saturating_sub(self.spikes.len() as u64);
+        self.spikes
+            .iter()
+            .zip(start..)
+            .flat_map(move |(spikes, time)| {
+                spikes.iter().map(move |&neuron| {
+                    let neuron = neuron as usize;
+                    (time, self.positions[neuron], self.colors[neuron])
+                })
+            })
+    }
+
+    /// Sets the BGRX colour of each neuron's spikes
+    pub fn set_colors(&mut self, colors: Vec<u32>) {
+        self.colors = colors;
+        self.rasterize_all();
+    }
+
+    /// Puts the neurons in rows from the bottom in this order instead of by index
+    pub fn set_order(&mut self, order: Vec<usize>) {
+        for (position, &neuron) in order.iter().enumerate() {
+            self.positions[neuron] = position;
+        }
+        self.order = order;
+        self.rasterize_all();
+    }
+
+    /// The neuron `position` rows of neurons up from the bottom
+    pub fn neuron_at(&self, position: usize) -> usize {
+        self.order[position.min(self.neurons.saturating_sub(1))]
+    }
+
+    fn rasterize(&self, spikes: &[u32]) -> Vec<u32> {
+        let mut column = vec![EMPTY; self.rows];
+        if self.rows == 0 || self.neurons == 0 {
+            return column;
+        }
+        for &neuron in spikes {
+            let neuron = neuron as usize;
+            // the first row is at the bottom like the chart's y axis, each neuron gets at least a
+            // pixel row
+            let position = self.positions[neuron];
+            let top = self.rows - (position + 1) * self.rows / self.neurons;
+            let bottom = (self.rows - position * self.rows / self.neurons).max(top + 1);
+            for pixel in &mut column[top..bottom.min(self.rows)] {
+                *pixel = self.colors[neuron];
+            }
+        }
+        column
+    }
+
+    fn rasterize_all(&mut self) {
+        self.columns = self.spikes.iter().map(|s| self.rasterize(s)).collect();
+    }
+
+    /// Copies the raster into the `x` by `y` pixel range of a `width` pixels wide buffer, oldest
+    /// step on the left, leaving the pixels without spikes as they are so the mesh shows through
+    pub fn blit(&mut self, buffer: &mut [u32], width: usize, x: Range<i32>, y: Range<i32>) {
+        let columns = (x.end - x.start).max(0) as usize;
+        let rows = (y.end - y.start).max(0) as usize;
+        if rows != self.rows {
+            self.rows = rows;
+            self.rasterize_all();
+        }
+
+        for column in 0..columns {
+            // every step that falls in this pixel column, at least one when they're wider
+            let first = column * self.capacity / columns;
+            let last = ((column + 1) * self.capacity / columns).max(first + 1);
+            let steps = first.min(self.columns.len())..last.min(self.columns.len());
+            let active: Vec<&Vec<u32>> = self
+                .columns
+                .range(steps.clone())
+                .zip(self.spikes.range(steps))
+                .filter(|(_, spikes)| !spikes.is_empty())
+                .map(|(column, _)| column)
+                .collect();
+            if active.is_empty() {
+                continue;
+            }
+            for row in 0..rows {
+                let pixel = active
+                    .iter()
+                    .map(|step| step[row])
+                    .find(|pixel| *pixel != EMPTY);
+                if let Some(pixel) = pixel {
+                    let offset = (y.start as usize + row) * width + x.start as usize + column;
+                    buffer[offset] = pixel;
+                }
+            }
+        }
+    }
+}
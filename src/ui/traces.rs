@@ -0,0 +1,83 @@
+use std::collections::VecDeque;
+
+use crate::izhikevich::Izhikevich;
+use crate::recording::Probe;
+
+/// steps the input current is averaged over, so the nullclines drawn from it don't jitter with
+/// the thalamic noise
+const INPUT_WINDOW: usize = 10;
+
+/// The v and u of each probed neuron over the last `capacity` steps, started afresh whenever a
+/// different set of neurons is probed
+pub struct Traces {
+    capacity: usize,
+    neurons: Vec<usize>,
+    /// (v, u) of each probed neuron at each step, oldest first
+    samples: Vec<VecDeque<(f32, f32)>>,
+    /// input current of each probed neuron over the last `INPUT_WINDOW` steps
+    inputs: Vec<VecDeque<f32>>,
+    /// latest state of each probed neuron
+    latest: Vec<Izhikevich>,
+}
+
+impl Traces {
+    pub fn new(capacity: usize) -> Self {
+        Traces {
+            capacity: capacity.max(1),
+            neurons: Vec::new(),
+            samples: Vec::new(),
+            inputs: Vec::new(),
+            latest: Vec::new(),
+        }
+    }
+
+    /// Adds the probes from the latest step
+    pub fn push(&mut self, probes: &[Probe]) {
+        if !self
+            .neurons
+            .iter()
+            .copied()
+            .eq(probes.iter().map(|probe| probe.neuron))
+        {
+            self.neurons = probes.iter().map(|probe| probe.neuron).collect();
+            self.samples = vec![VecDeque::with_capacity(self.capacity); probes.len()];
+            self.inputs = vec![VecDeque::with_capacity(INPUT_WINDOW); probes.len()];
+        }
+        for ((samples, inputs), probe) in self
+            .samples
+            .iter_mut()
+            .zip(self.inputs.iter_mut())
+            .zip(probes)
+        {
+            if samples.len() == self.capacity {
+                samples.pop_front();
+            }
+            samples.push_back((probe.state.v, probe.state.u));
+            if inputs.len() == INPUT_WINDOW {
+                inputs.pop_front();
+            }
+            inputs.push_back(probe.input);
+        }
+        self.latest = probes.iter().map(|probe| probe.state).collect();
+    }
+
+    pub fn neurons(&self) -> &[usize] {
+        &self.neurons
+    }
+
+    /// (v, u) of the `i`th probed neuron at each step, oldest first
+    pub fn samples(&self, i: usize) -> &VecDeque<(f32, f32)> {
+        &self.samples[i]
+    }
+
+    /// Input current of the `i`th probed neuron averaged over the last few steps
+    pub fn input(&self, i: usize) -> f32 {
+        let inputs = &self.inputs[i];
+        inputs.iter().sum::<f32>() / inputs.len().max(1) as f32
+    }
+
+    /// Latest state of the `i`th probed neuron, including its parameters
+    pub fn latest(&self, i: usize) -> &Izhikevich {
+        &self.latest[i]
+    }
+}
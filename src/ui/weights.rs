@@ -0,0 +1,104 @@
+use std::ops::Range;
+
+use ndarray::prelude::*;
+
+use crate::network::Population;
+
+/// bins in each pathway's histogram
+const BINS: usize = 40;
+/// most cells along each side of the heatmap
+const HEATMAP_CELLS: usize = 100;
+
+/// Distribution of the weights from one population to another, leaving out absent connections
+pub struct Histogram {
+    pub pre: Population,
+    pub post: Population,
+    /// weights covered by the bins
+    pub range: Range<f32>,
+    /// connections in each of `BINS` equal bins across `range`
+    pub counts: Vec<u32>,
+    pub mean: f32,
+}
+
+/// A snapshot of the connection weights boiled down to what the UI draws, worked out as it
+/// arrives rather than every frame
+pub struct Weights {
+    /// time of the step the snapshot was taken at in ms
+    pub time: u64,
+    pub total: usize,
+    /// E→E, E→I, I→E and I→I
+    pub histograms: Vec<Histogram>,
+    /// mean weight of each block of connections with postsynaptic rows and presynaptic columns
+    pub heatmap: Array2<f32>,
+    /// neurons along each side of a heatmap block
+    pub block: usize,
+}
+
+impl Weights {
+    pub fn new(time: u64, connections: ArrayView2<f32>, excitatory: usize) -> Self {
+        let total = connections.nrows();
+        let populations = [Population::Excitatory, Population::Inhibitory];
+        let histograms = populations
+            .iter()
+            .flat_map(|&pre| populations.iter().map(move |&post| (pre, post)))
+            .map(|(pre, post)| {
+                let pathway = connections.slice(s![
+                    post.range(excitatory, total),
+                    pre.range(excitatory, total)
+                ]);
+                histogram(pre, post, pathway)
+            })
+            .collect();
+
+        let block = total.div_ceil(HEATMAP_CELLS).max(1);
+        let cells = total.div_ceil(block);
+        let heatmap = Array2::from_shape_fn((cells, cells), |(row, column)| {
+            connections
+                .slice(s![
+                    row * block..((row + 1) * block).min(total),
+                    column * block..((column + 1) * block).min(total)
+                ])
+                .mean()
+                .unwrap_or(0.0)
+        });
+
+        Weights {
+            time,
+            total,
+            histograms,
+            heatmap,
+            block,
+        }
+    }
+}
+
+fn histogram(pre: Population, post: Population, pathway: ArrayView2<f32>) -> Histogram {
+    let present: Vec<f32> = pathway.iter().copied().filter(|w| *w != 0.0).collect();
+    let (min, max) = present.iter().fold((f32::MAX, f32::MIN), |(min, max), w| {
+        (min.min(*w), max.max(*w))
+    });
+    let range = if present.is_empty() {
+        0.0..1.0
+    } else if min < max {
+        min..max
+    } else {
+        // every weight is the same so give the single bar some width
+        min - 0.5..max + 0.5
+    };
+
+    let mut counts = vec![0; BINS];
+    let width = (range.end - range.start) / BINS as f32;
+    for w in &present {
+        let bin = ((w - range.start) / width) as usize;
+        counts[bin.min(BINS - 1)] += 1;
+    }
+    let mean = present.iter().sum::<f32>() / present.len().max(1) as f32;
+
+    Histogram {
+        pre,
+        post,
+        range,
+        counts,
+        mean,
+    }
+}